@@ -50,6 +50,49 @@ let set_abs_input_name sourcefile =
   sourcefile
 let setup_outcome_printer () = Lazy.force Res_outcome_printer.setup
 
+(* Runs the [-pp] preprocessor with the source file as its argument and returns
+   its output. Locations keep pointing to the source file. *)
+let preprocess pp sourcefile =
+  let tmpfile = Filename.temp_file "pp" (Filename.basename sourcefile) in
+  let comm =
+    Printf.sprintf "%s %s > %s" pp (Filename.quote sourcefile)
+      (Filename.quote tmpfile)
+  in
+  if Ccomp.command comm <> 0 then (
+    (try Sys.remove tmpfile with _ -> ());
+    Cmd_ast_exception.cannot_run comm);
+  let source = Ext_io.load_file tmpfile in
+  (try Sys.remove tmpfile with _ -> ());
+  source
+
+let parse_preprocessed parse pp sourcefile =
+  Location.input_name := sourcefile;
+  let (parse_result : (_, Res_diagnostics.t list) Res_driver.parse_result) =
+    parse ~for_printer:false ~display_filename:sourcefile
+      ~source:(preprocess pp sourcefile)
+  in
+  if parse_result.invalid then (
+    Res_diagnostics.print_report parse_result.diagnostics parse_result.source;
+    if not !Clflags.ignore_parse_errors then exit 1);
+  parse_result.parsetree
+
+let parse_implementation sourcefile =
+  match !Clflags.preprocessor with
+  | None ->
+    Res_driver.parse_implementation
+      ~ignore_parse_errors:!Clflags.ignore_parse_errors sourcefile
+  | Some pp ->
+    parse_preprocessed Res_driver.parse_implementation_from_source pp
+      sourcefile
+
+let parse_interface sourcefile =
+  match !Clflags.preprocessor with
+  | None ->
+    Res_driver.parse_interface ~ignore_parse_errors:!Clflags.ignore_parse_errors
+      sourcefile
+  | Some pp ->
+    parse_preprocessed Res_driver.parse_interface_from_source pp sourcefile
+
 let setup_runtime_path path = Runtime_package.path := path
 
 let process_file sourcefile ?kind ppf =
@@ -70,18 +113,11 @@ let process_file sourcefile ?kind ppf =
     match kind with
     | Res ->
       let sourcefile = set_abs_input_name sourcefile in
-      Js_implementation.implementation
-        ~parser:
-          (Res_driver.parse_implementation
-             ~ignore_parse_errors:!Clflags.ignore_parse_errors)
-        ppf sourcefile
+      Js_implementation.implementation ~parser:parse_implementation ppf
+        sourcefile
     | Resi ->
       let sourcefile = set_abs_input_name sourcefile in
-      Js_implementation.interface
-        ~parser:
-          (Res_driver.parse_interface
-             ~ignore_parse_errors:!Clflags.ignore_parse_errors)
-        ppf sourcefile
+      Js_implementation.interface ~parser:parse_interface ppf sourcefile
     | Intf_ast -> Js_implementation.interface_mliast ppf sourcefile
     (* The printer setup is done in the runtime depends on
        the content of ast
@@ -377,7 +413,8 @@ let command_line_flags : (string * Bsc_args.spec * string) array =
     ("-version", unit_call print_version_string, "Print version and exit");
     ( "-pp",
       string_optional_set Clflags.preprocessor,
-      "*internal* <command>  Pipe sources through preprocessor <command>" );
+      "*internal* <command>  Pipe sources through preprocessor <command>, which \
+       gets the source file as its argument and prints the preprocessed source" );
     ( "-absname",
       set absname,
       "*internal* Show absolute filenames in error messages" );
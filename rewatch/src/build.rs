@@ -1,13 +1,23 @@
+pub mod artifact_cache;
+pub mod build_graph;
 pub mod build_types;
 pub mod clean;
 pub mod compile;
 pub mod compiler_info;
 pub mod deps;
+pub mod diagnostics;
+pub mod events;
+pub mod generators;
 pub mod logs;
 pub mod namespaces;
 pub mod packages;
 pub mod parse;
 pub mod read_compile_state;
+pub mod remote_cache;
+pub mod report;
+pub mod source_files;
+pub mod source_hashes;
+pub mod timings;
 
 use self::parse::parser_args;
 use crate::build::compile::{mark_modules_with_deleted_deps_dirty, mark_modules_with_expired_deps_dirty};
@@ -16,9 +26,11 @@ use crate::helpers::emojis::*;
 use crate::helpers::{self};
 use crate::project_context::ProjectContext;
 use crate::sourcedirs;
+use crate::trace;
 use anyhow::{Context, Result, anyhow};
 use build_types::*;
 use console::style;
+use events::BuildEvent;
 use indicatif::{ProgressBar, ProgressStyle};
 use log::log_enabled;
 use serde::Serialize;
@@ -56,13 +68,28 @@ pub struct CompilerArgs {
 }
 
 pub fn get_compiler_args(rescript_file_path: &Path) -> Result<String> {
-    let filename = &helpers::get_abs_path(rescript_file_path);
-    let current_package = helpers::get_abs_path(
-        &helpers::get_nearest_config(rescript_file_path).expect("Couldn't find package root"),
-    );
+    let current_package = get_package_of_file(rescript_file_path)?;
     let project_context = ProjectContext::new(&current_package)?;
+    get_compiler_args_in_context(&project_context, &current_package, rescript_file_path)
+}
+
+/// The (absolute) folder of the package a source file belongs to.
+pub fn get_package_of_file(rescript_file_path: &Path) -> Result<PathBuf> {
+    helpers::get_nearest_config(rescript_file_path)
+        .map(|package| helpers::get_abs_path(&package))
+        .ok_or_else(|| anyhow!("Couldn't find package root of {}", rescript_file_path.display()))
+}
+
+/// Like `get_compiler_args`, with the project context of the package the file belongs to, e.g. the
+/// one of a running watcher.
+pub fn get_compiler_args_in_context(
+    project_context: &ProjectContext,
+    current_package: &Path,
+    rescript_file_path: &Path,
+) -> Result<String> {
+    let filename = &helpers::get_abs_path(rescript_file_path);
 
-    let is_type_dev = match filename.strip_prefix(&current_package) {
+    let is_type_dev = match filename.strip_prefix(current_package) {
         Err(_) => false,
         Ok(relative_path) => project_context
             .current_config
@@ -70,13 +97,13 @@ pub fn get_compiler_args(rescript_file_path: &Path) -> Result<String> {
     };
 
     // make PathBuf from package root and get the relative path for filename
-    let relative_filename = filename.strip_prefix(PathBuf::from(&current_package)).unwrap();
+    let relative_filename = filename.strip_prefix(current_package).unwrap();
 
-    let file_path = PathBuf::from(&current_package).join(filename);
+    let file_path = current_package.join(filename);
     let contents = helpers::read_file(&file_path).expect("Error reading file");
 
     let (ast_path, parser_args) = parser_args(
-        &project_context,
+        project_context,
         &project_context.current_config,
         relative_filename,
         &contents,
@@ -97,7 +124,7 @@ pub fn get_compiler_args(rescript_file_path: &Path) -> Result<String> {
         relative_filename,
         is_interface,
         has_interface,
-        &project_context,
+        project_context,
         &None,
         is_type_dev,
         true,
@@ -132,13 +159,22 @@ pub fn initialize_build(
     show_progress: bool,
     path: &Path,
     plain_output: bool,
+    json_output: bool,
     warn_error: Option<String>,
 ) -> Result<BuildCommandState> {
+    let _span = trace::span("initialize_build");
     let project_context = ProjectContext::new(path)?;
     let compiler = get_compiler_info(&project_context)?;
 
     let timing_clean_start = Instant::now();
-    let packages = packages::make(filter, &project_context, show_progress)?;
+    let build_graph = build_graph::load(&project_context, filter);
+    let packages = match build_graph
+        .as_ref()
+        .and_then(|graph| graph.packages(&project_context))
+    {
+        Some(packages) => packages,
+        None => packages::make(filter, &project_context, show_progress)?,
+    };
 
     let compiler_check = verify_compiler_info(&packages, &compiler);
 
@@ -149,11 +185,28 @@ pub fn initialize_build(
     let mut build_state = BuildCommandState::new(project_context, packages, compiler, warn_error);
     packages::parse_packages(&mut build_state)?;
 
-    let compile_assets_state = read_compile_state::read(&mut build_state)?;
+    let cached_ast_sources = build_graph
+        .as_ref()
+        .map(|graph| graph.ast_sources())
+        .unwrap_or_default();
+    let mut compile_assets_state = read_compile_state::read(&mut build_state, &cached_ast_sources)?;
+    build_state.ast_sources = std::mem::take(&mut compile_assets_state.ast_sources);
 
     let (diff_cleanup, total_cleanup) = clean::cleanup_previous_build(&mut build_state, compile_assets_state);
+    if let Some(graph) = &build_graph {
+        graph.restore_deps(&mut build_state);
+    }
     let timing_clean_total = timing_clean_start.elapsed();
 
+    if json_output {
+        events::emit(&BuildEvent::Cleaned {
+            cleaned: diff_cleanup,
+            total: total_cleanup,
+            compiler_update: matches!(compiler_check, CompilerCheckResult::CleanedPackagesDueToCompiler),
+            duration_seconds: default_timing.unwrap_or(timing_clean_total).as_secs_f64(),
+        });
+    }
+
     if show_progress {
         if plain_output {
             if let CompilerCheckResult::CleanedPackagesDueToCompiler = compiler_check {
@@ -193,6 +246,8 @@ fn format_step(current: usize, total: usize) -> console::StyledObject<String> {
 pub enum IncrementalBuildErrorKind {
     SourceFileParseError,
     CompileError(Option<String>),
+    /// The watcher cancelled the build because files changed, nothing was reported
+    Cancelled,
 }
 
 #[derive(Debug, Clone)]
@@ -225,10 +280,12 @@ impl fmt::Display for IncrementalBuildError {
                     write!(f, "{LINE_CLEAR}  {CROSS}Failed to Compile. See Errors Above",)
                 }
             }
+            IncrementalBuildErrorKind::Cancelled => write!(f, "{LINE_CLEAR}  Build was cancelled"),
         }
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn incremental_build(
     build_state: &mut BuildCommandState,
     default_timing: Option<Duration>,
@@ -237,7 +294,32 @@ pub fn incremental_build(
     only_incremental: bool,
     create_sourcedirs: bool,
     plain_output: bool,
+    json_output: bool,
 ) -> Result<(), IncrementalBuildError> {
+    let _span = trace::span("incremental_build");
+    let timing_total = Instant::now();
+    build_state.build_error = None;
+    // the generated files are parsed like the other source files
+    match generators::run(build_state) {
+        Ok(generated) if !generated.is_empty() => log::debug!("Generated {} files", generated.len()),
+        Ok(_) => (),
+        Err(err) => {
+            // the events are on stdout, so the error can be printed with --json too
+            eprintln!("{err:#}");
+            if json_output {
+                events::emit(&BuildEvent::Finished {
+                    success: false,
+                    compiled_modules: 0,
+                    duration_seconds: default_timing.unwrap_or(timing_total.elapsed()).as_secs_f64(),
+                });
+            }
+            build_state.build_error = Some(format!("{err:#}\n"));
+            return Err(IncrementalBuildError {
+                kind: IncrementalBuildErrorKind::CompileError(None),
+                plain_output,
+            });
+        }
+    }
     logs::initialize(&build_state.packages);
     let num_dirty_modules = build_state.modules.values().filter(|m| is_dirty(m)).count() as u64;
     let pb = if !plain_output && show_progress {
@@ -256,15 +338,27 @@ pub fn incremental_build(
         .unwrap(),
     );
 
+    if json_output {
+        events::emit(&BuildEvent::ParseStarted {
+            dirty_modules: num_dirty_modules,
+        });
+    }
+
     let timing_parse_start = Instant::now();
     let timing_ast = Instant::now();
     let result_asts = parse::generate_asts(build_state, || pb.inc(1));
     let timing_ast_elapsed = timing_ast.elapsed();
 
-    let parse_warnings = match result_asts {
+    let (parse_warnings, parse_errors) = match result_asts {
         Ok(warnings) => {
             pb.finish();
-            warnings
+            (warnings, None)
+        }
+        // With --keep-going we still compile all modules that don't depend on a file that failed
+        // to parse, the build fails at the end
+        Err(err) if build_state.keep_going => {
+            pb.finish();
+            (String::new(), Some(err.to_string()))
         }
         Err(err) => {
             logs::finalize(&build_state.packages);
@@ -280,7 +374,21 @@ pub fn incremental_build(
                 pb.finish();
             }
 
-            eprintln!("{}", &err);
+            if json_output {
+                emit_parse_diagnostics(build_state);
+                events::emit(&BuildEvent::ParseFinished {
+                    success: false,
+                    parsed_modules: num_dirty_modules,
+                    duration_seconds: default_timing.unwrap_or(timing_ast_elapsed).as_secs_f64(),
+                });
+                events::emit(&BuildEvent::Finished {
+                    success: false,
+                    compiled_modules: 0,
+                    duration_seconds: default_timing.unwrap_or(timing_total.elapsed()).as_secs_f64(),
+                });
+            } else {
+                eprintln!("{}", &err);
+            }
             return Err(IncrementalBuildError {
                 kind: IncrementalBuildErrorKind::SourceFileParseError,
                 plain_output,
@@ -291,8 +399,24 @@ pub fn incremental_build(
     deps::get_deps(build_state, &deleted_modules);
     let timing_parse_total = timing_parse_start.elapsed();
 
+    if build_state.is_cancelled() {
+        return Err(cancelled(build_state, &pb, plain_output, json_output));
+    }
+
     if show_progress {
-        if plain_output {
+        if parse_errors.is_some() {
+            if plain_output {
+                eprintln!("Could not parse all {num_dirty_modules} source files")
+            } else {
+                eprintln!(
+                    "{}{} {}Error parsing source files in {:.2}s",
+                    LINE_CLEAR,
+                    format_step(current_step, total_steps),
+                    CROSS,
+                    default_timing.unwrap_or(timing_parse_total).as_secs_f64()
+                );
+            }
+        } else if plain_output {
             println!("Parsed {num_dirty_modules} source files")
         } else {
             println!(
@@ -305,7 +429,16 @@ pub fn incremental_build(
             );
         }
     }
-    if helpers::contains_ascii_characters(&parse_warnings) {
+    if json_output {
+        emit_parse_diagnostics(build_state);
+        events::emit(&BuildEvent::ParseFinished {
+            success: parse_errors.is_none(),
+            parsed_modules: num_dirty_modules,
+            duration_seconds: default_timing.unwrap_or(timing_parse_total).as_secs_f64(),
+        });
+    } else if let Some(parse_errors) = &parse_errors {
+        eprintln!("{parse_errors}");
+    } else if helpers::contains_ascii_characters(&parse_warnings) {
         eprintln!("{}", &parse_warnings);
     }
 
@@ -340,6 +473,7 @@ pub fn incremental_build(
     let (compile_errors, compile_warnings, num_compiled_modules) = compile::compile(
         build_state,
         show_progress,
+        json_output,
         || pb.inc(1),
         |size| pb.set_length(size),
     )
@@ -348,6 +482,11 @@ pub fn incremental_build(
         plain_output,
     })?;
 
+    // The modules that were compiled keep their results, the others are still dirty
+    if build_state.is_cancelled() {
+        return Err(cancelled(build_state, &pb, plain_output, json_output));
+    }
+
     let compile_duration = start_compiling.elapsed();
 
     logs::finalize(&build_state.packages);
@@ -355,6 +494,13 @@ pub fn incremental_build(
         sourcedirs::print(build_state);
     }
     pb.finish();
+    if json_output {
+        events::emit(&BuildEvent::Finished {
+            success: compile_errors.is_empty() && parse_errors.is_none(),
+            compiled_modules: num_compiled_modules,
+            duration_seconds: default_timing.unwrap_or(timing_total.elapsed()).as_secs_f64(),
+        });
+    }
     if !compile_errors.is_empty() {
         if show_progress {
             if plain_output {
@@ -370,19 +516,44 @@ pub fn incremental_build(
                 );
             }
         }
-        if helpers::contains_ascii_characters(&compile_warnings) {
+        if !json_output && helpers::contains_ascii_characters(&compile_warnings) {
             eprintln!("{}", &compile_warnings);
         }
         if initial_build {
-            log_config_warnings(build_state);
+            log_config_warnings(build_state, json_output);
         }
-        if helpers::contains_ascii_characters(&compile_errors) {
+        if !json_output && helpers::contains_ascii_characters(&compile_errors) {
             eprintln!("{}", &compile_errors);
         }
         Err(IncrementalBuildError {
             kind: IncrementalBuildErrorKind::CompileError(None),
             plain_output,
         })
+    } else if parse_errors.is_some() {
+        if show_progress {
+            if plain_output {
+                println!("Compiled {num_compiled_modules} modules")
+            } else {
+                println!(
+                    "{}{} {}Compiled {} modules in {:.2}s",
+                    LINE_CLEAR,
+                    format_step(current_step, total_steps),
+                    SWORDS,
+                    num_compiled_modules,
+                    default_timing.unwrap_or(compile_duration).as_secs_f64()
+                );
+            }
+        }
+        if !json_output && helpers::contains_ascii_characters(&compile_warnings) {
+            eprintln!("{}", &compile_warnings);
+        }
+        if initial_build {
+            log_config_warnings(build_state, json_output);
+        }
+        Err(IncrementalBuildError {
+            kind: IncrementalBuildErrorKind::SourceFileParseError,
+            plain_output,
+        })
     } else {
         if show_progress {
             if plain_output {
@@ -399,11 +570,11 @@ pub fn incremental_build(
             }
         }
 
-        if helpers::contains_ascii_characters(&compile_warnings) {
+        if !json_output && helpers::contains_ascii_characters(&compile_warnings) {
             eprintln!("{}", &compile_warnings);
         }
         if initial_build {
-            log_config_warnings(build_state);
+            log_config_warnings(build_state, json_output);
         }
 
         // Write per-package compiler metadata to `lib/bs/compiler-info.json` (idempotent)
@@ -413,7 +584,43 @@ pub fn incremental_build(
     }
 }
 
-fn log_config_warnings(build_state: &BuildCommandState) {
+/// Stops a build that the watcher cancelled, without reporting its (outdated) errors.
+fn cancelled(
+    build_state: &BuildCommandState,
+    pb: &ProgressBar,
+    plain_output: bool,
+    json_output: bool,
+) -> IncrementalBuildError {
+    logs::finalize(&build_state.packages);
+    pb.finish_and_clear();
+    if json_output {
+        events::emit(&BuildEvent::Cancelled);
+    }
+    IncrementalBuildError {
+        kind: IncrementalBuildErrorKind::Cancelled,
+        plain_output,
+    }
+}
+
+fn emit_parse_diagnostics(build_state: &BuildCommandState) {
+    let mut modules = build_state
+        .modules
+        .iter()
+        .filter(|(_, module)| !module.parse_diagnostics.is_empty())
+        .collect::<Vec<_>>();
+    modules.sort_by_key(|(module_name, _)| *module_name);
+    for (module_name, module) in modules {
+        for diagnostic in module.parse_diagnostics.iter() {
+            events::emit(&BuildEvent::Diagnostic {
+                package: &module.package_name,
+                module: module_name,
+                diagnostic,
+            });
+        }
+    }
+}
+
+fn log_config_warnings(build_state: &BuildCommandState, json_output: bool) {
     build_state.packages.iter().for_each(|(_, package)| {
         // Only warn for local dependencies, not external packages
         if package.is_local_dep {
@@ -421,29 +628,55 @@ fn log_config_warnings(build_state: &BuildCommandState) {
                 .config
                 .get_unsupported_fields()
                 .iter()
-                .for_each(|field| log_unsupported_config_field(&package.name, field));
+                .for_each(|field| log_unsupported_config_field(&package.name, field, json_output));
 
             package
                 .config
                 .get_unknown_fields()
                 .iter()
-                .for_each(|field| log_unknown_config_field(&package.name, field));
+                .for_each(|field| log_unknown_config_field(&package.name, field, json_output));
+
+            generators::get_config_warnings(package)
+                .iter()
+                .for_each(|warning| log_config_warning(&package.name, warning, json_output));
+
+            package
+                .config
+                .get_external_includes()
+                .iter()
+                .filter(|dir| !dir.is_dir())
+                .for_each(|dir| {
+                    let warning = format!(
+                        "The folder '{}' in bs-external-includes of '{}' does not exist.",
+                        dir.display(),
+                        package.name
+                    );
+                    log_config_warning(&package.name, &warning, json_output)
+                });
         }
     });
 }
 
-fn log_unsupported_config_field(package_name: &str, field_name: &str) {
+fn log_unsupported_config_field(package_name: &str, field_name: &str, json_output: bool) {
     let warning = format!(
         "The field '{field_name}' found in the package config of '{package_name}' is not supported by ReScript 12's new build system."
     );
-    eprintln!("\n{}", style(warning).yellow());
+    log_config_warning(package_name, &warning, json_output);
 }
 
-fn log_unknown_config_field(package_name: &str, field_name: &str) {
+fn log_unknown_config_field(package_name: &str, field_name: &str, json_output: bool) {
     let warning = format!(
         "Unknown field '{field_name}' found in the package config of '{package_name}'. This option will be ignored."
     );
-    eprintln!("\n{}", style(warning).yellow());
+    log_config_warning(package_name, &warning, json_output);
+}
+
+fn log_config_warning(package_name: &str, warning: &str, json_output: bool) {
+    if json_output {
+        events::emit(&BuildEvent::warning(Some(package_name), warning));
+    } else {
+        eprintln!("\n{}", style(warning).yellow());
+    }
 }
 
 // write build.ninja files in the packages after a non-incremental build
@@ -467,7 +700,12 @@ pub fn build(
     no_timing: bool,
     create_sourcedirs: bool,
     plain_output: bool,
+    json_output: bool,
     warn_error: Option<String>,
+    keep_going: bool,
+    artifact_cache: Option<artifact_cache::ArtifactCache>,
+    reports: &[report::Report],
+    timings_formats: &[timings::TimingsFormat],
 ) -> Result<BuildCommandState> {
     let default_timing: Option<std::time::Duration> = if no_timing {
         Some(std::time::Duration::new(0.0 as u64, 0.0 as u32))
@@ -475,17 +713,22 @@ pub fn build(
         None
     };
     let timing_total = Instant::now();
+    let build_timings = (!timings_formats.is_empty()).then(timings::Timings::new);
     let mut build_state = initialize_build(
         default_timing,
         filter,
         show_progress,
         path,
         plain_output,
+        json_output,
         warn_error,
     )
     .with_context(|| "Could not initialize build")?;
+    build_state.timings = build_timings;
+    build_state.keep_going = keep_going;
+    build_state.artifact_cache = artifact_cache;
 
-    match incremental_build(
+    let result = incremental_build(
         &mut build_state,
         default_timing,
         true,
@@ -493,7 +736,24 @@ pub fn build(
         false,
         create_sourcedirs,
         plain_output,
-    ) {
+        json_output,
+    );
+    build_graph::save(&build_state, filter);
+    // reports that can't be written fail the build, but only after it was cleaned up
+    let timings_result = timings::write(&build_state, timings_formats).map(|timings_paths| {
+        for timings_path in timings_paths {
+            if show_progress {
+                println!("Timing report saved to {}", timings_path.to_string_lossy());
+            }
+        }
+    });
+    let report_errors = [report::write(&build_state, reports).err(), timings_result.err()]
+        .into_iter()
+        .flatten()
+        .map(|e| format!("{e:#}"))
+        .collect::<Vec<String>>();
+
+    match result {
         Ok(_) => {
             if !plain_output && show_progress {
                 let timing_total_elapsed = timing_total.elapsed();
@@ -506,11 +766,17 @@ pub fn build(
             }
             clean::cleanup_after_build(&build_state);
             write_build_ninja(&build_state);
+            if !report_errors.is_empty() {
+                return Err(anyhow!(report_errors.join("\n")));
+            }
             Ok(build_state)
         }
         Err(e) => {
             clean::cleanup_after_build(&build_state);
             write_build_ninja(&build_state);
+            for report_error in report_errors {
+                log::error!("{report_error}");
+            }
             Err(anyhow!("Incremental build failed. Error: {e}"))
         }
     }
@@ -0,0 +1,410 @@
+//! A content-addressed cache of compiler outputs, in a directory (`--cache-dir`) and/or on a
+//! remote HTTP server (`--remote-cache`), which can be shared by several checkouts and CI jobs.
+//!
+//! The key of a compilation is a hash of everything bsc reads: the AST, the compiler arguments,
+//! the `.cmi` and `.cmj` files of the dependencies, the compiler itself and the location of the
+//! package. bsc writes absolute paths into the AST and the `.cmi`/`.cmt` files, so entries are only
+//! reused by checkouts at the same location, which is what CI jobs usually look like. Packages with
+//! a `gentypeconfig` are not cached, as we don't know upfront which files bsc writes for them.
+
+use super::build_types::{BuildCommandState, Module, SourceType};
+use super::packages::Package;
+use super::remote_cache::{RemoteCache, RemoteCacheMode};
+use crate::config::Config;
+use crate::helpers;
+use anyhow::{Result, anyhow};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Bump when the key or the layout of an entry changes
+const KEY_VERSION: &[u8] = b"rescript-artifact-cache-4";
+
+/// The file in an entry with the compiler output (warnings) of the compilation
+const STDERR_FILE: &str = "stderr";
+
+#[derive(Debug)]
+pub struct ArtifactCache {
+    dir: Option<PathBuf>,
+    remote: Option<RemoteCache>,
+}
+
+/// A file written by bsc, stored under `name` in a cache entry.
+#[derive(Debug, Clone)]
+pub struct CompilerOutput {
+    name: String,
+    path: PathBuf,
+}
+
+/// The files of an entry by name, including the compiler output.
+type EntryFiles = Vec<(String, Vec<u8>)>;
+
+impl ArtifactCache {
+    /// Creates the cache, or `None` when neither a cache directory nor a remote cache is configured.
+    pub fn new(
+        dir: Option<&Path>,
+        remote_url: Option<&str>,
+        remote_mode: RemoteCacheMode,
+    ) -> Result<Option<Self>> {
+        if let Some(dir) = dir {
+            fs::create_dir_all(dir)
+                .map_err(|e| anyhow!("Could not create cache directory {}: {e}", dir.to_string_lossy()))?;
+        }
+        let remote = remote_url
+            .map(|url| RemoteCache::new(url, remote_mode))
+            .transpose()?;
+        Ok((dir.is_some() || remote.is_some()).then(|| Self {
+            dir: dir.map(Path::to_owned),
+            remote,
+        }))
+    }
+
+    fn entry_path(dir: &Path, key: &blake3::Hash) -> PathBuf {
+        let key = key.to_hex();
+        dir.join(&key[..2]).join(key.as_str())
+    }
+
+    /// Copies the outputs of a cached compilation to their place and returns its compiler output.
+    /// Entries found in the remote cache are kept in the cache directory too.
+    pub fn restore(&self, key: &blake3::Hash, outputs: &[CompilerOutput]) -> Option<String> {
+        if let Some(dir) = &self.dir
+            && let Some(stderr) = restore_local(&Self::entry_path(dir, key), outputs)
+        {
+            return Some(stderr);
+        }
+
+        let files = decode_entry(&self.remote.as_ref()?.get(key.to_hex().as_str())?)?;
+        let mut stderr = None;
+        for (name, contents) in files.iter() {
+            if name == STDERR_FILE {
+                stderr = Some(String::from_utf8_lossy(contents).to_string());
+            } else if let Some(output) = outputs.iter().find(|output| &output.name == name)
+                && fs::write(&output.path, contents).is_err()
+            {
+                // bsc writes all outputs again
+                return None;
+            }
+        }
+        if let Some(dir) = &self.dir {
+            store_local(dir, key, &files);
+        }
+        stderr
+    }
+
+    /// Stores the outputs of a successful compilation.
+    pub fn store(&self, key: &blake3::Hash, outputs: &[CompilerOutput], stderr: &str) {
+        let mut files = outputs
+            .iter()
+            .filter_map(|output| {
+                fs::read(&output.path)
+                    .ok()
+                    .map(|contents| (output.name.to_owned(), contents))
+            })
+            .collect::<EntryFiles>();
+        files.push((STDERR_FILE.to_string(), stderr.as_bytes().to_vec()));
+
+        if let Some(dir) = &self.dir {
+            store_local(dir, key, &files);
+        }
+        if let Some(remote) = &self.remote {
+            remote.put(key.to_hex().as_str(), &encode_entry(&files));
+        }
+    }
+}
+
+fn restore_local(entry: &Path, outputs: &[CompilerOutput]) -> Option<String> {
+    let stderr = fs::read_to_string(entry.join(STDERR_FILE)).ok()?;
+    for output in outputs {
+        let cached = entry.join(&output.name);
+        if cached.exists() && fs::copy(&cached, &output.path).is_err() {
+            return None;
+        }
+    }
+    Some(stderr)
+}
+
+/// The entry is written to a temporary folder first, so other builds sharing the cache directory
+/// never see a partial entry.
+fn store_local(dir: &Path, key: &blake3::Hash, files: &EntryFiles) {
+    let entry = ArtifactCache::entry_path(dir, key);
+    if entry.exists() {
+        return;
+    }
+    let tmp = dir
+        .join("tmp")
+        .join(format!("{}.{}", key.to_hex(), std::process::id()));
+    let written = fs::create_dir_all(&tmp).and_then(|_| {
+        for (name, contents) in files {
+            fs::write(tmp.join(name), contents)?;
+        }
+        fs::create_dir_all(entry.parent().expect("entry should be in a folder"))?;
+        fs::rename(&tmp, &entry)
+    });
+    if let Err(err) = written {
+        // most likely another build stored the same entry at the same time
+        log::debug!(
+            "Could not store {} in the artifact cache: {err}",
+            entry.to_string_lossy()
+        );
+        let _ = fs::remove_dir_all(&tmp);
+    }
+}
+
+/// Entries are sent to the remote cache as one blob: for every file the length of its name (u32),
+/// the name, the length of its contents (u64) and the contents, all lengths little endian.
+fn encode_entry(files: &EntryFiles) -> Vec<u8> {
+    let mut encoded = vec![];
+    for (name, contents) in files {
+        encoded.extend_from_slice(&(name.len() as u32).to_le_bytes());
+        encoded.extend_from_slice(name.as_bytes());
+        encoded.extend_from_slice(&(contents.len() as u64).to_le_bytes());
+        encoded.extend_from_slice(contents);
+    }
+    encoded
+}
+
+fn decode_entry(mut encoded: &[u8]) -> Option<EntryFiles> {
+    let mut files = vec![];
+    while !encoded.is_empty() {
+        let name_length = usize::try_from(u32::from_le_bytes(encoded.get(..4)?.try_into().ok()?)).ok()?;
+        let name_end = 4usize.checked_add(name_length)?;
+        let name = std::str::from_utf8(encoded.get(4..name_end)?).ok()?.to_string();
+        encoded = &encoded[name_end..];
+        let length = usize::try_from(u64::from_le_bytes(encoded.get(..8)?.try_into().ok()?)).ok()?;
+        let contents_end = 8usize.checked_add(length)?;
+        let contents = encoded.get(8..contents_end)?.to_vec();
+        encoded = &encoded[contents_end..];
+        files.push((name, contents));
+    }
+    Some(files)
+}
+
+fn update_with_file(hasher: &mut blake3::Hasher, path: &Path) {
+    match helpers::compute_file_hash(path) {
+        Some(hash) => hasher.update(hash.as_bytes()),
+        None => hasher.update(b"missing"),
+    };
+}
+
+/// The key of compiling `ast_path` (relative to the build folder of the package) with `args`, or
+/// `None` if the AST can't be read.
+pub fn key(
+    build_state: &BuildCommandState,
+    package: &Package,
+    module: &Module,
+    ast_path: &Path,
+    is_interface: bool,
+    args: &[String],
+) -> Option<blake3::Hash> {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(KEY_VERSION);
+    hasher.update(build_state.compiler_info.bsc_hash.as_bytes());
+    // the outputs contain absolute paths, so they are only valid at the same location
+    hasher.update(package.get_build_path().to_string_lossy().as_bytes());
+    hasher.update(&[0]);
+    for arg in args {
+        hasher.update(arg.as_bytes());
+        hasher.update(&[0]);
+    }
+    hasher.update(helpers::compute_file_hash(&package.get_build_path().join(ast_path))?.as_bytes());
+    if let Some(external_includes_hash) = package.external_includes_hash {
+        hasher.update(external_includes_hash.as_bytes());
+    }
+
+    // the implementation is checked against the interface that was compiled before it
+    if let SourceType::SourceFile(source_file) = &module.source_type
+        && source_file.interface.is_some()
+        && !is_interface
+    {
+        let cmi = helpers::get_compiler_asset(
+            package,
+            &package.namespace,
+            &source_file.implementation.path,
+            "cmi",
+        );
+        update_with_file(&mut hasher, &cmi);
+    }
+
+    let mut deps = module.deps.iter().collect::<Vec<&String>>();
+    deps.sort();
+    for dep_name in deps {
+        let Some(dep) = build_state.get_module(dep_name) else {
+            continue;
+        };
+        let Some(dep_package) = build_state.get_package(&dep.package_name) else {
+            continue;
+        };
+        hasher.update(dep_name.as_bytes());
+        match &dep.source_type {
+            SourceType::SourceFile(source_file) => {
+                for extension in ["cmi", "cmj"] {
+                    update_with_file(
+                        &mut hasher,
+                        &helpers::get_compiler_asset(
+                            dep_package,
+                            &dep_package.namespace,
+                            &source_file.implementation.path,
+                            extension,
+                        ),
+                    );
+                }
+            }
+            SourceType::MlMap(_) => update_with_file(&mut hasher, &dep_package.get_mlmap_compile_path()),
+        }
+    }
+    Some(hasher.finalize())
+}
+
+/// The files bsc writes when compiling the implementation or the interface of a module.
+pub fn outputs(
+    package: &Package,
+    root_config: &Config,
+    implementation_file_path: &Path,
+    basename: &str,
+    is_interface: bool,
+) -> Vec<CompilerOutput> {
+    let dir = package
+        .get_build_path()
+        .join(implementation_file_path.parent().unwrap_or(Path::new("")));
+    let extensions: &[&str] = if is_interface {
+        &["cmti", "cmi"]
+    } else {
+        &["cmi", "cmj", "cmt"]
+    };
+    let mut outputs = extensions
+        .iter()
+        .map(|extension| CompilerOutput {
+            name: extension.to_string(),
+            path: dir.join(format!("{basename}.{extension}")),
+        })
+        .collect::<Vec<CompilerOutput>>();
+    if !is_interface {
+        outputs.extend(
+            root_config
+                .get_package_specs()
+                .iter()
+                .enumerate()
+                .map(|(index, spec)| CompilerOutput {
+                    name: format!("js-{index}"),
+                    path: helpers::get_js_output_path(package, root_config, spec, implementation_file_path),
+                }),
+        );
+    }
+    outputs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::build::{build_types, remote_cache};
+    use tempfile::TempDir;
+
+    #[test]
+    fn restores_stored_outputs() {
+        let temp_dir = TempDir::new().expect("temp dir should be created");
+        let cache = ArtifactCache::new(
+            Some(&temp_dir.path().join("cache")),
+            None,
+            RemoteCacheMode::ReadWrite,
+        )
+        .expect("cache should be created")
+        .expect("cache should be configured");
+        let output = |name: &str| CompilerOutput {
+            name: name.to_string(),
+            path: temp_dir.path().join(format!("Main.{name}")),
+        };
+        let outputs = vec![output("cmi"), output("cmj")];
+        let key = blake3::hash(b"Main");
+        fs::write(&outputs[0].path, "cmi").unwrap();
+        fs::write(&outputs[1].path, "cmj").unwrap();
+
+        assert_eq!(cache.restore(&key, &outputs), None);
+        cache.store(&key, &outputs, "Warning 32");
+
+        fs::remove_file(&outputs[0].path).unwrap();
+        fs::remove_file(&outputs[1].path).unwrap();
+        assert_eq!(cache.restore(&key, &outputs), Some("Warning 32".to_string()));
+        assert_eq!(fs::read_to_string(&outputs[0].path).unwrap(), "cmi");
+        assert_eq!(fs::read_to_string(&outputs[1].path).unwrap(), "cmj");
+        assert!(
+            !temp_dir
+                .path()
+                .join("cache")
+                .join("tmp")
+                .read_dir()
+                .unwrap()
+                .any(|_| true)
+        );
+    }
+
+    #[test]
+    fn restores_outputs_from_the_remote_cache() {
+        let (url, _) = remote_cache::tests::start_mock_server();
+        let temp_dir = TempDir::new().expect("temp dir should be created");
+        let output = CompilerOutput {
+            name: "cmj".to_string(),
+            path: temp_dir.path().join("Main.cmj"),
+        };
+        let key = blake3::hash(b"Main");
+
+        // e.g. a CI job that only uses the remote cache
+        let ci_cache = ArtifactCache::new(None, Some(&url), RemoteCacheMode::ReadWrite)
+            .unwrap()
+            .unwrap();
+        fs::write(&output.path, "cmj").unwrap();
+        ci_cache.store(&key, std::slice::from_ref(&output), "");
+        fs::remove_file(&output.path).unwrap();
+
+        let local_dir = temp_dir.path().join("cache");
+        let cache = ArtifactCache::new(Some(&local_dir), Some(&url), RemoteCacheMode::ReadOnly)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            cache.restore(&key, std::slice::from_ref(&output)),
+            Some(String::new())
+        );
+        assert_eq!(fs::read_to_string(&output.path).unwrap(), "cmj");
+        assert!(ArtifactCache::entry_path(&local_dir, &key).join("cmj").exists());
+    }
+
+    #[test]
+    fn encodes_entries() {
+        let files = vec![
+            ("cmi".to_string(), b"cmi".to_vec()),
+            (STDERR_FILE.to_string(), vec![]),
+        ];
+        assert_eq!(decode_entry(&encode_entry(&files)), Some(files));
+        assert_eq!(decode_entry(b"\x05\x00"), None);
+        // a length that overflows
+        let mut encoded = encode_entry(&vec![("cmi".to_string(), vec![])]);
+        encoded.truncate(4 + 3);
+        encoded.extend(u64::MAX.to_le_bytes());
+        assert_eq!(decode_entry(&encoded), None);
+    }
+
+    #[test]
+    fn keys_depend_on_the_location_of_the_checkout() {
+        let files = [("src/Main.res", "let x = 1")];
+        let config = r#"{"name": "test", "sources": "src"}"#;
+        let key = |root: &Path| {
+            let build_state = build_types::tests::build_command_state(root);
+            let package = &build_state.packages["test"];
+            let ast_path = Path::new("src/Main.ast");
+            fs::create_dir_all(package.get_build_path().join("src")).unwrap();
+            fs::write(package.get_build_path().join(ast_path), "ast").unwrap();
+            key(
+                &build_state,
+                package,
+                &build_state.modules["Main"],
+                ast_path,
+                false,
+                &[],
+            )
+            .expect("AST should be read")
+        };
+        let (_temp_dir, root) = build_types::tests::project(config, &files);
+        let (_other_temp_dir, other_root) = build_types::tests::project(config, &files);
+
+        assert_eq!(key(&root), key(&root));
+        assert_ne!(key(&root), key(&other_root));
+    }
+}
@@ -0,0 +1,355 @@
+//! A cache of the resolved build graph, stored in `lib/bs/build-graph.json` of the root package.
+//!
+//! Resolving the packages, finding their source files and reading the dependencies of every
+//! module from its AST takes a while in big projects, while it rarely changes between two builds.
+//! The packages are reused when the config files they were resolved from have the same content,
+//! and the directories that were read (source folders and `node_modules`) have the same
+//! modification time. The dependencies of a module are reused when its ASTs didn't change.
+
+use super::build_types::{BuildCommandState, BuildState, SourceType};
+use super::packages::{self, Package, SourceFileMeta};
+use crate::helpers;
+use crate::helpers::StrippedVerbatimPath;
+use crate::project_context::ProjectContext;
+use crate::trace;
+use ahash::{AHashMap, AHashSet};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+const VERSION: u32 = 1;
+
+/// The source file an AST was generated from, as read from the AST header.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct AstSource {
+    pub modified: SystemTime,
+    pub source: PathBuf,
+}
+
+/// The sources of the ASTs in `lib/ocaml`, by AST path.
+pub type AstSources = AHashMap<PathBuf, AstSource>;
+
+#[derive(Serialize, Deserialize, Debug)]
+struct CachedPackage {
+    name: String,
+    path: PathBuf,
+    is_root: bool,
+    is_local_dep: bool,
+    /// Source files relative to the package, and whether they are dev sources
+    source_files: BTreeMap<PathBuf, bool>,
+    read_dirs: BTreeMap<PathBuf, SystemTime>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct CachedModule {
+    ast_modified: SystemTime,
+    iast_modified: Option<SystemTime>,
+    deps: BTreeSet<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct BuildGraph {
+    version: u32,
+    rescript_version: String,
+    filter: Option<String>,
+    /// Content hashes of the config files, `None` if the file didn't exist
+    configs: BTreeMap<PathBuf, Option<String>>,
+    /// The `node_modules` folders dependencies were resolved from
+    node_modules: BTreeMap<PathBuf, SystemTime>,
+    packages: BTreeMap<String, CachedPackage>,
+    module_names: BTreeSet<String>,
+    modules: BTreeMap<String, CachedModule>,
+    ast_sources: BTreeMap<PathBuf, AstSource>,
+}
+
+fn get_build_graph_path(project_context: &ProjectContext) -> Option<PathBuf> {
+    let package_dir = project_context
+        .current_config
+        .path
+        .parent()?
+        .canonicalize()
+        .map(StrippedVerbatimPath::to_stripped_verbatim_path)
+        .ok()?;
+    Some(packages::get_build_path(&package_dir).join("build-graph.json"))
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|meta| meta.modified()).ok()
+}
+
+fn hash_file(path: &Path) -> Option<String> {
+    helpers::compute_file_hash(path).map(|hash| hash.to_hex().to_string())
+}
+
+/// Reads the cached build graph, if it was written by this version for the same filter.
+pub fn load(project_context: &ProjectContext, filter: &Option<regex::Regex>) -> Option<BuildGraph> {
+    let _span = trace::span("build_graph::load");
+    let contents = fs::read_to_string(get_build_graph_path(project_context)?).ok()?;
+    match serde_json::from_str::<BuildGraph>(&contents) {
+        Ok(graph)
+            if graph.version == VERSION
+                && graph.rescript_version == env!("CARGO_PKG_VERSION")
+                && graph.filter.as_deref() == filter.as_ref().map(|filter| filter.as_str()) =>
+        {
+            Some(graph)
+        }
+        Ok(_) => {
+            log::debug!("Build graph cache is outdated");
+            None
+        }
+        Err(err) => {
+            log::debug!("Could not read build graph cache: {err}");
+            None
+        }
+    }
+}
+
+impl BuildGraph {
+    /// The cached packages with their source files, or `None` when anything they were resolved
+    /// from changed.
+    pub fn packages(&self, project_context: &ProjectContext) -> Option<AHashMap<String, Package>> {
+        let _span = trace::span("build_graph::packages");
+        let configs_unchanged = self
+            .configs
+            .par_iter()
+            .all(|(path, hash)| &hash_file(path) == hash);
+        let dirs_unchanged = self
+            .node_modules
+            .iter()
+            .chain(
+                self.packages
+                    .values()
+                    .flat_map(|package| package.read_dirs.iter()),
+            )
+            .par_bridge()
+            .all(|(dir, mtime)| modified(dir) == Some(*mtime));
+        if !configs_unchanged || !dirs_unchanged {
+            log::debug!("Build graph cache is stale, resolving packages");
+            return None;
+        }
+
+        self.packages
+            .par_iter()
+            .map(|(key, cached)| {
+                let config = if cached.is_root {
+                    project_context.current_config.to_owned()
+                } else {
+                    packages::read_config(&cached.path).ok()?
+                };
+                let source_files = cached
+                    .source_files
+                    .iter()
+                    .map(|(path, is_type_dev)| {
+                        modified(&cached.path.join(path)).map(|modified| {
+                            (
+                                path.to_owned(),
+                                SourceFileMeta {
+                                    modified,
+                                    is_type_dev: *is_type_dev,
+                                },
+                            )
+                        })
+                    })
+                    .collect::<Option<AHashMap<PathBuf, SourceFileMeta>>>()?;
+                let mut package = packages::new_package(
+                    cached.name.to_owned(),
+                    config,
+                    cached.path.to_owned(),
+                    cached.is_root,
+                    cached.is_local_dep,
+                );
+                packages::set_source_files(
+                    &mut package,
+                    source_files,
+                    cached.read_dirs.clone().into_iter().collect(),
+                );
+                Some((key.to_owned(), package))
+            })
+            .collect::<Option<Vec<(String, Package)>>>()
+            .map(|packages| packages.into_iter().collect())
+    }
+
+    pub fn ast_sources(&self) -> AstSources {
+        self.ast_sources
+            .iter()
+            .map(|(path, source)| (path.to_owned(), source.to_owned()))
+            .collect()
+    }
+
+    /// Restores the dependencies of the modules whose ASTs didn't change. Only when the same
+    /// modules exist, because a new module could be a dependency that was left out before.
+    pub fn restore_deps(&self, build_state: &mut BuildState) {
+        let _span = trace::span("build_graph::restore_deps");
+        if !build_state.deleted_modules.is_empty()
+            || self.module_names.len() != build_state.module_names.len()
+            || !self
+                .module_names
+                .iter()
+                .all(|module_name| build_state.module_names.contains(module_name))
+        {
+            return;
+        }
+
+        let packages = &build_state.packages;
+        build_state
+            .modules
+            .par_iter_mut()
+            .for_each(|(module_name, module)| {
+                let (Some(cached), SourceType::SourceFile(source_file)) =
+                    (self.modules.get(module_name), &module.source_type)
+                else {
+                    return;
+                };
+                let Some(package) = packages.get(&module.package_name) else {
+                    return;
+                };
+                let build_path = package.get_build_path();
+                let ast_modified =
+                    modified(&build_path.join(helpers::get_ast_path(&source_file.implementation.path)));
+                let iast_modified = source_file
+                    .interface
+                    .as_ref()
+                    .and_then(|interface| modified(&build_path.join(helpers::get_ast_path(&interface.path))));
+                if ast_modified == Some(cached.ast_modified) && iast_modified == cached.iast_modified {
+                    module.deps = cached.deps.iter().cloned().collect();
+                    module.deps_dirty = false;
+                }
+            });
+        // the modules without cached deps are still deps_dirty, so only those are read
+        build_state.deps_initialized = true;
+    }
+}
+
+/// The `node_modules` folders (and scope folders in them) of the packages and the project root,
+/// a reinstall that resolves a dependency to another folder changes them.
+fn get_node_modules(build_state: &BuildState) -> BTreeMap<PathBuf, SystemTime> {
+    let scopes = build_state
+        .packages
+        .keys()
+        .filter(|name| name.starts_with('@'))
+        .filter_map(|name| name.split('/').next())
+        .collect::<AHashSet<&str>>();
+    build_state
+        .packages
+        .values()
+        .map(|package| package.path.as_path())
+        .chain([build_state.project_context.get_root_path()])
+        .flat_map(|dir| {
+            let node_modules = dir.join("node_modules");
+            scopes
+                .iter()
+                .map(|scope| node_modules.join(scope))
+                .chain([node_modules.to_owned()])
+                .collect::<Vec<PathBuf>>()
+        })
+        .filter_map(|dir| modified(&dir).map(|modified| (dir, modified)))
+        .collect()
+}
+
+fn get_configs(build_state: &BuildState) -> BTreeMap<PathBuf, Option<String>> {
+    build_state
+        .packages
+        .values()
+        .flat_map(|package| [package.config.path.to_owned(), package.path.join("package.json")])
+        .chain([build_state.get_root_config().path.to_owned()])
+        .collect::<BTreeSet<PathBuf>>()
+        .into_par_iter()
+        .map(|path| {
+            let hash = hash_file(&path);
+            (path, hash)
+        })
+        .collect()
+}
+
+fn get_modules(build_state: &BuildState) -> BTreeMap<String, CachedModule> {
+    if !build_state.deps_initialized {
+        return BTreeMap::new();
+    }
+    build_state
+        .modules
+        .par_iter()
+        .filter_map(|(module_name, module)| {
+            let SourceType::SourceFile(source_file) = &module.source_type else {
+                return None;
+            };
+            if module.deps_dirty || module.has_parse_error() {
+                return None;
+            }
+            let build_path = build_state.get_package(&module.package_name)?.get_build_path();
+            let ast_modified =
+                modified(&build_path.join(helpers::get_ast_path(&source_file.implementation.path)))?;
+            let iast_modified = match &source_file.interface {
+                Some(interface) => Some(modified(
+                    &build_path.join(helpers::get_ast_path(&interface.path)),
+                )?),
+                None => None,
+            };
+            Some((
+                module_name.to_owned(),
+                CachedModule {
+                    ast_modified,
+                    iast_modified,
+                    deps: module.deps.iter().cloned().collect(),
+                },
+            ))
+        })
+        .collect()
+}
+
+/// Writes the build graph of the (finished or failed) build.
+pub fn save(build_state: &BuildCommandState, filter: &Option<regex::Regex>) {
+    let _span = trace::span("build_graph::save");
+    let Some(path) = get_build_graph_path(&build_state.project_context) else {
+        return;
+    };
+    let graph = BuildGraph {
+        version: VERSION,
+        rescript_version: env!("CARGO_PKG_VERSION").to_string(),
+        filter: filter.as_ref().map(|filter| filter.as_str().to_string()),
+        configs: get_configs(build_state),
+        node_modules: get_node_modules(build_state),
+        packages: build_state
+            .packages
+            .iter()
+            .map(|(key, package)| {
+                let cached = CachedPackage {
+                    name: package.name.to_owned(),
+                    path: package.path.to_owned(),
+                    is_root: package.is_root,
+                    is_local_dep: package.is_local_dep,
+                    source_files: package
+                        .source_files
+                        .iter()
+                        .flatten()
+                        .map(|(path, meta)| (path.to_owned(), meta.is_type_dev))
+                        .collect(),
+                    read_dirs: package
+                        .read_dirs
+                        .iter()
+                        .flatten()
+                        .map(|(dir, modified)| (dir.to_owned(), *modified))
+                        .collect(),
+                };
+                (key.to_owned(), cached)
+            })
+            .collect(),
+        module_names: build_state.module_names.iter().cloned().collect(),
+        modules: get_modules(build_state),
+        ast_sources: build_state
+            .ast_sources
+            .iter()
+            .map(|(path, source)| (path.to_owned(), source.to_owned()))
+            .collect(),
+    };
+    match serde_json::to_string(&graph) {
+        Ok(contents) => {
+            if let Err(err) = fs::write(&path, contents) {
+                log::debug!("Could not write build graph cache: {err}");
+            }
+        }
+        Err(err) => log::debug!("Could not serialize build graph cache: {err}"),
+    }
+}
@@ -1,8 +1,14 @@
+use crate::build::artifact_cache::ArtifactCache;
+use crate::build::build_graph::AstSources;
+use crate::build::diagnostics::Diagnostic;
 use crate::build::packages::{Namespace, Package};
+use crate::build::timings::Timings;
 use crate::config::Config;
 use crate::project_context::ProjectContext;
 use ahash::{AHashMap, AHashSet};
 use blake3::Hash;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::{fmt::Display, ops::Deref, path::PathBuf, time::SystemTime};
 
 #[derive(Debug, Clone, PartialEq)]
@@ -27,10 +33,6 @@ pub struct Interface {
     pub compile_state: CompileState,
     pub last_modified: SystemTime,
     pub parse_dirty: bool,
-    /// Compiler warning output (from bsc stderr) stored for re-emission
-    /// during incremental builds when this module is not recompiled.
-    /// Written to `.compiler.log` on each build cycle.
-    pub compile_warnings: Option<String>,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -40,10 +42,6 @@ pub struct Implementation {
     pub compile_state: CompileState,
     pub last_modified: SystemTime,
     pub parse_dirty: bool,
-    /// Compiler warning output (from bsc stderr) stored for re-emission
-    /// during incremental builds when this module is not recompiled.
-    /// Written to `.compiler.log` on each build cycle.
-    pub compile_warnings: Option<String>,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -83,6 +81,13 @@ pub struct Module {
     pub last_compiled_cmt: Option<SystemTime>,
     pub deps_dirty: bool,
     pub is_type_dev: bool,
+    /// Diagnostics reported by bsc when parsing the implementation and interface of this module
+    /// (or when compiling the mlmap of a namespace).
+    pub parse_diagnostics: Vec<Diagnostic>,
+    /// Diagnostics reported by bsc when this module was last compiled. They are re-emitted
+    /// during incremental builds when this module is not recompiled, and written to
+    /// `.compiler.log` on each build cycle.
+    pub compile_diagnostics: Vec<Diagnostic>,
 }
 
 impl Module {
@@ -96,6 +101,20 @@ impl Module {
             _ => &None,
         }
     }
+
+    /// The implementation or interface (or the mlmap of a namespace) could not be parsed.
+    pub fn has_parse_error(&self) -> bool {
+        match &self.source_type {
+            SourceType::SourceFile(source_file) => {
+                source_file.implementation.parse_state == ParseState::ParseError
+                    || source_file
+                        .interface
+                        .as_ref()
+                        .is_some_and(|interface| interface.parse_state == ParseState::ParseError)
+            }
+            SourceType::MlMap(_) => !self.parse_diagnostics.is_empty(),
+        }
+    }
 }
 
 /// Core build state containing all the essential data needed for compilation.
@@ -125,6 +144,18 @@ pub struct BuildCommandState {
     pub build_state: BuildState,
     // Command-line --warn-error flag override (takes precedence over rescript.json config)
     pub warn_error_override: Option<String>,
+    // Records the bsc invocations of the build when --timings is passed
+    pub timings: Option<Timings>,
+    // Command-line --keep-going flag: compile every module whose dependencies succeeded
+    pub keep_going: bool,
+    // The sources of the ASTs found when the build was initialized, kept in the build graph cache
+    pub ast_sources: AstSources,
+    // Command-line --cache-dir flag: restore compiler outputs from (and store them in) this cache
+    pub artifact_cache: Option<ArtifactCache>,
+    // Set by the watcher when a file changes during a build, no new modules are compiled after that
+    pub cancel: Option<Arc<AtomicBool>>,
+    // An error of the last build that doesn't belong to a module, like a failing generator
+    pub build_error: Option<String>,
 }
 
 #[derive(Debug, Clone)]
@@ -179,9 +210,21 @@ impl BuildCommandState {
         Self {
             build_state: BuildState::new(project_context, packages, compiler),
             warn_error_override,
+            timings: None,
+            keep_going: false,
+            ast_sources: AstSources::new(),
+            artifact_cache: None,
+            cancel: None,
+            build_error: None,
         }
     }
 
+    pub fn is_cancelled(&self) -> bool {
+        self.cancel
+            .as_ref()
+            .is_some_and(|cancel| cancel.load(Ordering::Relaxed))
+    }
+
     pub fn get_warn_error_override(&self) -> Option<String> {
         self.warn_error_override.clone()
     }
@@ -229,4 +272,51 @@ pub struct CompileAssetsState {
     pub cmt_modules: AHashMap<String, SystemTime>,
     pub ast_rescript_file_locations: AHashSet<PathBuf>,
     pub rescript_file_locations: AHashSet<PathBuf>,
+    pub ast_sources: AstSources,
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::build::packages;
+    use std::fs;
+    use std::path::Path;
+    use tempfile::TempDir;
+
+    /// A project in a temporary folder with `config` as its `rescript.json` and the given files,
+    /// by path relative to the root.
+    pub(crate) fn project(config: &str, files: &[(&str, &str)]) -> (TempDir, PathBuf) {
+        let temp_dir = TempDir::new().expect("temp dir should be created");
+        let root = temp_dir.path().canonicalize().unwrap();
+        fs::write(root.join("rescript.json"), config).unwrap();
+        for (path, contents) in files {
+            let path = root.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+        (temp_dir, root)
+    }
+
+    /// The build state of the project in `root`, with its source files read.
+    pub(crate) fn build_state(root: &Path) -> BuildState {
+        build_command_state(root).build_state
+    }
+
+    /// Like `build_state`, with the options of a build command.
+    pub(crate) fn build_command_state(root: &Path) -> BuildCommandState {
+        let project_context = ProjectContext::new(root).expect("project context should be read");
+        let packages = packages::make(&None, &project_context, false).expect("packages should be read");
+        let mut build_state = BuildCommandState::new(
+            project_context,
+            packages,
+            CompilerInfo {
+                bsc_path: PathBuf::from("bsc"),
+                bsc_hash: blake3::hash(b"bsc"),
+                runtime_path: PathBuf::from("runtime"),
+            },
+            None,
+        );
+        packages::parse_packages(&mut build_state).expect("packages should be parsed");
+        build_state
+    }
 }
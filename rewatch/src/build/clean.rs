@@ -1,12 +1,14 @@
 use super::build_types::*;
 use super::packages;
+use super::source_hashes::{self, SourceHashes};
 use crate::build;
 use crate::build::packages::Package;
 use crate::config::Config;
 use crate::helpers;
 use crate::helpers::emojis::*;
 use crate::project_context::ProjectContext;
-use ahash::AHashSet;
+use crate::trace;
+use ahash::{AHashMap, AHashSet};
 use anyhow::Result;
 use console::style;
 use rayon::prelude::*;
@@ -62,6 +64,27 @@ pub fn remove_compile_assets(package: &packages::Package, source_file: &Path) {
     }
 }
 
+/// Removes the ASTs, compiler assets and JavaScript files of a source file that was deleted.
+pub fn remove_source_file_assets(package: &packages::Package, root_config: &Config, source_file: &Path) {
+    remove_ast(package, source_file);
+    remove_iast(package, source_file);
+    remove_compile_assets(package, source_file);
+    for spec in root_config.get_package_specs() {
+        let _ = std::fs::remove_file(helpers::get_js_output_path(
+            package,
+            root_config,
+            &spec,
+            source_file,
+        ));
+    }
+}
+
+/// Removes the AST and the compiled interface of an interface file that was deleted.
+pub fn remove_interface_assets(package: &packages::Package, source_file: &Path) {
+    remove_iast(package, source_file);
+    remove_compile_asset(package, source_file, "cmti");
+}
+
 fn clean_source_files(build_state: &BuildState, root_config: &Config) {
     // get all rescript file locations
     let rescript_file_locations = build_state
@@ -106,6 +129,7 @@ pub fn cleanup_previous_build(
     build_state: &mut BuildCommandState,
     compile_assets_state: CompileAssetsState,
 ) -> (usize, usize) {
+    let _span = trace::span("cleanup_previous_build");
     // delete the .mjs file which appear in our previous compile assets
     // but does not exists anymore
     // delete the compiler assets for which modules we can't find a rescript file
@@ -152,12 +176,16 @@ pub fn cleanup_previous_build(
         .filter_map(|module_name| module_name.to_owned())
         .collect::<AHashSet<String>>();
 
+    // the stored source hashes, only read for packages with sources that are newer than their AST
+    let mut hashes_by_package = AHashMap::<String, SourceHashes>::new();
+
     compile_assets_state
         .ast_rescript_file_locations
         .intersection(&compile_assets_state.rescript_file_locations)
         .for_each(|res_file_location| {
             let AstModule {
                 module_name,
+                package_name,
                 last_modified: ast_last_modified,
                 ast_file_path,
                 ..
@@ -165,6 +193,44 @@ pub fn cleanup_previous_build(
                 .ast_modules
                 .get(res_file_location)
                 .expect("Could not find module name for ast file");
+
+            // The source is newer than the AST, but it might still have the same content as when
+            // the AST was generated, e.g. after switching branches with git.
+            let mut has_same_content = |source_path: &Path| {
+                let package = build_state
+                    .packages
+                    .get(package_name)
+                    .expect("Could not find package");
+                let hashes = hashes_by_package
+                    .entry(package_name.to_owned())
+                    .or_insert_with(|| source_hashes::read(package));
+                source_hashes::is_unchanged(hashes, package, source_path)
+            };
+            let is_ast_up_to_date = match &build_state
+                .modules
+                .get(module_name)
+                .expect("Could not find module for ast file")
+                .source_type
+            {
+                SourceType::MlMap(_) => unreachable!("MlMap is not matched with a ReScript file"),
+                SourceType::SourceFile(source_file) => {
+                    let (source_path, source_last_modified) = if helpers::is_interface_ast_file(ast_file_path)
+                    {
+                        let interface = source_file
+                            .interface
+                            .as_ref()
+                            .expect("Could not find interface for module");
+                        (&interface.path, interface.last_modified)
+                    } else {
+                        (
+                            &source_file.implementation.path,
+                            source_file.implementation.last_modified,
+                        )
+                    };
+                    ast_last_modified > &source_last_modified || has_same_content(source_path)
+                }
+            };
+
             let module = build_state
                 .modules
                 .get_mut(module_name)
@@ -191,16 +257,12 @@ pub fn cleanup_previous_build(
                             .as_mut()
                             .expect("Could not find interface for module");
 
-                        let source_last_modified = interface.last_modified;
-                        if ast_last_modified > &source_last_modified {
+                        if is_ast_up_to_date {
                             interface.parse_dirty = false;
                         }
                     } else {
                         let implementation = &mut source_file.implementation;
-                        let source_last_modified = implementation.last_modified;
-                        if ast_last_modified > &source_last_modified
-                            && !deleted_interfaces.contains(module_name)
-                        {
+                        if is_ast_up_to_date && !deleted_interfaces.contains(module_name) {
                             implementation.parse_dirty = false;
                         }
                     }
@@ -301,6 +363,7 @@ fn has_compile_warnings(module: &Module) -> bool {
 }
 
 pub fn cleanup_after_build(build_state: &BuildCommandState) {
+    let _span = trace::span("cleanup_after_build");
     build_state.modules.par_iter().for_each(|(_module_name, module)| {
         let package = build_state.get_package(&module.package_name).unwrap();
         if has_parse_warnings(module)
@@ -2,24 +2,31 @@
 
 mod dependency_cycle;
 
+use super::artifact_cache;
 use super::build_types::*;
+use super::diagnostics::{self, Diagnostic, Severity};
+use super::events::{self, BuildEvent, ModuleStatus};
 use super::logs;
 use super::packages;
+use super::timings::{self, UnitKind};
 use crate::config;
 use crate::config::Config;
 use crate::helpers;
 use crate::helpers::StrippedVerbatimPath;
 use crate::project_context::ProjectContext;
+use crate::trace;
 use ahash::{AHashMap, AHashSet};
 use anyhow::{Result, anyhow};
 use console::style;
 use log::{debug, info, trace, warn};
 use rayon::prelude::*;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
 use std::path::Path;
 use std::path::PathBuf;
 use std::process::Command;
-use std::sync::OnceLock;
-use std::time::SystemTime;
+use std::sync::{OnceLock, mpsc};
+use std::time::{Instant, SystemTime};
 
 /// Execute js-post-build command for a compiled JavaScript file.
 /// The command runs in the directory containing the rescript.json that defines it.
@@ -72,14 +79,175 @@ fn execute_post_build_command(cmd: &str, js_file_path: &Path, working_dir: &Path
     }
 }
 
+/// Why a module was not compiled with `--keep-going`.
+enum SkipReason {
+    /// The module itself could not be parsed
+    ParseError,
+    /// The module (transitively) depends on this module, which failed to parse or compile
+    FailedDependency(String),
+}
+
+/// The outcome of compiling (or skipping) a single module.
+struct ModuleResult {
+    module_name: String,
+    result: Result<Option<String>>,
+    interface_result: Option<Result<Option<String>>>,
+    /// The cmi didn't change, so the dependents don't need to be recompiled because of this module
+    is_clean: bool,
+    is_compiled: bool,
+    skipped: Option<SkipReason>,
+}
+
+impl ModuleResult {
+    fn not_compiled(module_name: String, is_clean: bool) -> Self {
+        ModuleResult {
+            module_name,
+            result: Ok(None),
+            interface_result: Some(Ok(None)),
+            is_clean,
+            is_compiled: false,
+            skipped: None,
+        }
+    }
+
+    fn skipped(module_name: String, reason: SkipReason) -> Self {
+        ModuleResult {
+            skipped: Some(reason),
+            ..ModuleResult::not_compiled(module_name, false)
+        }
+    }
+}
+
+/// The modules in the compile universe that depend on `module_name`. `Module::dependents` only ever
+/// grows, so we check that the dependent still has `module_name` in its deps.
+fn dependents_in<'a>(
+    build_state: &'a BuildState,
+    compile_universe: &'a AHashSet<String>,
+    module_name: &'a str,
+) -> impl Iterator<Item = &'a String> {
+    build_state
+        .get_module(module_name)
+        .into_iter()
+        .flat_map(|module| module.dependents.iter())
+        .filter(move |dependent| {
+            compile_universe.contains(*dependent)
+                && build_state
+                    .get_module(dependent)
+                    .is_some_and(|module| module.deps.contains(module_name))
+        })
+}
+
+/// For every module in the compile universe, the number of modules on the longest path from it to
+/// a module that nothing depends on (including itself). Scheduling the modules with the longest
+/// remaining path first keeps the deep parts of the graph moving, so all cores stay busy.
+fn critical_path_lengths(
+    build_state: &BuildState,
+    compile_universe: &AHashSet<String>,
+    pending_deps: &AHashMap<String, usize>,
+) -> AHashMap<String, usize> {
+    // topological order of the universe (modules in a cycle are left out)
+    let mut pending_deps = pending_deps.clone();
+    let mut queue = pending_deps
+        .iter()
+        .filter(|(_, count)| **count == 0)
+        .map(|(module_name, _)| module_name.to_string())
+        .collect::<Vec<String>>();
+    let mut order = vec![];
+    while let Some(module_name) = queue.pop() {
+        for dependent in dependents_in(build_state, compile_universe, &module_name) {
+            let count = pending_deps.get_mut(dependent).unwrap();
+            *count -= 1;
+            if *count == 0 {
+                queue.push(dependent.to_string());
+            }
+        }
+        order.push(module_name);
+    }
+
+    let mut lengths = AHashMap::<String, usize>::new();
+    for module_name in order.iter().rev() {
+        let length = 1 + dependents_in(build_state, compile_universe, module_name)
+            .filter_map(|dependent| lengths.get(dependent))
+            .max()
+            .unwrap_or(&0);
+        lengths.insert(module_name.to_string(), length);
+    }
+    lengths
+}
+
+fn compile_module(build_state: &BuildCommandState, module_name: String) -> ModuleResult {
+    let module = build_state.get_module(&module_name).unwrap();
+    let package = build_state
+        .get_package(&module.package_name)
+        .expect("Package not found");
+    match &module.source_type {
+        SourceType::MlMap(_) => {
+            // the mlmap needs to be compiled before the files are compiled
+            // in the same namespace, otherwise we get a compile error
+            // this is why mlmap is compiled in the AST generation stage
+            ModuleResult::not_compiled(module_name, false)
+        }
+        SourceType::SourceFile(source_file) => {
+            let cmi_path = helpers::get_compiler_asset(
+                package,
+                &package.namespace,
+                &source_file.implementation.path,
+                "cmi",
+            );
+
+            let cmi_digest = helpers::compute_file_hash(Path::new(&cmi_path));
+
+            let interface_result = source_file.interface.as_ref().map(|Interface { path, .. }| {
+                compile_file(
+                    package,
+                    &helpers::get_ast_path(path),
+                    module,
+                    true,
+                    build_state,
+                    build_state.get_warn_error_override(),
+                )
+            });
+            let result = compile_file(
+                package,
+                &helpers::get_ast_path(&source_file.implementation.path),
+                module,
+                false,
+                build_state,
+                build_state.get_warn_error_override(),
+            );
+            let cmi_digest_after = helpers::compute_file_hash(Path::new(&cmi_path));
+
+            // we want to compare both the hash of interface and the implementation
+            // compile assets to verify that nothing changed. We also need to checke the interface
+            // because we can include MyModule, so the modules that depend on this module might
+            // change when this modules interface does not change, but the implementation does
+            let is_clean_cmi = match (cmi_digest, cmi_digest_after) {
+                (Some(cmi_digest), Some(cmi_digest_after)) => cmi_digest.eq(&cmi_digest_after),
+
+                _ => false,
+            };
+
+            ModuleResult {
+                module_name,
+                result,
+                interface_result,
+                is_clean: is_clean_cmi,
+                is_compiled: true,
+                skipped: None,
+            }
+        }
+    }
+}
+
 pub fn compile(
     build_state: &mut BuildCommandState,
     show_progress: bool,
+    json_output: bool,
     inc: impl Fn() + std::marker::Sync,
     set_length: impl Fn(u64),
 ) -> anyhow::Result<(String, String, usize)> {
-    let mut compiled_modules = AHashSet::<String>::new();
-    let dirty_modules = build_state
+    let _span = trace::span("compile::compile");
+    let mut dirty_modules = build_state
         .modules
         .iter()
         .filter_map(|(module_name, module)| {
@@ -91,30 +259,15 @@ pub fn compile(
         })
         .collect::<AHashSet<String>>();
 
-    // dirty_modules.iter().for_each(|m| println!("dirty module: {}", m));
-    // println!("{} dirty modules", dirty_modules.len());
-    let mut sorted_dirty_modules = dirty_modules.iter().collect::<Vec<&String>>();
-    sorted_dirty_modules.sort();
-    // dirty_modules.iter().for_each(|m| println!("dirty module: {}", m));
-    // sorted_dirty_modules
-    //     .iter()
-    //     .for_each(|m| println!("dirty module: {}", m));
-
-    // for sure clean modules -- after checking the hash of the cmi
-    let mut clean_modules = AHashSet::<String>::new();
-
-    // TODO: calculate the real dirty modules from the original dirty modules in each iteration
-    // taken into account the modules that we know are clean, so they don't propagate through the
-    // deps graph
-    // create a hashset of all clean modules from the file-hashes
-    let mut loop_count = 0;
-    let mut files_total_count = compiled_modules.len();
-    let mut files_current_loop_count;
+    let mut compiled_modules = AHashSet::<String>::new();
     let mut compile_errors = "".to_string();
     let mut compile_warnings = "".to_string();
     let mut num_compiled_modules = 0;
-    let mut sorted_modules = build_state.module_names.iter().collect::<Vec<&String>>();
-    sorted_modules.sort();
+    let keep_going = build_state.keep_going;
+    // failed modules (and the modules skipped because of them), with the module that failed
+    let mut failed = AHashMap::<String, String>::new();
+    // modules skipped with --keep-going, with the failed module they depend on
+    let mut skipped = Vec::<(String, String)>::new();
 
     // this is the whole "compile universe" all modules that might be dirty
     // we get this by expanding the dependents from the dirty modules
@@ -141,273 +294,226 @@ pub fn compile(
     let compile_universe_count = compile_universe.len();
     set_length(compile_universe_count as u64);
 
-    // start off with all modules that have no deps in this compile universe
-    let mut in_progress_modules = compile_universe
-        .iter()
-        .filter(|module_name| {
-            let module = build_state.get_module(module_name).unwrap();
-            module.deps.intersection(&compile_universe).count() == 0
-        })
-        .map(|module_name| module_name.to_string())
-        .collect::<AHashSet<String>>();
-
-    loop {
-        files_current_loop_count = 0;
-        loop_count += 1;
-
-        trace!(
-            "Compiled: {} out of {}. Compile loop: {}",
-            files_total_count,
-            compile_universe.len(),
-            loop_count,
-        );
+    // The results are applied to the build state once all modules are done, because the build
+    // state is shared with the workers while compiling.
+    let mut finished: Vec<(ModuleResult, Vec<Diagnostic>, bool)> = vec![];
 
-        let current_in_progres_modules = in_progress_modules.clone();
+    {
+        let build_state: &BuildCommandState = build_state;
 
-        let results = current_in_progres_modules
-            .par_iter()
-            .filter_map(|module_name| {
+        // the number of dependencies of each module that still need to be compiled
+        let mut pending_deps = compile_universe
+            .iter()
+            .map(|module_name| {
                 let module = build_state.get_module(module_name).unwrap();
-                let package = build_state
-                    .get_package(&module.package_name)
-                    .expect("Package not found");
-                // all dependencies that we care about are compiled
-                if module
-                    .deps
-                    .intersection(&compile_universe)
-                    .all(|dep| compiled_modules.contains(dep))
+                (
+                    module_name.to_string(),
+                    module.deps.intersection(&compile_universe).count(),
+                )
+            })
+            .collect::<AHashMap<String, usize>>();
+        let priorities = critical_path_lengths(build_state, &compile_universe, &pending_deps);
+        let priority = |module_name: &str| priorities.get(module_name).copied().unwrap_or(1);
+
+        // start off with all modules that have no deps in this compile universe
+        let mut ready = pending_deps
+            .iter()
+            .filter(|(_, count)| **count == 0)
+            .map(|(module_name, _)| (priority(module_name), Reverse(module_name.to_string())))
+            .collect::<BinaryHeap<(usize, Reverse<String>)>>();
+
+        // We only hand as many modules to rayon as it has threads, so that the modules with the
+        // longest remaining path are always picked first from the ready queue.
+        let max_in_flight = rayon::current_num_threads();
+        let (sender, receiver) = mpsc::channel::<ModuleResult>();
+
+        rayon::in_place_scope(|scope| -> Result<()> {
+            let mut in_flight = 0;
+            loop {
+                // after an error we don't start any new modules, but let the ones in flight finish.
+                // With --keep-going we continue with every module that doesn't depend on a failed one.
+                // The same goes for a cancelled build, the modules that didn't start stay dirty.
+                while in_flight < max_in_flight
+                    && (keep_going || compile_errors.is_empty())
+                    && !build_state.is_cancelled()
                 {
-                    if !module.compile_dirty {
-                        // we are sure we don't have to compile this, so we can mark it as compiled and clean
-                        return Some((module_name.to_string(), Ok(None), Some(Ok(None)), true, false));
+                    let Some((_, Reverse(module_name))) = ready.pop() else {
+                        break;
+                    };
+                    in_flight += 1;
+                    let module = build_state.get_module(&module_name).unwrap();
+                    let skip_reason = if module.has_parse_error() {
+                        Some(SkipReason::ParseError)
+                    } else {
+                        module
+                            .deps
+                            .iter()
+                            .filter_map(|dep| failed.get(dep))
+                            .min()
+                            .map(|failed_module| SkipReason::FailedDependency(failed_module.to_string()))
+                    };
+                    if let Some(reason) = skip_reason {
+                        let _ = sender.send(ModuleResult::skipped(module_name, reason));
+                        continue;
                     }
-                    match module.source_type.to_owned() {
-                        SourceType::MlMap(_) => {
-                            // the mlmap needs to be compiled before the files are compiled
-                            // in the same namespace, otherwise we get a compile error
-                            // this is why mlmap is compiled in the AST generation stage
-                            // compile_mlmap(&module.package, module_name, &project_root);
-                            Some((
-                                package.namespace.to_suffix().unwrap(),
-                                Ok(None),
-                                Some(Ok(None)),
-                                false,
-                                false,
-                            ))
-                        }
-                        SourceType::SourceFile(source_file) => {
-                            let cmi_path = helpers::get_compiler_asset(
-                                package,
-                                &package.namespace,
-                                &source_file.implementation.path,
-                                "cmi",
-                            );
-
-                            let cmi_digest = helpers::compute_file_hash(Path::new(&cmi_path));
-
-                            let package = build_state
-                                .get_package(&module.package_name)
-                                .expect("Package not found");
-
-                            let interface_result = match source_file.interface.to_owned() {
-                                Some(Interface { path, .. }) => {
-                                    let result = compile_file(
-                                        package,
-                                        &helpers::get_ast_path(&path),
-                                        module,
-                                        true,
-                                        build_state,
-                                        build_state.get_warn_error_override(),
-                                    );
-                                    Some(result)
-                                }
-                                _ => None,
-                            };
-                            let result = compile_file(
-                                package,
-                                &helpers::get_ast_path(&source_file.implementation.path),
-                                module,
-                                false,
-                                build_state,
-                                build_state.get_warn_error_override(),
-                            );
-                            let cmi_digest_after = helpers::compute_file_hash(Path::new(&cmi_path));
-
-                            // we want to compare both the hash of interface and the implementation
-                            // compile assets to verify that nothing changed. We also need to checke the interface
-                            // because we can include MyModule, so the modules that depend on this module might
-                            // change when this modules interface does not change, but the implementation does
-                            let is_clean_cmi = match (cmi_digest, cmi_digest_after) {
-                                (Some(cmi_digest), Some(cmi_digest_after)) => {
-                                    cmi_digest.eq(&cmi_digest_after)
-                                }
-
-                                _ => false,
-                            };
-
-                            Some((
-                                module_name.to_string(),
-                                result,
-                                interface_result,
-                                is_clean_cmi,
-                                true,
-                            ))
-                        }
+                    if !dirty_modules.contains(&module_name) {
+                        // we are sure we don't have to compile this, so we can mark it as compiled and clean
+                        let _ = sender.send(ModuleResult::not_compiled(module_name, true));
+                        continue;
                     }
-                } else {
-                    None
+                    let sender = sender.clone();
+                    scope.spawn(move |_| {
+                        let _ = sender.send(compile_module(build_state, module_name));
+                    });
                 }
-                .inspect(|_res| {
-                    if show_progress {
-                        inc();
-                    }
-                })
-            })
-            .collect::<Vec<_>>();
-
-        for result in results.iter() {
-            let (module_name, result, interface_result, is_clean, is_compiled) = result;
-            in_progress_modules.remove(module_name);
-
-            if *is_compiled {
-                num_compiled_modules += 1;
-            }
-
-            files_current_loop_count += 1;
-            compiled_modules.insert(module_name.to_string());
-
-            if *is_clean {
-                // actually add it to a list of clean modules
-                clean_modules.insert(module_name.to_string());
-            }
-
-            let module_dependents = build_state.get_module(module_name).unwrap().dependents.clone();
-
-            // if not clean -- compile modules that depend on this module
-            for dep in module_dependents.iter() {
-                //  mark the reverse dep as dirty when the source is not clean
-                if !*is_clean {
-                    let dep_module = build_state.modules.get_mut(dep).unwrap();
-                    //  mark the reverse dep as dirty when the source is not clean
-                    dep_module.compile_dirty = true;
+                if in_flight == 0 {
+                    break;
                 }
-                if !compiled_modules.contains(dep) {
-                    in_progress_modules.insert(dep.to_string());
+
+                let module_result = receiver.recv().expect("Compile workers stopped unexpectedly");
+                in_flight -= 1;
+                if show_progress {
+                    inc();
                 }
-            }
 
-            let package_name = {
-                let module = build_state
-                    .build_state
-                    .modules
-                    .get(module_name)
-                    .ok_or(anyhow!("Module not found"))?;
-                module.package_name.clone()
-            };
+                let module_name = &module_result.module_name;
+                if module_result.is_compiled {
+                    num_compiled_modules += 1;
+                }
+                compiled_modules.insert(module_name.to_string());
 
-            let package = build_state
-                .build_state
-                .packages
-                .get(&package_name)
-                .ok_or(anyhow!("Package name not found"))?;
+                trace!(
+                    "Compiled: {} out of {}. Compiled: {}",
+                    compiled_modules.len(),
+                    compile_universe_count,
+                    module_name,
+                );
 
-            // Process results and update module state
-            let (compile_warning, compile_error, interface_warning, interface_error) = {
                 let module = build_state
-                    .build_state
-                    .modules
-                    .get_mut(module_name)
+                    .get_module(module_name)
                     .ok_or(anyhow!("Module not found"))?;
 
-                let (compile_warning, compile_error) = match module.source_type {
-                    SourceType::MlMap(ref mut mlmap) => {
-                        module.compile_dirty = false;
-                        mlmap.parse_dirty = false;
-                        (None, None)
+                // if not clean -- compile modules that depend on this module
+                if !module_result.is_clean {
+                    //  mark the reverse deps as dirty when the source is not clean
+                    dirty_modules.extend(module.dependents.iter().cloned());
+                }
+                for dependent in dependents_in(build_state, &compile_universe, module_name) {
+                    let count = pending_deps.get_mut(dependent).unwrap();
+                    *count -= 1;
+                    if *count == 0 {
+                        ready.push((priority(dependent), Reverse(dependent.to_string())));
                     }
-                    SourceType::SourceFile(ref mut source_file) => match result {
-                        Ok(Some(err)) => {
-                            let warning_text = err.to_string();
-                            source_file.implementation.compile_state = CompileState::Warning;
-                            source_file.implementation.compile_warnings = Some(warning_text.clone());
-                            (Some(warning_text), None)
-                        }
-                        Ok(None) => {
-                            source_file.implementation.compile_state = CompileState::Success;
-                            source_file.implementation.compile_warnings = None;
-                            (None, None)
-                        }
-                        Err(err) => {
-                            source_file.implementation.compile_state = CompileState::Error;
-                            source_file.implementation.compile_warnings = None;
-                            (None, Some(err.to_string()))
+                }
+
+                let package = build_state
+                    .get_package(&module.package_name)
+                    .ok_or(anyhow!("Package name not found"))?;
+
+                if let Some(reason) = &module_result.skipped {
+                    // skipped modules stay dirty, so they are compiled once their dependencies are fixed
+                    dirty_modules.insert(module_name.to_string());
+                    match reason {
+                        // the parse errors have been reported already
+                        SkipReason::ParseError => {
+                            failed.insert(module_name.to_string(), module_name.to_string());
                         }
-                    },
-                };
-
-                let (interface_warning, interface_error) =
-                    if let SourceType::SourceFile(ref mut source_file) = module.source_type {
-                        match interface_result {
-                            Some(Ok(Some(err))) => {
-                                let warning_text = err.to_string();
-                                source_file.interface.as_mut().unwrap().compile_state = CompileState::Warning;
-                                source_file.interface.as_mut().unwrap().compile_warnings =
-                                    Some(warning_text.clone());
-                                (Some(warning_text), None)
-                            }
-                            Some(Ok(None)) => {
-                                if let Some(interface) = source_file.interface.as_mut() {
-                                    interface.compile_state = CompileState::Success;
-                                    interface.compile_warnings = None;
-                                }
-                                (None, None)
+                        SkipReason::FailedDependency(failed_module) => {
+                            failed.insert(module_name.to_string(), failed_module.to_string());
+                            skipped.push((module_name.to_string(), failed_module.to_string()));
+                            if json_output {
+                                events::emit(&BuildEvent::ModuleSkipped {
+                                    module: module_name,
+                                    package: &package.name,
+                                    failed_dependency: failed_module,
+                                });
                             }
-                            Some(Err(err)) => {
-                                source_file.interface.as_mut().unwrap().compile_state = CompileState::Error;
-                                source_file.interface.as_mut().unwrap().compile_warnings = None;
-                                (None, Some(err.to_string()))
-                            }
-                            _ => (None, None),
                         }
+                    }
+                    continue;
+                }
+
+                let diagnostics = diagnostics::dedup(
+                    [
+                        to_diagnostics(&module_result.result),
+                        module_result
+                            .interface_result
+                            .as_ref()
+                            .map(to_diagnostics)
+                            .unwrap_or_default(),
+                    ]
+                    .concat(),
+                );
+                let has_error = module_result.result.is_err()
+                    || module_result
+                        .interface_result
+                        .as_ref()
+                        .is_some_and(|r| r.is_err());
+
+                if json_output && module_result.is_compiled {
+                    let status = if has_error {
+                        ModuleStatus::Error
+                    } else if !diagnostics.is_empty() {
+                        ModuleStatus::Warning
                     } else {
-                        (None, None)
+                        ModuleStatus::Success
                     };
-
-                // Update compilation timestamps for successful compilation
-                if result.is_ok() && interface_result.as_ref().is_none_or(|r| r.is_ok()) {
-                    module.compile_dirty = false;
-                    module.last_compiled_cmi = Some(SystemTime::now());
-                    module.last_compiled_cmt = Some(SystemTime::now());
+                    events::emit(&BuildEvent::ModuleCompiled {
+                        module: module_name,
+                        package: &package.name,
+                        status,
+                    });
                 }
 
-                (compile_warning, compile_error, interface_warning, interface_error)
-            };
+                if !diagnostics.is_empty() {
+                    let rendered = diagnostics::render(&diagnostics);
+                    logs::append(package, &rendered);
+                    if json_output {
+                        for diagnostic in diagnostics.iter() {
+                            events::emit(&BuildEvent::Diagnostic {
+                                package: &package.name,
+                                module: module_name,
+                                diagnostic,
+                            });
+                        }
+                    }
+                    if has_error {
+                        compile_errors.push_str(&rendered);
+                    } else {
+                        compile_warnings.push_str(&rendered);
+                    }
+                }
+                if has_error {
+                    failed.insert(module_name.to_string(), module_name.to_string());
+                }
 
-            // Handle logging outside the mutable borrow
-            if let Some(warning) = compile_warning {
-                logs::append(package, &warning);
-                compile_warnings.push_str(&warning);
-            }
-            if let Some(error) = compile_error {
-                logs::append(package, &error);
-                compile_errors.push_str(&error);
-            }
-            if let Some(warning) = interface_warning {
-                logs::append(package, &warning);
-                compile_warnings.push_str(&warning);
+                finished.push((module_result, diagnostics, has_error));
             }
-            if let Some(error) = interface_error {
-                logs::append(package, &error);
-                compile_errors.push_str(&error);
-            }
-        }
+            Ok(())
+        })?;
 
-        files_total_count += files_current_loop_count;
-
-        if files_total_count == compile_universe_count {
-            break;
+        if !skipped.is_empty() {
+            skipped.sort();
+            let message = format!(
+                "\n{}\n{}\n",
+                style(format!(
+                    "Skipped {} module(s) because a module they depend on failed:",
+                    skipped.len()
+                ))
+                .yellow(),
+                skipped
+                    .iter()
+                    .map(|(module_name, failed_module)| format!(
+                        "  {module_name} (depends on {failed_module})"
+                    ))
+                    .collect::<Vec<String>>()
+                    .join("\n")
+            );
+            compile_errors.push_str(&message);
         }
-        if in_progress_modules.is_empty() || in_progress_modules.eq(&current_in_progres_modules) {
+
+        let stopped_early = (!keep_going && !compile_errors.is_empty()) || build_state.is_cancelled();
+        if compiled_modules.len() != compile_universe_count && !stopped_early {
             // find the dependency cycle
             let cycle = dependency_cycle::find(
                 &compile_universe
@@ -436,42 +542,95 @@ pub fn compile(
                 }
             }
 
+            if json_output {
+                events::emit(&BuildEvent::DependencyCycle { modules: &cycle });
+            }
+
             compile_errors.push_str(&message)
         }
-        if !compile_errors.is_empty() {
-            break;
+    }
+
+    // Modules that were marked dirty by one of their dependencies stay dirty until they are
+    // compiled successfully, so the next build picks them up if we stopped early.
+    for module_name in dirty_modules.iter() {
+        if let Some(module) = build_state.modules.get_mut(module_name) {
+            module.compile_dirty = true;
+        }
+    }
+
+    for (module_result, diagnostics, has_error) in finished {
+        let module = build_state
+            .build_state
+            .modules
+            .get_mut(&module_result.module_name)
+            .ok_or(anyhow!("Module not found"))?;
+
+        match module.source_type {
+            SourceType::MlMap(ref mut mlmap) => {
+                module.compile_dirty = false;
+                mlmap.parse_dirty = false;
+            }
+            SourceType::SourceFile(ref mut source_file) => {
+                source_file.implementation.compile_state = to_compile_state(&module_result.result);
+                if let (Some(interface), Some(interface_result)) =
+                    (source_file.interface.as_mut(), &module_result.interface_result)
+                {
+                    interface.compile_state = to_compile_state(interface_result);
+                }
+            }
         };
+        module.compile_diagnostics = diagnostics;
+
+        // Update compilation timestamps for successful compilation
+        if !has_error {
+            module.compile_dirty = false;
+            module.last_compiled_cmi = Some(SystemTime::now());
+            module.last_compiled_cmt = Some(SystemTime::now());
+        }
     }
 
     // Collect warnings from modules that were not recompiled in this build
     // but still have stored warnings from a previous compilation.
     // This ensures warnings are not lost during incremental builds in watch mode.
     for (module_name, module) in build_state.modules.iter() {
-        if compile_universe.contains(module_name) {
+        if compile_universe.contains(module_name) || module.compile_diagnostics.is_empty() {
             continue;
         }
-        if let SourceType::SourceFile(ref source_file) = module.source_type {
-            let package = build_state.get_package(&module.package_name);
-            if let Some(ref warning) = source_file.implementation.compile_warnings {
-                if let Some(package) = package {
-                    logs::append(package, warning);
-                }
-                compile_warnings.push_str(warning);
-            }
-            if let Some(ref interface) = source_file.interface
-                && let Some(ref warning) = interface.compile_warnings
-            {
-                if let Some(package) = package {
-                    logs::append(package, warning);
-                }
-                compile_warnings.push_str(warning);
+        let rendered = diagnostics::render(&module.compile_diagnostics);
+        if let Some(package) = build_state.get_package(&module.package_name) {
+            logs::append(package, &rendered);
+        }
+        if json_output {
+            for diagnostic in module.compile_diagnostics.iter() {
+                events::emit(&BuildEvent::Diagnostic {
+                    package: &module.package_name,
+                    module: module_name,
+                    diagnostic,
+                });
             }
         }
+        compile_warnings.push_str(&rendered);
     }
 
     Ok((compile_errors, compile_warnings, num_compiled_modules))
 }
 
+fn to_compile_state(result: &Result<Option<String>>) -> CompileState {
+    match result {
+        Ok(Some(_)) => CompileState::Warning,
+        Ok(None) => CompileState::Success,
+        Err(_) => CompileState::Error,
+    }
+}
+
+fn to_diagnostics(result: &Result<Option<String>>) -> Vec<Diagnostic> {
+    match result {
+        Ok(Some(warnings)) => diagnostics::parse(warnings, Severity::Warning),
+        Ok(None) => vec![],
+        Err(err) => diagnostics::parse(&err.to_string(), Severity::Error),
+    }
+}
+
 static RUNTIME_PATH_MEMO: OnceLock<PathBuf> = OnceLock::new();
 
 pub fn get_runtime_path(package_config: &Config, project_context: &ProjectContext) -> Result<PathBuf> {
@@ -523,6 +682,11 @@ pub fn compiler_args(
 ) -> Result<Vec<String>> {
     let bsc_flags = config::flatten_flags(&config.compiler_flags);
     let dependency_paths = get_dependency_paths(config, project_context, packages, is_type_dev);
+    let external_include_args = config
+        .get_external_includes()
+        .iter()
+        .flat_map(|dir| vec!["-I".to_string(), dir.to_string_lossy().to_string()])
+        .collect::<Vec<String>>();
     let module_name = helpers::file_path_to_module_name(file_path, &config.get_namespace());
 
     let namespace_args = match &config.get_namespace() {
@@ -613,6 +777,7 @@ pub fn compiler_args(
         ],
         runtime_path_args,
         dependency_paths,
+        external_include_args,
         jsx_args,
         jsx_module_args,
         jsx_mode_args,
@@ -728,7 +893,7 @@ fn compile_file(
     ast_path: &Path,
     module: &Module,
     is_interface: bool,
-    build_state: &BuildState,
+    build_state: &BuildCommandState,
     warn_error_override: Option<String>,
 ) -> Result<Option<String>> {
     let BuildState {
@@ -736,7 +901,7 @@ fn compile_file(
         project_context,
         compiler_info,
         ..
-    } = build_state;
+    } = &build_state.build_state;
     let root_config = build_state.get_root_config();
     let ocaml_build_path_abs = package.get_ocaml_build_path();
     let build_path_abs = package.get_build_path();
@@ -766,16 +931,72 @@ fn compile_file(
         warn_error_override,
     )?;
 
-    let to_mjs = Command::new(&compiler_info.bsc_path)
-        .current_dir(
-            build_path_abs
-                .canonicalize()
-                .map(StrippedVerbatimPath::to_stripped_verbatim_path)
-                .ok()
-                .unwrap(),
-        )
-        .args(to_mjs_args)
-        .output();
+    let module_name = helpers::file_path_to_module_name(implementation_file_path, &package.namespace);
+    let cache_entry = build_state
+        .artifact_cache
+        .as_ref()
+        .filter(|_| package.config.gentype_config.is_none())
+        .and_then(|cache| {
+            let key =
+                artifact_cache::key(build_state, package, module, ast_path, is_interface, &to_mjs_args)?;
+            let outputs = artifact_cache::outputs(
+                package,
+                root_config,
+                implementation_file_path,
+                &basename,
+                is_interface,
+            );
+            Some((cache, key, outputs))
+        });
+    let restored = cache_entry
+        .as_ref()
+        .and_then(|(cache, key, outputs)| cache.restore(key, outputs));
+
+    let to_mjs = match &restored {
+        Some(stderr) => {
+            log::debug!("Restored {module_name} from the artifact cache");
+            Ok(std::process::Output {
+                status: std::process::ExitStatus::default(),
+                stdout: vec![],
+                stderr: stderr.as_bytes().to_vec(),
+            })
+        }
+        None => {
+            let bsc_span = trace::span_with_args(
+                &format!(
+                    "compile {module_name}{}",
+                    if is_interface { " (interface)" } else { "" }
+                ),
+                "bsc",
+                Some(serde_json::json!({ "package": package.name })),
+            );
+            let started = Instant::now();
+            let to_mjs = Command::new(&compiler_info.bsc_path)
+                .current_dir(
+                    build_path_abs
+                        .canonicalize()
+                        .map(StrippedVerbatimPath::to_stripped_verbatim_path)
+                        .ok()
+                        .unwrap(),
+                )
+                .args(&to_mjs_args)
+                .output();
+            timings::record(
+                &build_state.timings,
+                if is_interface {
+                    UnitKind::CompileInterface
+                } else {
+                    UnitKind::Compile
+                },
+                &package.name,
+                &module_name,
+                started,
+                to_mjs.as_ref().is_ok_and(|output| output.status.success()),
+            );
+            drop(bsc_span);
+            to_mjs
+        }
+    };
 
     match to_mjs {
         Ok(x) if !x.status.success() => {
@@ -791,6 +1012,12 @@ fn compile_file(
                 .expect("stdout should be non-null")
                 .to_string();
 
+            if restored.is_none()
+                && let Some((cache, key, outputs)) = &cache_entry
+            {
+                cache.store(key, outputs, &err);
+            }
+
             let dir = Path::new(implementation_file_path).parent().unwrap();
 
             // perhaps we can do this copying somewhere else
@@ -912,23 +1139,7 @@ fn compile_file(
             {
                 // Execute post-build command for each package spec (each output format)
                 for spec in root_config.get_package_specs() {
-                    // Determine the correct JS file path based on in-source setting:
-                    // - in-source: true  -> next to the source file (e.g., src/Foo.js)
-                    // - in-source: false -> in lib/<module>/ directory (e.g., lib/es6/src/Foo.js)
-                    let js_file = if spec.in_source {
-                        helpers::get_source_file_from_rescript_file(
-                            &Path::new(&package.path).join(path),
-                            &root_config.get_suffix(&spec),
-                        )
-                    } else {
-                        helpers::get_source_file_from_rescript_file(
-                            &Path::new(&package.path)
-                                .join("lib")
-                                .join(spec.get_out_of_source_dir())
-                                .join(path),
-                            &root_config.get_suffix(&spec),
-                        )
-                    };
+                    let js_file = helpers::get_js_output_path(package, root_config, &spec, path);
 
                     if js_file.exists() {
                         // Fail the build if post-build command fails (matches bsb behavior with &&)
@@ -1000,20 +1211,15 @@ pub fn mark_modules_with_expired_deps_dirty(build_state: &mut BuildCommandState)
                         // compile of the interface of the module it depends on, if the interface
                         // didn't change it doesn't matter
                         match (dependent_module.last_compiled_cmt, module.last_compiled_cmt) {
-                            (Some(last_compiled_dependent), Some(last_compiled)) => {
-                                if last_compiled_dependent < last_compiled {
-                                    // println!(
-                                    //     "✅ {} is a dependent of {} ({:?} / {:?})",
-                                    //     module_name, dependent, last_compiled_dependent, last_compiled
-                                    // );
-
-                                    modules_with_expired_deps.insert(dependent.to_string());
-                                } else {
-                                    // println!(
-                                    //     "🛑 {} is a dependent of {} ({:?} / {:?})",
-                                    //     module_name, dependent, last_compiled_dependent, last_compiled
-                                    // );
-                                }
+                            (Some(last_compiled_dependent), Some(last_compiled))
+                                if last_compiled_dependent < last_compiled =>
+                            {
+                                // println!(
+                                //     "✅ {} is a dependent of {} ({:?} / {:?})",
+                                //     module_name, dependent, last_compiled_dependent, last_compiled
+                                // );
+
+                                modules_with_expired_deps.insert(dependent.to_string());
                             }
                             (None, _) => {
                                 // println!(
@@ -3,6 +3,7 @@ use crate::helpers;
 use super::build_types::{BuildCommandState, CompilerInfo};
 use super::packages;
 use super::{clean, logs};
+use crate::trace;
 use ahash::AHashMap;
 use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
@@ -20,6 +21,9 @@ struct CompilerInfoFile {
     rescript_config_hash: String,
     runtime_path: String,
     generated_at: String,
+    // only written for packages with bs-external-includes
+    #[serde(default)]
+    external_includes_hash: Option<String>,
 }
 
 pub enum CompilerCheckResult {
@@ -97,6 +101,18 @@ pub fn verify_compiler_info(
                 mismatch = true;
             }
 
+            let current_external_includes_hash =
+                package.external_includes_hash.map(|hash| hash.to_hex().to_string());
+            if parsed.external_includes_hash != current_external_includes_hash {
+                log::debug!(
+                    "compiler-info mismatch for {}: external_includes_hash changed (stored='{:?}', current='{:?}')",
+                    package.name,
+                    parsed.external_includes_hash,
+                    current_external_includes_hash
+                );
+                mismatch = true;
+            }
+
             mismatch
         })
         .collect::<Vec<_>>();
@@ -114,6 +130,7 @@ pub fn verify_compiler_info(
 }
 
 pub fn write_compiler_info(build_state: &BuildCommandState) {
+    let _span = trace::span("write_compiler_info");
     let bsc_path = build_state.compiler_info.bsc_path.to_string_lossy().to_string();
     let bsc_hash = build_state.compiler_info.bsc_hash.to_hex().to_string();
     let runtime_path = build_state
@@ -134,6 +151,8 @@ pub fn write_compiler_info(build_state: &BuildCommandState) {
         rescript_config_hash: String,
         runtime_path: &'a str,
         generated_at: &'a str,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        external_includes_hash: Option<String>,
     }
 
     build_state.packages.values().par_bridge().for_each(|package| {
@@ -145,6 +164,7 @@ pub fn write_compiler_info(build_state: &BuildCommandState) {
                 rescript_config_hash: rescript_config_hash.to_hex().to_string(),
                 runtime_path: &runtime_path,
                 generated_at: &generated_at,
+                external_includes_hash: package.external_includes_hash.map(|hash| hash.to_hex().to_string()),
             };
             let contents = match serde_json::to_string_pretty(&out) {
                 Ok(s) => s,
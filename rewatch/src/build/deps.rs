@@ -1,6 +1,7 @@
 use super::build_types::*;
 use super::packages;
 use crate::helpers;
+use crate::trace;
 use ahash::AHashSet;
 use rayon::prelude::*;
 
@@ -97,6 +98,7 @@ fn get_dep_modules(
 }
 
 pub fn get_deps(build_state: &mut BuildState, deleted_modules: &AHashSet<String>) {
+    let _span = trace::span("deps::get_deps");
     let all_mod = &build_state.module_names.union(deleted_modules).cloned().collect();
     build_state
         .modules
@@ -108,7 +110,9 @@ pub fn get_deps(build_state: &mut BuildState, deleted_modules: &AHashSet<String>
                     .get_package(&module.package_name)
                     .expect("Package not found");
                 let ast_path = helpers::get_ast_path(&source_file.implementation.path);
-                if module.deps_dirty || !build_state.deps_initialized {
+                // With --keep-going we get here with files that could not be parsed. Their AST
+                // might not exist, so we keep the deps until they parse again.
+                if (module.deps_dirty || !build_state.deps_initialized) && !module.has_parse_error() {
                     let mut deps = get_dep_modules(
                         &ast_path.to_string_lossy(),
                         package.namespace.to_suffix(),
@@ -0,0 +1,306 @@
+use super::logs;
+use crate::helpers;
+use ahash::AHashSet;
+use regex::Regex;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// A source range as printed by bsc: lines and columns are 1-based and the end column is inclusive.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Range {
+    pub start_line: u32,
+    pub start_column: u32,
+    pub end_line: u32,
+    pub end_column: u32,
+}
+
+/// A single error or warning reported by bsc, parsed from its (human readable) stderr output.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    /// `None` when bsc did not report a location, or when the output could not be parsed.
+    pub file: Option<PathBuf>,
+    pub range: Option<Range>,
+    pub severity: Severity,
+    pub warning_number: Option<u32>,
+    /// The message without colours, code frame and location.
+    pub message: String,
+    /// The original output of bsc for this diagnostic (including colours and the code frame). This
+    /// is what we print to the terminal and write to `.compiler.log`, so that editor tooling keeps
+    /// getting the exact same format.
+    #[serde(skip)]
+    pub rendered: String,
+}
+
+impl Diagnostic {
+    fn key(&self) -> (Option<&Path>, Option<Range>, Severity, Option<u32>, &str) {
+        (
+            self.file.as_deref(),
+            self.range,
+            self.severity,
+            self.warning_number,
+            &self.message,
+        )
+    }
+}
+
+fn location_regex() -> &'static Regex {
+    static LOCATION: OnceLock<Regex> = OnceLock::new();
+    LOCATION.get_or_init(|| {
+        Regex::new(r"^(?P<file>.+?)(?::(?P<line>\d+):(?P<col>\d+)(?:-(?P<end>\d+)(?::(?P<end_col>\d+))?)?)?$")
+            .expect("Could not create regex")
+    })
+}
+
+fn code_frame_regex() -> &'static Regex {
+    static CODE_FRAME: OnceLock<Regex> = OnceLock::new();
+    CODE_FRAME.get_or_init(|| Regex::new(r"^\s*(?:\d+|\.)?\s*[│┆]").expect("Could not create regex"))
+}
+
+/// Returns the severity and warning number when the line starts a new diagnostic.
+fn parse_header(line: &str) -> Option<(Severity, Option<u32>)> {
+    let line = line.trim();
+    if let Some(rest) = line.strip_prefix("Warning number ") {
+        let number = rest
+            .chars()
+            .take_while(|c| c.is_ascii_digit())
+            .collect::<String>()
+            .parse()
+            .ok();
+        let severity = if rest.contains("(configured as error)") {
+            Severity::Error
+        } else {
+            Severity::Warning
+        };
+        Some((severity, number))
+    } else if line.starts_with("We've found a bug for you!") || line.starts_with("Syntax error!") {
+        Some((Severity::Error, None))
+    } else {
+        None
+    }
+}
+
+fn parse_location(line: &str) -> (Option<PathBuf>, Option<Range>) {
+    let Some(captures) = location_regex().captures(line.trim()) else {
+        return (None, None);
+    };
+    let file = match &captures["file"] {
+        "_none_" => None,
+        file => Some(PathBuf::from(file)),
+    };
+    let number = |name: &str| captures.name(name).and_then(|m| m.as_str().parse::<u32>().ok());
+    let range = match (number("line"), number("col")) {
+        (Some(start_line), Some(start_column)) => {
+            let (end_line, end_column) = match (number("end"), number("end_col")) {
+                (Some(end_line), Some(end_column)) => (end_line, end_column),
+                (Some(end_column), None) => (start_line, end_column),
+                _ => (start_line, start_column),
+            };
+            Some(Range {
+                start_line,
+                start_column,
+                end_line,
+                end_column,
+            })
+        }
+        _ => None,
+    };
+    (file, range)
+}
+
+/// Parses the lines of a single diagnostic, `header` is the index of the line that starts it.
+fn parse_block(lines: &[&str], header: usize, severity: Severity, warning_number: Option<u32>) -> Diagnostic {
+    let stripped = lines
+        .iter()
+        .map(|line| logs::escape_colours(line.trim_end()))
+        .collect::<Vec<String>>();
+
+    // The location is the first non-empty line after the header
+    let location_index = stripped
+        .iter()
+        .skip(header + 1)
+        .position(|line| !line.trim().is_empty())
+        .map(|index| index + header + 1);
+    let (file, range) = location_index
+        .map(|index| parse_location(&stripped[index]))
+        .unwrap_or((None, None));
+
+    let message = stripped
+        .iter()
+        .skip(location_index.unwrap_or(header) + 1)
+        .filter(|line| !code_frame_regex().is_match(line))
+        .map(|line| line.strip_prefix("  ").unwrap_or(line))
+        .collect::<Vec<&str>>()
+        .join("\n")
+        .trim()
+        .to_string();
+
+    Diagnostic {
+        file,
+        range,
+        severity,
+        warning_number,
+        message,
+        rendered: lines.concat(),
+    }
+}
+
+/// Parses the output of a bsc invocation into diagnostics. Output that doesn't look like a bsc
+/// diagnostic (for instance when bsc could not be started) is kept as a single diagnostic without
+/// a location, using `fallback_severity`.
+///
+/// Rendering the returned diagnostics (see [`render`]) gives back the original output.
+pub fn parse(output: &str, fallback_severity: Severity) -> Vec<Diagnostic> {
+    if !helpers::contains_ascii_characters(output) {
+        return vec![];
+    }
+
+    let lines = output.split_inclusive('\n').collect::<Vec<&str>>();
+    let headers = lines
+        .iter()
+        .enumerate()
+        .filter_map(|(index, line)| {
+            parse_header(&logs::escape_colours(line)).map(|(severity, number)| (index, severity, number))
+        })
+        .collect::<Vec<_>>();
+
+    if headers.is_empty() {
+        return vec![Diagnostic {
+            file: None,
+            range: None,
+            severity: fallback_severity,
+            warning_number: None,
+            message: logs::escape_colours(output).trim().to_string(),
+            rendered: output.to_string(),
+        }];
+    }
+
+    // bsc starts every diagnostic with a blank line, which belongs to the diagnostic that follows
+    // it. Anything printed before the first diagnostic is kept with the first one.
+    let starts = headers
+        .iter()
+        .enumerate()
+        .map(|(i, (header, _, _))| match i {
+            0 => 0,
+            _ if header - 1 > headers[i - 1].0 && !helpers::contains_ascii_characters(lines[header - 1]) => {
+                header - 1
+            }
+            _ => *header,
+        })
+        .collect::<Vec<usize>>();
+
+    headers
+        .iter()
+        .enumerate()
+        .map(|(i, (header, severity, number))| {
+            let start = starts[i];
+            let end = starts.get(i + 1).copied().unwrap_or(lines.len());
+            parse_block(&lines[start..end], header - start, *severity, *number)
+        })
+        .collect()
+}
+
+/// Removes diagnostics that were already reported, keeping the first occurrence. This happens when
+/// the same warning is reported for both the implementation and the interface, or both while
+/// parsing and compiling.
+pub fn dedup(diagnostics: Vec<Diagnostic>) -> Vec<Diagnostic> {
+    let mut seen = AHashSet::new();
+    let keep = diagnostics
+        .iter()
+        .map(|diagnostic| seen.insert(diagnostic.key()))
+        .collect::<Vec<bool>>();
+    diagnostics
+        .into_iter()
+        .zip(keep)
+        .filter_map(|(diagnostic, keep)| keep.then_some(diagnostic))
+        .collect()
+}
+
+pub fn render(diagnostics: &[Diagnostic]) -> String {
+    diagnostics
+        .iter()
+        .map(|diagnostic| diagnostic.rendered.as_str())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WARNING: &str = "\n  \u{1b}[1;33mWarning number 32\u{1b}[0m\n  \u{1b}[36m/packages/main/src/ModuleA.res\u{1b}[0m:\u{1b}[2m1:5-15\u{1b}[0m\n\n  \u{1b}[1;33m1\u{1b}[0m \u{1b}[2m│\u{1b}[0m let \u{1b}[1;33munusedValue\u{1b}[0m = 42\n  2 \u{1b}[2m│\u{1b}[0m \n\n  unused value unusedValue.\n\n";
+
+    const ERROR: &str = "\n  \u{1b}[1;31mWe've found a bug for you!\u{1b}[0m\n  \u{1b}[36m/packages/main/src/Main.res\u{1b}[0m:\u{1b}[2m3:27-4:2\u{1b}[0m\n\n  3 \u{1b}[2m│\u{1b}[0m let x = 1 + \"a\"\n\n  This has type: string\n  But it's expected to have type: int\n\n";
+
+    #[test]
+    fn parses_warnings_and_errors() {
+        let output = format!("{WARNING}{ERROR}");
+        let diagnostics = parse(&output, Severity::Error);
+
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!(
+            diagnostics[0].file,
+            Some(PathBuf::from("/packages/main/src/ModuleA.res"))
+        );
+        assert_eq!(
+            diagnostics[0].range,
+            Some(Range {
+                start_line: 1,
+                start_column: 5,
+                end_line: 1,
+                end_column: 15
+            })
+        );
+        assert_eq!(diagnostics[0].severity, Severity::Warning);
+        assert_eq!(diagnostics[0].warning_number, Some(32));
+        assert_eq!(diagnostics[0].message, "unused value unusedValue.");
+
+        assert_eq!(diagnostics[1].severity, Severity::Error);
+        assert_eq!(diagnostics[1].warning_number, None);
+        assert_eq!(
+            diagnostics[1]
+                .range
+                .map(|range| (range.end_line, range.end_column)),
+            Some((4, 2))
+        );
+        assert_eq!(
+            diagnostics[1].message,
+            "This has type: string\nBut it's expected to have type: int"
+        );
+
+        assert_eq!(render(&diagnostics), output);
+    }
+
+    #[test]
+    fn warnings_configured_as_error_are_errors() {
+        let output = WARNING.replace("Warning number 32", "Warning number 32 (configured as error) ");
+        let diagnostics = parse(&output, Severity::Warning);
+        assert_eq!(diagnostics[0].severity, Severity::Error);
+        assert_eq!(diagnostics[0].warning_number, Some(32));
+    }
+
+    #[test]
+    fn unrecognized_output_is_kept_as_is() {
+        let diagnostics = parse("Could not compile file.\n", Severity::Error);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].file, None);
+        assert_eq!(diagnostics[0].message, "Could not compile file.");
+        assert_eq!(diagnostics[0].rendered, "Could not compile file.\n");
+
+        assert!(parse("\n\n", Severity::Error).is_empty());
+    }
+
+    #[test]
+    fn dedup_keeps_first_occurrence() {
+        let output = format!("{WARNING}{ERROR}{WARNING}");
+        let diagnostics = dedup(parse(&output, Severity::Error));
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!(render(&diagnostics), format!("{WARNING}{ERROR}"));
+    }
+}
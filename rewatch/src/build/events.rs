@@ -0,0 +1,151 @@
+use super::diagnostics::Diagnostic;
+use super::logs;
+use serde::Serialize;
+use std::io::Write;
+
+/// A single machine-readable build event, emitted as one line of JSON (NDJSON) on stdout when
+/// `build` or `watch` run with `--json`. Consumers should dispatch on the `event` field and ignore
+/// fields (or events) they don't know about, so new ones can be added without breaking them.
+#[derive(Serialize, Debug, Clone)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum BuildEvent<'a> {
+    /// Stale compiler assets were removed while initializing the build.
+    Cleaned {
+        cleaned: usize,
+        total: usize,
+        /// The previous build was produced by a different compiler and was cleaned completely.
+        compiler_update: bool,
+        duration_seconds: f64,
+    },
+    ParseStarted {
+        dirty_modules: u64,
+    },
+    ParseFinished {
+        success: bool,
+        parsed_modules: u64,
+        duration_seconds: f64,
+    },
+    ModuleCompiled {
+        module: &'a str,
+        package: &'a str,
+        status: ModuleStatus,
+    },
+    /// With `--keep-going`, a module that was not compiled because a module it (transitively)
+    /// depends on failed to parse or compile.
+    ModuleSkipped {
+        module: &'a str,
+        package: &'a str,
+        failed_dependency: &'a str,
+    },
+    /// A warning from the build system itself, like an unsupported field in `rescript.json`.
+    Warning {
+        package: Option<&'a str>,
+        message: String,
+    },
+    /// An error or warning reported by bsc while parsing or compiling a module.
+    Diagnostic {
+        package: &'a str,
+        module: &'a str,
+        #[serde(flatten)]
+        diagnostic: &'a Diagnostic,
+    },
+    DependencyCycle {
+        modules: &'a [String],
+    },
+    /// In watch mode, the build was stopped because files changed (and a new build follows) or the
+    /// watcher was stopped. This build has no `finished` event.
+    Cancelled,
+    /// Summary of a single (initial or incremental) build.
+    Finished {
+        success: bool,
+        compiled_modules: usize,
+        duration_seconds: f64,
+    },
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ModuleStatus {
+    Success,
+    Warning,
+    Error,
+}
+
+impl<'a> BuildEvent<'a> {
+    pub fn warning(package: Option<&'a str>, message: &str) -> Self {
+        BuildEvent::Warning {
+            package,
+            message: logs::escape_colours(message),
+        }
+    }
+}
+
+/// Writes the event as a single line to stdout. The stdout lock is held for the whole line, so
+/// events emitted from different threads never interleave.
+pub fn emit(event: &BuildEvent) {
+    match serde_json::to_string(event) {
+        Ok(line) => {
+            let mut stdout = std::io::stdout().lock();
+            let _ = writeln!(stdout, "{line}");
+            let _ = stdout.flush();
+        }
+        Err(err) => log::error!("Could not serialize build event {event:?}: {err}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::build::diagnostics::Severity;
+
+    #[test]
+    fn serializes_with_event_tag() {
+        let event = BuildEvent::ModuleCompiled {
+            module: "Foo",
+            package: "@testrepo/main",
+            status: ModuleStatus::Warning,
+        };
+        assert_eq!(
+            serde_json::to_string(&event).unwrap(),
+            r#"{"event":"module_compiled","module":"Foo","package":"@testrepo/main","status":"warning"}"#
+        );
+    }
+
+    #[test]
+    fn serializes_events_without_fields() {
+        assert_eq!(
+            serde_json::to_string(&BuildEvent::Cancelled).unwrap(),
+            r#"{"event":"cancelled"}"#
+        );
+    }
+
+    #[test]
+    fn flattens_diagnostics() {
+        let diagnostic = Diagnostic {
+            file: Some("src/Foo.res".into()),
+            range: None,
+            severity: Severity::Warning,
+            warning_number: Some(32),
+            message: "unused value x.".to_string(),
+            rendered: "\n  Warning number 32\n".to_string(),
+        };
+        let event = BuildEvent::Diagnostic {
+            package: "@testrepo/main",
+            module: "Foo",
+            diagnostic: &diagnostic,
+        };
+        assert_eq!(
+            serde_json::to_string(&event).unwrap(),
+            r#"{"event":"diagnostic","package":"@testrepo/main","module":"Foo","file":"src/Foo.res","range":null,"severity":"warning","warning_number":32,"message":"unused value x."}"#
+        );
+    }
+
+    #[test]
+    fn strips_colours_from_messages() {
+        let event = BuildEvent::warning(None, "\u{1b}[33mUnknown field 'foo'\u{1b}[0m");
+        match event {
+            BuildEvent::Warning { message, .. } => assert_eq!(message, "Unknown field 'foo'"),
+            other => panic!("expected warning event, got {other:?}"),
+        }
+    }
+}
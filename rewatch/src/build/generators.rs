@@ -0,0 +1,314 @@
+use super::build_types::BuildState;
+use super::packages::Package;
+use super::source_files;
+use crate::config;
+use ahash::AHashSet;
+use anyhow::{Result, anyhow};
+use rayon::prelude::*;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::SystemTime;
+
+/// An edge of a generator in a source folder: the command of its rule, with its inputs and
+/// outputs as absolute paths.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Generator {
+    pub name: String,
+    pub command: String,
+    pub inputs: Vec<PathBuf>,
+    pub outputs: Vec<PathBuf>,
+}
+
+fn split_edge(edge: &[String]) -> Option<(&[String], &[String])> {
+    let separator = edge.iter().position(|part| part == ":")?;
+    let (outputs, inputs) = (&edge[..separator], &edge[separator + 1..]);
+    (!outputs.is_empty() && !inputs.is_empty()).then_some((outputs, inputs))
+}
+
+fn get_rule<'a>(package: &'a Package, name: &str) -> Option<&'a config::RuleGenerator> {
+    package
+        .config
+        .generators
+        .iter()
+        .flatten()
+        .find(|rule| rule.name == name)
+}
+
+/// The generators of the source folders of a package. Generators without a rule or with an invalid
+/// edge are left out, they are reported by `get_config_warnings`.
+pub fn get_generators(package: &Package) -> Vec<Generator> {
+    package
+        .source_folders
+        .iter()
+        .flat_map(|source| {
+            let dir = package.path.join(&source.dir);
+            source.generators.iter().flatten().filter_map(move |generator| {
+                let rule = get_rule(package, &generator.name)?;
+                let (outputs, inputs) = split_edge(&generator.edge)?;
+                Some(Generator {
+                    name: generator.name.to_owned(),
+                    command: rule.command.to_owned(),
+                    inputs: inputs.iter().map(|input| dir.join(input)).collect(),
+                    outputs: outputs.iter().map(|output| dir.join(output)).collect(),
+                })
+            })
+        })
+        .collect()
+}
+
+pub fn get_config_warnings(package: &Package) -> Vec<String> {
+    package
+        .source_folders
+        .iter()
+        .flat_map(|source| {
+            source.generators.iter().flatten().filter_map(|generator| {
+                if get_rule(package, &generator.name).is_none() {
+                    Some(format!(
+                        "The generator '{}' of the source folder '{}' of '{}' is not defined in 'generators'.",
+                        generator.name, source.dir, package.name
+                    ))
+                } else if split_edge(&generator.edge).is_none() {
+                    Some(format!(
+                        "The edge of the generator '{}' of the source folder '{}' of '{}' should list the outputs, a ':' and the inputs.",
+                        generator.name, source.dir, package.name
+                    ))
+                } else {
+                    None
+                }
+            })
+        })
+        .collect()
+}
+
+/// The inputs and the outputs of the generators of the local packages.
+pub fn get_edges(build_state: &BuildState) -> (AHashSet<PathBuf>, AHashSet<PathBuf>) {
+    let generators = build_state
+        .packages
+        .values()
+        .filter(|package| package.is_local_dep)
+        .flat_map(get_generators)
+        .collect::<Vec<Generator>>();
+    let inputs = generators
+        .iter()
+        .flat_map(|generator| generator.inputs.iter().cloned())
+        .collect();
+    let outputs = generators
+        .into_iter()
+        .flat_map(|generator| generator.outputs.into_iter())
+        .collect();
+    (inputs, outputs)
+}
+
+fn get_modified(path: &Path) -> Option<SystemTime> {
+    path.metadata().and_then(|metadata| metadata.modified()).ok()
+}
+
+/// A generator runs when one of its outputs is missing, or older than one of its inputs.
+fn is_outdated(generator: &Generator) -> Result<bool> {
+    let mut last_input: Option<SystemTime> = None;
+    for input in generator.inputs.iter() {
+        let modified = get_modified(input).ok_or_else(|| {
+            anyhow!(
+                "The input {} of the generator '{}' does not exist",
+                input.display(),
+                generator.name
+            )
+        })?;
+        last_input = last_input.max(Some(modified));
+    }
+    Ok(generator
+        .outputs
+        .iter()
+        .any(|output| get_modified(output).is_none_or(|modified| Some(modified) < last_input)))
+}
+
+fn relative_paths(paths: &[PathBuf], dir: &Path) -> String {
+    paths
+        .iter()
+        .map(|path| {
+            path.strip_prefix(dir)
+                .unwrap_or(path)
+                .to_string_lossy()
+                .to_string()
+        })
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+/// Runs the command of a generator in the folder of its package, with `$in` and `$out` replaced by
+/// the inputs and outputs relative to that folder.
+fn execute(generator: &Generator, package_dir: &Path) -> Result<()> {
+    let command = generator
+        .command
+        .replace("$in", &relative_paths(&generator.inputs, package_dir))
+        .replace("$out", &relative_paths(&generator.outputs, package_dir));
+    log::debug!("Running generator: {} (in {})", command, package_dir.display());
+
+    let output = if cfg!(target_os = "windows") {
+        Command::new("cmd")
+            .args(["/C", &command])
+            .current_dir(package_dir)
+            .output()
+    } else {
+        Command::new("sh")
+            .args(["-c", &command])
+            .current_dir(package_dir)
+            .output()
+    }
+    .map_err(|e| anyhow!("Failed to execute the generator '{}': {}", generator.name, e))?;
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    if !stdout.is_empty() {
+        log::info!("{}", stdout.trim());
+    }
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(anyhow!(
+            "The generator '{}' failed for {}\n{}",
+            generator.name,
+            relative_paths(&generator.inputs, package_dir),
+            stderr.trim()
+        ));
+    }
+    if let Some(missing) = generator.outputs.iter().find(|output| !output.exists()) {
+        return Err(anyhow!(
+            "The generator '{}' did not create {}",
+            generator.name,
+            missing.display()
+        ));
+    }
+    Ok(())
+}
+
+/// Runs the generators of the local packages that are outdated, and adds their outputs to the
+/// build so they are parsed again. Packages with `cut-generators` keep the files that were
+/// generated before. Returns the files that were generated.
+pub fn run(build_state: &mut BuildState) -> Result<Vec<PathBuf>> {
+    let outdated = build_state
+        .packages
+        .values()
+        .filter(|package| package.is_local_dep && package.config.cut_generators != Some(true))
+        .flat_map(|package| {
+            get_generators(package)
+                .into_iter()
+                .map(|generator| (package.path.to_owned(), generator))
+        })
+        .map(|(package_dir, generator)| Ok(is_outdated(&generator)?.then_some((package_dir, generator))))
+        .collect::<Result<Vec<_>>>()?
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>();
+    if outdated.is_empty() {
+        return Ok(vec![]);
+    }
+
+    outdated
+        .par_iter()
+        .map(|(package_dir, generator)| execute(generator, package_dir))
+        .collect::<Result<Vec<()>>>()?;
+
+    let generated = outdated
+        .into_iter()
+        .flat_map(|(_, generator)| generator.outputs.into_iter())
+        .collect::<Vec<PathBuf>>();
+    match source_files::update(build_state, &generated, &None) {
+        source_files::Update::NeedsFullBuild => Err(anyhow!(
+            "A generated file has the same module name as another file of its package"
+        )),
+        source_files::Update::Unchanged | source_files::Update::Updated => Ok(generated),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::build::build_types::tests::build_state;
+    use std::fs;
+    use std::path::PathBuf;
+    use tempfile::TempDir;
+
+    fn project(config: &str) -> (TempDir, PathBuf) {
+        crate::build::build_types::tests::project(
+            config,
+            &[
+                ("src/Main.res", "let x = Query.x"),
+                ("src/Query.txt", "let x = 1"),
+            ],
+        )
+    }
+
+    const CONFIG: &str = r#"{
+        "name": "test",
+        "sources": {
+            "dir": "src",
+            "generators": [{"name": "copy", "edge": ["Query.res", ":", "Query.txt"]}]
+        },
+        "generators": [{"name": "copy", "command": "cp $in $out"}]
+    }"#;
+
+    #[test]
+    fn reads_the_generators_of_the_sources() {
+        let (_temp_dir, root) = project(CONFIG);
+        let build_state = build_state(&root);
+
+        assert_eq!(
+            get_generators(&build_state.packages["test"]),
+            vec![Generator {
+                name: "copy".to_string(),
+                command: "cp $in $out".to_string(),
+                inputs: vec![root.join("src").join("Query.txt")],
+                outputs: vec![root.join("src").join("Query.res")],
+            }]
+        );
+        assert!(get_config_warnings(&build_state.packages["test"]).is_empty());
+    }
+
+    #[test]
+    fn warns_about_invalid_generators() {
+        let (_temp_dir, root) = project(
+            r#"{
+                "name": "test",
+                "sources": {
+                    "dir": "src",
+                    "generators": [
+                        {"name": "missing", "edge": ["A.res", ":", "A.txt"]},
+                        {"name": "copy", "edge": ["A.res", "A.txt"]}
+                    ]
+                },
+                "generators": [{"name": "copy", "command": "cp $in $out"}]
+            }"#,
+        );
+        let build_state = build_state(&root);
+
+        assert!(get_generators(&build_state.packages["test"]).is_empty());
+        assert_eq!(get_config_warnings(&build_state.packages["test"]).len(), 2);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn runs_outdated_generators() {
+        let (_temp_dir, root) = project(CONFIG);
+        let mut build_state = build_state(&root);
+
+        let generated = run(&mut build_state).expect("generators should run");
+        assert_eq!(generated, vec![root.join("src").join("Query.res")]);
+        assert_eq!(
+            fs::read_to_string(root.join("src").join("Query.res")).unwrap(),
+            "let x = 1"
+        );
+        assert!(build_state.get_module("Query").is_some());
+
+        // the output is up to date now
+        assert!(run(&mut build_state).expect("generators should run").is_empty());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn keeps_the_outputs_with_cut_generators() {
+        let (_temp_dir, root) = project(&CONFIG.replacen("{", r#"{"cut-generators": true,"#, 1));
+        let mut build_state = build_state(&root);
+
+        assert!(run(&mut build_state).expect("generators should run").is_empty());
+        assert!(!root.join("src").join("Query.res").exists());
+    }
+}
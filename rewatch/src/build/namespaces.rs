@@ -1,7 +1,9 @@
 use crate::build::compile::get_runtime_path_args;
 use crate::build::packages;
+use crate::build::timings::{self, Timings, UnitKind};
 use crate::helpers::StrippedVerbatimPath;
 use crate::project_context::ProjectContext;
+use crate::trace;
 use ahash::AHashSet;
 use anyhow::{Result, anyhow};
 use std::fs::File;
@@ -9,6 +11,7 @@ use std::io::Write;
 use std::path::Path;
 use std::path::PathBuf;
 use std::process::Command;
+use std::time::Instant;
 // Namespaces work like the following: The build system will generate a file
 // called `MyModule.mlmap` which contains all modules that are in the namespace
 //
@@ -58,6 +61,7 @@ pub fn compile_mlmap(
     package: &packages::Package,
     namespace: &str,
     bsc_path: &Path,
+    timings: &Option<Timings>,
 ) -> Result<()> {
     let build_path_abs = package.get_build_path();
     let mlmap_name = format!("{namespace}.mlmap");
@@ -74,6 +78,12 @@ pub fn compile_mlmap(
     ]);
     args.push(mlmap_name.clone());
 
+    let bsc_span = trace::span_with_args(
+        &format!("mlmap {namespace}"),
+        "bsc",
+        Some(serde_json::json!({ "package": package.name })),
+    );
+    let started = Instant::now();
     let output = Command::new(bsc_path)
         .current_dir(
             build_path_abs
@@ -84,6 +94,15 @@ pub fn compile_mlmap(
         )
         .args(&args)
         .output()?;
+    timings::record(
+        timings,
+        UnitKind::Mlmap,
+        &package.name,
+        namespace,
+        started,
+        output.status.success(),
+    );
+    drop(bsc_span);
 
     if !output.status.success() {
         let stderr = String::from_utf8_lossy(&output.stderr).to_string();
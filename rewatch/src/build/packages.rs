@@ -7,6 +7,7 @@ use crate::helpers;
 use crate::helpers::StrippedVerbatimPath;
 use crate::helpers::emojis::*;
 use crate::project_context::{MonoRepoContext, ProjectContext};
+use crate::trace;
 use ahash::{AHashMap, AHashSet};
 use anyhow::{Result, anyhow};
 use console::style;
@@ -63,8 +64,12 @@ pub struct Package {
     // canonicalized dir of the package
     pub path: PathBuf,
     pub dirs: Option<AHashSet<PathBuf>>,
+    // the (absolute) directories that were read to find the source files, with their modification time
+    pub read_dirs: Option<AHashMap<PathBuf, SystemTime>>,
     pub is_local_dep: bool,
     pub is_root: bool,
+    // the hash of the files in the folders of bs-external-includes, see `get_external_includes_hash`
+    pub external_includes_hash: Option<blake3::Hash>,
 }
 
 pub fn get_build_path(canonical_path: &Path) -> PathBuf {
@@ -96,6 +101,10 @@ impl Package {
         self.get_build_path().join("compiler-info.json")
     }
 
+    pub fn get_source_hashes_path(&self) -> PathBuf {
+        self.get_build_path().join("source-hashes.json")
+    }
+
     pub fn get_js_path(&self) -> PathBuf {
         get_js_path(&self.path)
     }
@@ -140,23 +149,30 @@ impl Hash for Package {
     }
 }
 
-fn matches_filter(filter: &Option<regex::Regex>, path: &str) -> bool {
+pub fn matches_filter(filter: &Option<regex::Regex>, path: &str) -> bool {
     match filter {
         Some(filter) => filter.is_match(path),
         None => true,
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn read_folders(
     filter: &Option<regex::Regex>,
+    ignored_dirs: &config::IgnoredDirs,
+    file_filter: &config::FileFilter,
     package_dir: &Path,
     path: &Path,
     recurse: bool,
     is_type_dev: bool,
+    read_dirs: &mut AHashMap<PathBuf, SystemTime>,
 ) -> Result<AHashMap<PathBuf, SourceFileMeta>, Box<dyn error::Error>> {
     let mut map: AHashMap<PathBuf, SourceFileMeta> = AHashMap::new();
     let path_buf = PathBuf::from(path);
     let meta = fs::metadata(package_dir.join(path));
+    if let Ok(modified) = meta.as_ref().map(|meta| meta.modified().unwrap()) {
+        read_dirs.insert(package_dir.join(path), modified);
+    }
     let path_with_meta = meta.map(|meta| {
         (
             path.to_owned(),
@@ -175,15 +191,28 @@ pub fn read_folders(
         let path_ext = entry_path_buf.extension().and_then(|x| x.to_str());
         let new_path = path_buf.join(&name);
         if metadata.file_type().is_dir() && recurse {
-            match read_folders(filter, package_dir, &new_path, recurse, is_type_dev) {
-                Ok(s) => map.extend(s),
-                Err(e) => log::error!("Could not read directory: {e}"),
+            if ignored_dirs.is_ignored(&new_path) {
+                log::debug!("Ignored: {new_path:?}");
+            } else {
+                match read_folders(
+                    filter,
+                    ignored_dirs,
+                    file_filter,
+                    package_dir,
+                    &new_path,
+                    recurse,
+                    is_type_dev,
+                    read_dirs,
+                ) {
+                    Ok(s) => map.extend(s),
+                    Err(e) => log::error!("Could not read directory: {e}"),
+                }
             }
         }
 
         match path_ext {
             Some(extension) if helpers::is_source_file(extension) => match path_with_meta {
-                Ok((ref path, _)) if matches_filter(filter, &name) => {
+                Ok((ref path, _)) if matches_filter(filter, &name) && file_filter.includes(&name) => {
                     let mut path = path.to_owned();
                     path.push(&name);
                     map.insert(
@@ -208,8 +237,12 @@ pub fn read_folders(
 /// Given a projects' root folder and a `config::Source`, this recursively creates all the
 /// sources in a flat list. In the process, it removes the children, as they are being resolved
 /// because of the recursiveness. So you get a flat list of files back, retaining the type_ and
-/// whether it needs to recurse into all structures
-fn get_source_dirs(source: config::Source, sub_path: Option<PathBuf>) -> AHashSet<config::PackageSource> {
+/// whether it needs to recurse into all structures. Subdirectories in `ignored_dirs` are left out.
+fn get_source_dirs(
+    source: config::Source,
+    sub_path: Option<PathBuf>,
+    ignored_dirs: &config::IgnoredDirs,
+) -> AHashSet<config::PackageSource> {
     let mut source_folders: AHashSet<config::PackageSource> = AHashSet::new();
 
     let source_folder = source.to_qualified_without_children(sub_path.to_owned());
@@ -233,8 +266,16 @@ fn get_source_dirs(source: config::Source, sub_path: Option<PathBuf>) -> AHashSe
         subdirs
             .unwrap_or(vec![])
             .par_iter()
+            .filter(|subsource| {
+                let subsource = subsource.to_qualified_without_children(Some(sub_path.to_owned()));
+                !ignored_dirs.is_ignored(Path::new(&subsource.dir))
+            })
             .map(|subsource| {
-                get_source_dirs(subsource.set_type(source.get_type()), Some(sub_path.to_owned()))
+                get_source_dirs(
+                    subsource.set_type(source.get_type()),
+                    Some(sub_path.to_owned()),
+                    ignored_dirs,
+                )
             })
             .collect::<Vec<AHashSet<config::PackageSource>>>()
             .into_iter()
@@ -447,14 +488,19 @@ pub fn read_package_name(package_dir: &Path) -> Result<String> {
         .ok_or_else(|| anyhow!("No name field found in package.json"))
 }
 
-fn make_package(config: config::Config, package_path: &Path, is_root: bool, is_local_dep: bool) -> Package {
-    let source_folders = match config.sources.to_owned() {
-        Some(config::OneOrMore::Single(source)) => get_source_dirs(source, None),
+fn get_package_source_folders(
+    config: &config::Config,
+    package_path: &Path,
+    is_root: bool,
+) -> AHashSet<config::PackageSource> {
+    let ignored_dirs = config.get_ignored_dirs();
+    match config.sources.to_owned() {
+        Some(config::OneOrMore::Single(source)) => get_source_dirs(source, None, &ignored_dirs),
         Some(config::OneOrMore::Multiple(sources)) => {
             let mut source_folders: AHashSet<config::PackageSource> = AHashSet::new();
             sources
                 .iter()
-                .map(|source| get_source_dirs(source.to_owned(), None))
+                .map(|source| get_source_dirs(source.to_owned(), None, &ignored_dirs))
                 .collect::<Vec<AHashSet<config::PackageSource>>>()
                 .into_iter()
                 .for_each(|source| source_folders.extend(source));
@@ -472,8 +518,10 @@ fn make_package(config: config::Config, package_path: &Path, is_root: bool, is_l
 
             AHashSet::new()
         }
-    };
+    }
+}
 
+fn make_package(config: config::Config, package_path: &Path, is_root: bool, is_local_dep: bool) -> Package {
     let package_name = read_package_name(package_path).expect("Could not read package name");
     if package_name != config.name {
         log::warn!(
@@ -486,19 +534,64 @@ This inconsistency will cause issues with package resolution.\n",
         );
     }
 
+    // we canonicalize the path name so it's always the same
+    let canonical_path = package_path
+        .canonicalize()
+        .map(StrippedVerbatimPath::to_stripped_verbatim_path)
+        .expect("Could not canonicalize");
+    new_package(package_name, config, canonical_path, is_root, is_local_dep)
+}
+
+/// A hash of the files in the folders of bs-external-includes (by name and content), so the package
+/// is compiled again when one of them changes. `None` when the package has no external includes.
+fn get_external_includes_hash(config: &config::Config) -> Option<blake3::Hash> {
+    let includes = config.get_external_includes();
+    if includes.is_empty() {
+        return None;
+    }
+    let mut hasher = blake3::Hasher::new();
+    for dir in includes {
+        hasher.update(dir.to_string_lossy().as_bytes());
+        hasher.update(&[0]);
+        let mut files = fs::read_dir(&dir)
+            .map(|entries| {
+                entries
+                    .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                    .filter(|path| path.is_file())
+                    .collect::<Vec<PathBuf>>()
+            })
+            .unwrap_or_default();
+        files.sort();
+        for file in files {
+            hasher.update(file.file_name().unwrap_or_default().as_encoded_bytes());
+            hasher.update(&[0]);
+            if let Some(hash) = helpers::compute_file_hash(&file) {
+                hasher.update(hash.as_bytes());
+            }
+        }
+    }
+    Some(hasher.finalize())
+}
+
+/// Creates a package without source files, `package_path` should already be canonicalized.
+pub fn new_package(
+    name: String,
+    config: config::Config,
+    package_path: PathBuf,
+    is_root: bool,
+    is_local_dep: bool,
+) -> Package {
     Package {
-        name: package_name,
-        config: config.to_owned(),
-        source_folders,
-        source_files: None,
+        name,
+        source_folders: get_package_source_folders(&config, &package_path, is_root),
         namespace: config.get_namespace(),
+        external_includes_hash: get_external_includes_hash(&config),
+        config,
+        source_files: None,
         modules: None,
-        // we canonicalize the path name so it's always the same
-        path: package_path
-            .canonicalize()
-            .map(StrippedVerbatimPath::to_stripped_verbatim_path)
-            .expect("Could not canonicalize"),
+        path: package_path,
         dirs: None,
+        read_dirs: None,
         is_local_dep,
         is_root,
     }
@@ -545,15 +638,18 @@ fn read_packages(project_context: &ProjectContext, show_progress: bool) -> Resul
 /// data from the config and pushes it forwards. Another thing is the 'type_', some files / folders
 /// can be marked with the type 'dev'. Which means that they may not be around in the distributed
 /// NPM package. The file reader allows for this, just warns when this happens.
+/// Next to the files, it returns the directories that were read.
 /// TODO -> Check whether we actually need the `fs::Metadata`
 pub fn get_source_files(
     package_name: &String,
     package_dir: &Path,
     filter: &Option<regex::Regex>,
+    ignored_dirs: &config::IgnoredDirs,
     source: &config::PackageSource,
     build_dev_deps: bool,
-) -> AHashMap<PathBuf, SourceFileMeta> {
+) -> (AHashMap<PathBuf, SourceFileMeta>, AHashMap<PathBuf, SystemTime>) {
     let mut map: AHashMap<PathBuf, SourceFileMeta> = AHashMap::new();
+    let mut read_dirs: AHashMap<PathBuf, SystemTime> = AHashMap::new();
 
     let recurse = match source {
         config::PackageSource {
@@ -567,10 +663,19 @@ pub fn get_source_files(
     let is_type_dev = source.is_type_dev();
 
     if !build_dev_deps && is_type_dev {
-        return map;
+        return (map, read_dirs);
     }
 
-    match read_folders(filter, package_dir, path_dir, recurse, is_type_dev) {
+    match read_folders(
+        filter,
+        ignored_dirs,
+        &source.get_file_filter(),
+        package_dir,
+        path_dir,
+        recurse,
+        is_type_dev,
+        &mut read_dirs,
+    ) {
         Ok(files) => map.extend(files),
 
         Err(_e) => log::error!(
@@ -581,7 +686,7 @@ pub fn get_source_files(
         ),
     };
 
-    map
+    (map, read_dirs)
 }
 
 /// This takes the tree of packages, and finds all the source files for each, adding them to the
@@ -592,6 +697,8 @@ fn extend_with_children(
 ) -> AHashMap<String, Package> {
     for (_key, package) in build.iter_mut() {
         let mut map: AHashMap<PathBuf, SourceFileMeta> = AHashMap::new();
+        let mut read_dirs: AHashMap<PathBuf, SystemTime> = AHashMap::new();
+        let ignored_dirs = package.config.get_ignored_dirs();
         package
             .source_folders
             .par_iter()
@@ -600,39 +707,53 @@ fn extend_with_children(
                     &package.name,
                     Path::new(&package.path),
                     filter,
+                    &ignored_dirs,
                     source,
                     package.is_local_dep,
                 )
             })
-            .collect::<Vec<AHashMap<PathBuf, SourceFileMeta>>>()
+            .collect::<Vec<(AHashMap<PathBuf, SourceFileMeta>, AHashMap<PathBuf, SystemTime>)>>()
             .into_iter()
-            .for_each(|source| map.extend(source));
+            .for_each(|(source, dirs)| {
+                map.extend(source);
+                read_dirs.extend(dirs);
+            });
 
-        let mut modules = AHashSet::from_iter(
-            map.keys()
-                .map(|key| helpers::file_path_to_module_name(key, &package.namespace)),
-        );
-        match package.namespace.to_owned() {
-            Namespace::Namespace(namespace) => {
-                let _ = modules.insert(namespace);
-            }
-            Namespace::NamespaceWithEntry { namespace, entry: _ } => {
-                let _ = modules.insert("@".to_string() + &namespace);
-            }
-            Namespace::NoNamespace => (),
-        }
-        package.modules = Some(modules);
-        let mut dirs = AHashSet::new();
-        map.keys().for_each(|path| {
-            let dir = std::path::Path::new(&path).parent().unwrap();
-            dirs.insert(dir.to_owned());
-        });
-        package.dirs = Some(dirs);
-        package.source_files = Some(map);
+        set_source_files(package, map, read_dirs);
     }
     build
 }
 
+/// Sets the source files of a package, together with the modules and directories derived from them.
+pub fn set_source_files(
+    package: &mut Package,
+    map: AHashMap<PathBuf, SourceFileMeta>,
+    read_dirs: AHashMap<PathBuf, SystemTime>,
+) {
+    let mut modules = AHashSet::from_iter(
+        map.keys()
+            .map(|key| helpers::file_path_to_module_name(key, &package.namespace)),
+    );
+    match package.namespace.to_owned() {
+        Namespace::Namespace(namespace) => {
+            let _ = modules.insert(namespace);
+        }
+        Namespace::NamespaceWithEntry { namespace, entry: _ } => {
+            let _ = modules.insert("@".to_string() + &namespace);
+        }
+        Namespace::NoNamespace => (),
+    }
+    package.modules = Some(modules);
+    let mut dirs = AHashSet::new();
+    map.keys().for_each(|path| {
+        let dir = std::path::Path::new(&path).parent().unwrap();
+        dirs.insert(dir.to_owned());
+    });
+    package.dirs = Some(dirs);
+    package.read_dirs = Some(read_dirs);
+    package.source_files = Some(map);
+}
+
 /// Make turns a folder, that should contain a config, into a tree of Packages.
 /// It does so in two steps:
 /// 1. Get all the packages parsed, and take all the source folders from the config
@@ -645,6 +766,7 @@ pub fn make(
     project_context: &ProjectContext,
     show_progress: bool,
 ) -> Result<AHashMap<String, Package>> {
+    let _span = trace::span("packages::make");
     let map = read_packages(project_context, show_progress)?;
 
     /* Once we have the deduplicated packages, we can add the source files for each - to minimize
@@ -654,7 +776,65 @@ pub fn make(
     Ok(result)
 }
 
+/// Generates the mlmap "AST" file of a package that has a namespace configured. Returns the name of
+/// the mlmap module and the modules of the namespace it depends on.
+pub fn gen_mlmap(package: &Package) -> Option<(String, AHashSet<String>)> {
+    let namespace = package.namespace.to_suffix()?;
+    let source_files = match package.source_files.to_owned() {
+        Some(source_files) => source_files
+            .keys()
+            .map(|key| key.to_owned())
+            .collect::<Vec<PathBuf>>(),
+        None => unreachable!(),
+    };
+    let entry = match &package.namespace {
+        packages::Namespace::NamespaceWithEntry { entry, namespace: _ } => Some(entry),
+        _ => None,
+    };
+
+    let depending_modules = source_files
+        .iter()
+        .map(|path| helpers::file_path_to_module_name(path, &packages::Namespace::NoNamespace))
+        .filter(|module_name| {
+            if let Some(entry) = entry {
+                module_name != entry
+            } else {
+                true
+            }
+        })
+        .filter(|module_name| helpers::is_non_exotic_module_name(module_name))
+        .collect::<AHashSet<String>>();
+
+    let mlmap = namespaces::gen_mlmap(package, &namespace, &depending_modules);
+
+    // mlmap will be compiled in the AST generation step
+    // compile_mlmap(&package, namespace, &project_root);
+    let deps = source_files
+        .iter()
+        .filter(|path| {
+            helpers::is_non_exotic_module_name(&helpers::file_path_to_module_name(
+                path,
+                &packages::Namespace::NoNamespace,
+            ))
+        })
+        .map(|path| helpers::file_path_to_module_name(path, &package.namespace))
+        .filter(|module_name| {
+            if let Some(entry) = entry {
+                module_name != entry
+            } else {
+                true
+            }
+        })
+        .collect::<AHashSet<String>>();
+
+    Some((
+        helpers::file_path_to_module_name(&mlmap, &packages::Namespace::NoNamespace),
+        deps,
+    ))
+}
+
 pub fn parse_packages(build_state: &mut BuildState) -> Result<()> {
+    let _span = trace::span("packages::parse_packages");
     let packages = build_state.packages.clone();
     for (package_name, package) in packages.iter() {
         debug!("Parsing package: {package_name}");
@@ -698,57 +878,9 @@ pub fn parse_packages(build_state: &mut BuildState) -> Result<()> {
             }
         });
 
-        package.namespace.to_suffix().iter().for_each(|namespace| {
-            // generate the mlmap "AST" file for modules that have a namespace configured
-            let source_files = match package.source_files.to_owned() {
-                Some(source_files) => source_files
-                    .keys()
-                    .map(|key| key.to_owned())
-                    .collect::<Vec<PathBuf>>(),
-                None => unreachable!(),
-            };
-            let entry = match &package.namespace {
-                packages::Namespace::NamespaceWithEntry { entry, namespace: _ } => Some(entry),
-                _ => None,
-            };
-
-            let depending_modules = source_files
-                .iter()
-                .map(|path| helpers::file_path_to_module_name(path, &packages::Namespace::NoNamespace))
-                .filter(|module_name| {
-                    if let Some(entry) = entry {
-                        module_name != entry
-                    } else {
-                        true
-                    }
-                })
-                .filter(|module_name| helpers::is_non_exotic_module_name(module_name))
-                .collect::<AHashSet<String>>();
-
-            let mlmap = namespaces::gen_mlmap(package, namespace, &depending_modules);
-
-            // mlmap will be compiled in the AST generation step
-            // compile_mlmap(&package, namespace, &project_root);
-            let deps = source_files
-                .iter()
-                .filter(|path| {
-                    helpers::is_non_exotic_module_name(&helpers::file_path_to_module_name(
-                        path,
-                        &packages::Namespace::NoNamespace,
-                    ))
-                })
-                .map(|path| helpers::file_path_to_module_name(path, &package.namespace))
-                .filter(|module_name| {
-                    if let Some(entry) = entry {
-                        module_name != entry
-                    } else {
-                        true
-                    }
-                })
-                .collect::<AHashSet<String>>();
-
+        if let Some((mlmap_name, deps)) = gen_mlmap(package) {
             build_state.insert_module(
-                &helpers::file_path_to_module_name(&mlmap.to_owned(), &packages::Namespace::NoNamespace),
+                &mlmap_name,
                 Module {
                     deps_dirty: false,
                     source_type: SourceType::MlMap(MlMap { parse_dirty: false }),
@@ -760,9 +892,11 @@ pub fn parse_packages(build_state: &mut BuildState) -> Result<()> {
                     last_compiled_cmi: None,
                     // Not sure if this is correct
                     is_type_dev: false,
+                    parse_diagnostics: vec![],
+                    compile_diagnostics: vec![],
                 },
             );
-        });
+        }
 
         debug!("Building source file-tree for package: {}", package.name);
         if let Some(source_files) = &package.source_files {
@@ -800,7 +934,6 @@ pub fn parse_packages(build_state: &mut BuildState) -> Result<()> {
                                         compile_state: CompileState::Pending,
                                         last_modified: metadata.modified,
                                         parse_dirty: true,
-                                        compile_warnings: None,
                                     },
                                     interface: None,
                                 }),
@@ -811,6 +944,8 @@ pub fn parse_packages(build_state: &mut BuildState) -> Result<()> {
                                 last_compiled_cmt: None,
                                 last_compiled_cmi: None,
                                 is_type_dev: metadata.is_type_dev,
+                                parse_diagnostics: vec![],
+                                compile_diagnostics: vec![],
                             });
                         }
                     }
@@ -854,7 +989,7 @@ pub fn parse_packages(build_state: &mut BuildState) -> Result<()> {
                                     interface_display
                                 ));
                             }
-                            println!(
+                            eprintln!(
                                 "{} No implementation file found for interface file (skipping): {}",
                                 LINE_CLEAR,
                                 file.to_string_lossy()
@@ -872,7 +1007,6 @@ pub fn parse_packages(build_state: &mut BuildState) -> Result<()> {
                                             compile_state: CompileState::Pending,
                                             last_modified: metadata.modified,
                                             parse_dirty: true,
-                                            compile_warnings: None,
                                         });
                                     }
                                 })
@@ -886,7 +1020,6 @@ pub fn parse_packages(build_state: &mut BuildState) -> Result<()> {
                                             compile_state: CompileState::Pending,
                                             last_modified: metadata.modified,
                                             parse_dirty: true,
-                                            compile_warnings: None,
                                         },
                                         interface: Some(Interface {
                                             path: file.to_owned(),
@@ -894,7 +1027,6 @@ pub fn parse_packages(build_state: &mut BuildState) -> Result<()> {
                                             compile_state: CompileState::Pending,
                                             last_modified: metadata.modified,
                                             parse_dirty: true,
-                                            compile_warnings: None,
                                         }),
                                     }),
                                     deps: AHashSet::new(),
@@ -904,6 +1036,8 @@ pub fn parse_packages(build_state: &mut BuildState) -> Result<()> {
                                     last_compiled_cmt: None,
                                     last_compiled_cmi: None,
                                     is_type_dev: metadata.is_type_dev,
+                                    parse_diagnostics: vec![],
+                                    compile_diagnostics: vec![],
                                 });
                         }
                     }
@@ -1025,8 +1159,9 @@ pub fn validate_packages_dependencies(packages: &AHashMap<String, Package>) -> b
 mod test {
     use crate::config;
 
-    use super::{Namespace, Package};
+    use super::{Namespace, Package, get_external_includes_hash};
     use ahash::{AHashMap, AHashSet};
+    use std::fs;
     use std::path::PathBuf;
 
     pub struct CreatePackageArgs {
@@ -1052,8 +1187,10 @@ mod test {
             modules: None,
             path: PathBuf::from("./something"),
             dirs: None,
+            read_dirs: None,
             is_root: false,
             is_local_dep: false,
+            external_includes_hash: None,
         }
     }
     #[test]
@@ -1133,4 +1270,26 @@ mod test {
         let is_valid = super::validate_packages_dependencies(&packages);
         assert!(is_valid)
     }
+
+    #[test]
+    fn hashes_the_files_of_the_external_includes() {
+        let temp_dir = tempfile::TempDir::new().expect("temp dir should be created");
+        let root = temp_dir.path();
+        fs::create_dir(root.join("vendor")).unwrap();
+        fs::write(root.join("vendor").join("Vendored.cmi"), "1").unwrap();
+        let mut config =
+            config::Config::new_from_json_string(r#"{"name": "test", "bs-external-includes": ["vendor"]}"#)
+                .unwrap();
+        config.path = root.join("rescript.json");
+
+        let hash = get_external_includes_hash(&config);
+        assert!(hash.is_some());
+        assert_eq!(get_external_includes_hash(&config), hash);
+
+        fs::write(root.join("vendor").join("Vendored.cmi"), "2").unwrap();
+        assert_ne!(get_external_includes_hash(&config), hash);
+
+        config.bs_external_includes = None;
+        assert_eq!(get_external_includes_hash(&config), None);
+    }
 }
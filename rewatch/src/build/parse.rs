@@ -1,24 +1,40 @@
 use super::build_types::*;
+use super::diagnostics::{self, Severity};
 use super::logs;
 use super::namespaces;
+use super::source_hashes;
+use super::timings::{self, UnitKind};
 use crate::build::packages::Package;
 use crate::config;
 use crate::config::{Config, OneOrMore};
 use crate::helpers;
 use crate::project_context::ProjectContext;
-use ahash::AHashSet;
+use crate::trace;
+use ahash::{AHashMap, AHashSet};
 use anyhow::anyhow;
 use log::debug;
 use rayon::prelude::*;
 use std::path::{Path, PathBuf};
 use std::process::Command;
+use std::time::Instant;
 
 pub fn generate_asts(
     build_state: &mut BuildCommandState,
     inc: impl Fn() + std::marker::Sync,
 ) -> anyhow::Result<String> {
+    let _span = trace::span("parse::generate_asts");
     let mut has_failure = false;
     let mut stderr = "".to_string();
+    // the source hashes of the parsed files, see `source_hashes`
+    let mut parsed_hashes = AHashMap::<String, Vec<(PathBuf, Option<source_hashes::SourceHash>)>>::new();
+    // the stored hashes of the packages with a preprocessor, their sources are preprocessed here to
+    // find out whether they changed, see `source_hashes`
+    let preprocessed_hashes = build_state
+        .packages
+        .values()
+        .filter(|package| package.config.pp_flags.is_some())
+        .map(|package| (package.name.to_owned(), source_hashes::read(package)))
+        .collect::<AHashMap<String, source_hashes::SourceHashes>>();
 
     build_state
         .modules
@@ -35,44 +51,12 @@ pub fn generate_asts(
                         Ok((Path::new(&path).to_path_buf(), None)),
                         Ok(None),
                         false,
+                        (None, None),
                     )
                 }
 
                 SourceType::SourceFile(source_file) => {
-                    let (ast_result, iast_result, dirty) = if source_file.implementation.parse_dirty
-                        || source_file
-                            .interface
-                            .as_ref()
-                            .map(|i| i.parse_dirty)
-                            .unwrap_or(false)
-                    {
-                        debug!("Generating AST for module: {module_name}");
-                        inc();
-                        let ast_result = generate_ast(
-                            package.to_owned(),
-                            &source_file.implementation.path.to_owned(),
-                            build_state,
-                            build_state.get_warn_error_override(),
-                        )
-                        .map_err(|e| e.to_string());
-
-                        let iast_result = match source_file.interface.as_ref().map(|i| i.path.to_owned()) {
-                            Some(interface_file_path) => {
-                                match generate_ast(
-                                    package.to_owned(),
-                                    &interface_file_path.to_owned(),
-                                    build_state,
-                                    build_state.get_warn_error_override(),
-                                ) {
-                                    Ok(v) => Ok(Some(v)),
-                                    Err(e) => Err(e.to_string()),
-                                }
-                            }
-                            _ => Ok(None),
-                        };
-
-                        (ast_result, iast_result, true)
-                    } else {
+                    let up_to_date = || {
                         (
                             Ok((
                                 PathBuf::from(helpers::get_basename(&source_file.implementation.path))
@@ -86,10 +70,83 @@ pub fn generate_asts(
                                 )
                             })),
                             false,
+                            (None, None),
                         )
                     };
+                    let is_parse_dirty = source_file.implementation.parse_dirty
+                        || source_file
+                            .interface
+                            .as_ref()
+                            .map(|i| i.parse_dirty)
+                            .unwrap_or(false);
+                    let (ast_result, iast_result, dirty, hashes) = if is_parse_dirty {
+                        inc();
+                        // hash the sources before parsing, so a change while parsing makes the
+                        // file dirty again on the next build
+                        let project_context = &build_state.project_context;
+                        let hashes = (
+                            source_hashes::compute(
+                                project_context,
+                                package,
+                                &source_file.implementation.path,
+                            ),
+                            source_file
+                                .interface
+                                .as_ref()
+                                .and_then(|i| source_hashes::compute(project_context, package, &i.path)),
+                        );
+                        let is_preprocessed_unchanged =
+                            preprocessed_hashes.get(&package.name).is_some_and(|stored| {
+                                source_hashes::is_preprocessed_unchanged(
+                                    stored,
+                                    package,
+                                    &source_file.implementation.path,
+                                    hashes.0,
+                                ) && source_file.interface.as_ref().is_none_or(|i| {
+                                    source_hashes::is_preprocessed_unchanged(
+                                        stored, package, &i.path, hashes.1,
+                                    )
+                                })
+                            });
+
+                        if is_preprocessed_unchanged {
+                            debug!("The preprocessed sources of module {module_name} are unchanged");
+                            // the hashes are recorded again with the current modification times
+                            let (ast_result, iast_result, dirty, _) = up_to_date();
+                            (ast_result, iast_result, dirty, hashes)
+                        } else {
+                            debug!("Generating AST for module: {module_name}");
+                            let ast_result = generate_ast(
+                                package.to_owned(),
+                                &source_file.implementation.path.to_owned(),
+                                build_state,
+                                build_state.get_warn_error_override(),
+                            )
+                            .map_err(|e| e.to_string());
+
+                            let iast_result = match source_file.interface.as_ref().map(|i| i.path.to_owned())
+                            {
+                                Some(interface_file_path) => {
+                                    match generate_ast(
+                                        package.to_owned(),
+                                        &interface_file_path.to_owned(),
+                                        build_state,
+                                        build_state.get_warn_error_override(),
+                                    ) {
+                                        Ok(v) => Ok(Some(v)),
+                                        Err(e) => Err(e.to_string()),
+                                    }
+                                }
+                                _ => Ok(None),
+                            };
+
+                            (ast_result, iast_result, true, hashes)
+                        }
+                    } else {
+                        up_to_date()
+                    };
 
-                    (module_name.to_owned(), ast_result, iast_result, dirty)
+                    (module_name.to_owned(), ast_result, iast_result, dirty, hashes)
                 }
             }
         })
@@ -98,98 +155,125 @@ pub fn generate_asts(
             Result<(PathBuf, Option<helpers::StdErr>), String>,
             Result<Option<(PathBuf, Option<helpers::StdErr>)>, String>,
             bool,
+            (
+                Option<source_hashes::SourceHash>,
+                Option<source_hashes::SourceHash>,
+            ),
         )>>()
         .into_iter()
-        .for_each(|(module_name, ast_result, iast_result, is_dirty)| {
-            // Get package name first to avoid borrow checker issues
-            let package_name = build_state
-                .build_state
-                .modules
-                .get(&module_name)
-                .map(|module| module.package_name.clone())
-                .unwrap_or_else(|| {
-                    eprintln!("Module not found: {module_name}");
-                    String::new()
-                });
+        .for_each(
+            |(module_name, ast_result, iast_result, is_dirty, (hash, interface_hash))| {
+                // Get package name first to avoid borrow checker issues
+                let package_name = build_state
+                    .build_state
+                    .modules
+                    .get(&module_name)
+                    .map(|module| module.package_name.clone())
+                    .unwrap_or_else(|| {
+                        eprintln!("Module not found: {module_name}");
+                        String::new()
+                    });
 
-            let package = build_state
-                .build_state
-                .packages
-                .get(&package_name)
-                .expect("Package not found");
+                let package = build_state
+                    .build_state
+                    .packages
+                    .get(&package_name)
+                    .expect("Package not found");
 
-            if let Some(module) = build_state.build_state.modules.get_mut(&module_name) {
-                // if the module is dirty, mark it also compile_dirty
-                // do NOT set to false if the module is not parse_dirty, it needs to keep
-                // the compile_dirty flag if it was set before
-                if is_dirty {
-                    module.compile_dirty = true;
-                    module.deps_dirty = true;
-                }
-                if let SourceType::SourceFile(ref mut source_file) = module.source_type {
-                    // We get Err(x) when there is a parse error. When it's Ok(_, Some(
-                    // stderr_warnings )), the outputs are warnings
-                    match ast_result {
-                        // In case of an internal dependency, we want to keep on
-                        // propagating the warning with every compile. So we mark it as dirty for
-                        // the next round
-                        Ok((_path, Some(stderr_warnings))) if package.is_local_dep => {
-                            source_file.implementation.parse_state = ParseState::Warning;
-                            source_file.implementation.parse_dirty = true;
-                            logs::append(package, &stderr_warnings);
-                            stderr.push_str(&stderr_warnings);
-                        }
-                        Ok((_path, Some(_))) | Ok((_path, None)) => {
-                            source_file.implementation.parse_state = ParseState::Success;
-                            source_file.implementation.parse_dirty = false;
-                        }
-                        Err(err) => {
-                            // Some compilation error
-                            source_file.implementation.parse_state = ParseState::ParseError;
-                            source_file.implementation.parse_dirty = true;
-                            logs::append(package, &err);
-                            has_failure = true;
-                            stderr.push_str(&err);
+                if let Some(module) = build_state.build_state.modules.get_mut(&module_name) {
+                    // if the module is dirty, mark it also compile_dirty
+                    // do NOT set to false if the module is not parse_dirty, it needs to keep
+                    // the compile_dirty flag if it was set before
+                    if is_dirty {
+                        module.compile_dirty = true;
+                        module.deps_dirty = true;
+                    }
+                    if let SourceType::SourceFile(ref mut source_file) = module.source_type {
+                        if is_dirty || hash.is_some() {
+                            let hashes = parsed_hashes.entry(package_name.to_owned()).or_default();
+                            hashes.push((
+                                source_file.implementation.path.to_owned(),
+                                hash.filter(|_| ast_result.is_ok()),
+                            ));
+                            if let Some(interface) = &source_file.interface {
+                                hashes.push((
+                                    interface.path.to_owned(),
+                                    interface_hash.filter(|_| iast_result.is_ok()),
+                                ));
+                            }
                         }
-                    };
 
-                    // We get Err(x) when there is a parse error. When it's Ok(_, Some(( _path,
-                    // stderr_warnings ))), the outputs are warnings
-                    match iast_result {
-                        // In case of an internal dependency, we want to keep on
-                        // propagating the warning with every compile. So we mark it as dirty for
-                        // the next round
-                        Ok(Some((_path, Some(stderr_warnings)))) if package.is_local_dep => {
-                            if let Some(interface) = source_file.interface.as_mut() {
-                                interface.parse_state = ParseState::Warning;
-                                interface.parse_dirty = true;
+                        // We get Err(x) when there is a parse error. When it's Ok(_, Some(
+                        // stderr_warnings )), the outputs are warnings
+                        let implementation_diagnostics = match ast_result {
+                            // In case of an internal dependency, we want to keep on
+                            // propagating the warning with every compile. So we mark it as dirty for
+                            // the next round
+                            Ok((_path, Some(stderr_warnings))) if package.is_local_dep => {
+                                source_file.implementation.parse_state = ParseState::Warning;
+                                source_file.implementation.parse_dirty = true;
+                                diagnostics::parse(&stderr_warnings, Severity::Warning)
                             }
-                            logs::append(package, &stderr_warnings);
-                            stderr.push_str(&stderr_warnings);
-                        }
-                        Ok(Some((_, None))) | Ok(Some((_, Some(_)))) => {
-                            if let Some(interface) = source_file.interface.as_mut() {
-                                interface.parse_state = ParseState::Success;
-                                interface.parse_dirty = false;
+                            Ok((_path, Some(_))) | Ok((_path, None)) => {
+                                source_file.implementation.parse_state = ParseState::Success;
+                                source_file.implementation.parse_dirty = false;
+                                vec![]
                             }
-                        }
-                        Err(err) => {
-                            // Some compilation error
-                            if let Some(interface) = source_file.interface.as_mut() {
-                                interface.parse_state = ParseState::ParseError;
-                                interface.parse_dirty = true;
+                            Err(err) => {
+                                // Some compilation error
+                                source_file.implementation.parse_state = ParseState::ParseError;
+                                source_file.implementation.parse_dirty = true;
+                                has_failure = true;
+                                diagnostics::parse(&err, Severity::Error)
                             }
-                            logs::append(package, &err);
-                            has_failure = true;
-                            stderr.push_str(&err);
-                        }
-                        Ok(None) => {
-                            // The file had no interface file associated
+                        };
+
+                        // We get Err(x) when there is a parse error. When it's Ok(_, Some(( _path,
+                        // stderr_warnings ))), the outputs are warnings
+                        let interface_diagnostics = match iast_result {
+                            // In case of an internal dependency, we want to keep on
+                            // propagating the warning with every compile. So we mark it as dirty for
+                            // the next round
+                            Ok(Some((_path, Some(stderr_warnings)))) if package.is_local_dep => {
+                                if let Some(interface) = source_file.interface.as_mut() {
+                                    interface.parse_state = ParseState::Warning;
+                                    interface.parse_dirty = true;
+                                }
+                                diagnostics::parse(&stderr_warnings, Severity::Warning)
+                            }
+                            Ok(Some((_, None))) | Ok(Some((_, Some(_)))) => {
+                                if let Some(interface) = source_file.interface.as_mut() {
+                                    interface.parse_state = ParseState::Success;
+                                    interface.parse_dirty = false;
+                                }
+                                vec![]
+                            }
+                            Err(err) => {
+                                // Some compilation error
+                                if let Some(interface) = source_file.interface.as_mut() {
+                                    interface.parse_state = ParseState::ParseError;
+                                    interface.parse_dirty = true;
+                                }
+                                has_failure = true;
+                                diagnostics::parse(&err, Severity::Error)
+                            }
+                            Ok(None) => {
+                                // The file had no interface file associated
+                                vec![]
+                            }
+                        };
+
+                        module.parse_diagnostics =
+                            diagnostics::dedup([implementation_diagnostics, interface_diagnostics].concat());
+                        if !module.parse_diagnostics.is_empty() {
+                            let rendered = diagnostics::render(&module.parse_diagnostics);
+                            logs::append(package, &rendered);
+                            stderr.push_str(&rendered);
                         }
-                    }
-                };
-            }
-        });
+                    };
+                }
+            },
+        );
 
     // compile the mlmaps of dirty modules
     // first collect dirty packages
@@ -206,56 +290,58 @@ pub fn generate_asts(
     for (module_name, package_name) in module_package_pairs {
         if let Some(module) = build_state.build_state.modules.get_mut(&module_name) {
             let is_dirty = match &module.source_type {
-                SourceType::MlMap(_) => {
-                    if dirty_packages.contains(&package_name) {
-                        let package = build_state
-                            .build_state
-                            .packages
-                            .get(&package_name)
-                            .expect("Package not found");
-                        // probably better to do this in a different function
-                        // specific to compiling mlmaps
-                        let compile_path = package.get_mlmap_compile_path();
-                        let mlmap_hash = helpers::compute_file_hash(Path::new(&compile_path));
-                        if let Err(err) = namespaces::compile_mlmap(
-                            &build_state.build_state.project_context,
-                            package,
-                            &module_name,
-                            &build_state.build_state.compiler_info.bsc_path,
-                        ) {
+                SourceType::MlMap(_) if dirty_packages.contains(&package_name) => {
+                    let package = build_state
+                        .build_state
+                        .packages
+                        .get(&package_name)
+                        .expect("Package not found");
+                    // probably better to do this in a different function
+                    // specific to compiling mlmaps
+                    let compile_path = package.get_mlmap_compile_path();
+                    let mlmap_hash = helpers::compute_file_hash(Path::new(&compile_path));
+                    module.parse_diagnostics = match namespaces::compile_mlmap(
+                        &build_state.build_state.project_context,
+                        package,
+                        &module_name,
+                        &build_state.build_state.compiler_info.bsc_path,
+                        &build_state.timings,
+                    ) {
+                        Ok(_) => vec![],
+                        Err(err) => {
                             has_failure = true;
-                            stderr.push_str(&format!("{err}\n"));
+                            let diagnostics = diagnostics::parse(&format!("{err}\n"), Severity::Error);
+                            stderr.push_str(&diagnostics::render(&diagnostics));
+                            diagnostics
                         }
-                        let mlmap_hash_after = helpers::compute_file_hash(Path::new(&compile_path));
-
-                        let suffix = package
-                            .namespace
-                            .to_suffix()
-                            .expect("namespace should be set for mlmap module");
-                        let base_build_path = package.get_build_path().join(&suffix);
-                        let base_ocaml_build_path = package.get_ocaml_build_path().join(&suffix);
-                        let _ = std::fs::copy(
-                            base_build_path.with_extension("cmi"),
-                            base_ocaml_build_path.with_extension("cmi"),
-                        );
-                        let _ = std::fs::copy(
-                            base_build_path.with_extension("cmt"),
-                            base_ocaml_build_path.with_extension("cmt"),
-                        );
-                        let _ = std::fs::copy(
-                            base_build_path.with_extension("cmj"),
-                            base_ocaml_build_path.with_extension("cmj"),
-                        );
-                        let _ = std::fs::copy(
-                            base_build_path.with_extension("mlmap"),
-                            base_ocaml_build_path.with_extension("mlmap"),
-                        );
-                        match (mlmap_hash, mlmap_hash_after) {
-                            (Some(digest), Some(digest_after)) => !digest.eq(&digest_after),
-                            _ => true,
-                        }
-                    } else {
-                        false
+                    };
+                    let mlmap_hash_after = helpers::compute_file_hash(Path::new(&compile_path));
+
+                    let suffix = package
+                        .namespace
+                        .to_suffix()
+                        .expect("namespace should be set for mlmap module");
+                    let base_build_path = package.get_build_path().join(&suffix);
+                    let base_ocaml_build_path = package.get_ocaml_build_path().join(&suffix);
+                    let _ = std::fs::copy(
+                        base_build_path.with_extension("cmi"),
+                        base_ocaml_build_path.with_extension("cmi"),
+                    );
+                    let _ = std::fs::copy(
+                        base_build_path.with_extension("cmt"),
+                        base_ocaml_build_path.with_extension("cmt"),
+                    );
+                    let _ = std::fs::copy(
+                        base_build_path.with_extension("cmj"),
+                        base_ocaml_build_path.with_extension("cmj"),
+                    );
+                    let _ = std::fs::copy(
+                        base_build_path.with_extension("mlmap"),
+                        base_ocaml_build_path.with_extension("mlmap"),
+                    );
+                    match (mlmap_hash, mlmap_hash_after) {
+                        (Some(digest), Some(digest_after)) => !digest.eq(&digest_after),
+                        _ => true,
                     }
                 }
                 _ => false,
@@ -266,6 +352,12 @@ pub fn generate_asts(
         }
     }
 
+    parsed_hashes.iter().for_each(|(package_name, hashes)| {
+        if let Some(package) = build_state.packages.get(package_name) {
+            source_hashes::update(package, hashes);
+        }
+    });
+
     if has_failure {
         Err(anyhow!(stderr))
     } else {
@@ -297,11 +389,17 @@ pub fn parser_args(
     let bsc_flags = config::flatten_flags(&package_config.compiler_flags);
     let warning_args = package_config.get_warning_args(is_local_dep, warn_error_override);
 
+    let pp_args = match config::get_pp_command(project_context, package_config)? {
+        Some(command) => vec!["-pp".to_string(), command],
+        None => vec![],
+    };
+
     let file = PathBuf::from("..").join("..").join(file);
 
     Ok((
         ast_path.to_owned(),
         [
+            pp_args,
             ppx_flags,
             jsx_args,
             jsx_module_args,
@@ -325,7 +423,7 @@ pub fn parser_args(
 fn generate_ast(
     package: Package,
     filename: &Path,
-    build_state: &BuildState,
+    build_state: &BuildCommandState,
     warn_error_override: Option<String>,
 ) -> anyhow::Result<(PathBuf, Option<helpers::StdErr>)> {
     let file_path = PathBuf::from(&package.path).join(filename);
@@ -346,19 +444,33 @@ fn generate_ast(
     helpers::create_path(&ast_parent_path);
 
     /* Create .ast */
-    let result = match Some(
-        Command::new(&build_state.compiler_info.bsc_path)
-            .current_dir(&build_path_abs)
-            .args(parser_args)
-            .output()
-            .map_err(|e| {
-                anyhow!(
-                    "Error running bsc for parsing {}: {}",
-                    filename.to_string_lossy(),
-                    e
-                )
-            })?,
-    ) {
+    let module_name = helpers::file_path_to_module_name(filename, &package.namespace);
+    let bsc_span = trace::span_with_args(
+        &format!("parse {module_name}"),
+        "bsc",
+        Some(serde_json::json!({ "package": package.name })),
+    );
+    let started = Instant::now();
+    let output = Command::new(&build_state.compiler_info.bsc_path)
+        .current_dir(&build_path_abs)
+        .args(parser_args)
+        .output();
+    timings::record(
+        &build_state.timings,
+        UnitKind::Parse,
+        &package.name,
+        &module_name,
+        started,
+        output.as_ref().is_ok_and(|output| output.status.success()),
+    );
+    drop(bsc_span);
+    let result = match Some(output.map_err(|e| {
+        anyhow!(
+            "Error running bsc for parsing {}: {}",
+            filename.to_string_lossy(),
+            e
+        )
+    })?) {
         Some(res_to_ast) => {
             let stderr = String::from_utf8_lossy(&res_to_ast.stderr).to_string();
 
@@ -391,6 +503,53 @@ fn generate_ast(
     result
 }
 
+/// Runs the preprocessor of pp-flags over a source file (relative to the package). Returns the
+/// preprocessed source, or `None` when the package has no preprocessor. bsc runs the preprocessor
+/// itself when parsing (`-pp`), this is used to find out whether the preprocessed source changed.
+pub fn preprocess(
+    project_context: &ProjectContext,
+    package: &Package,
+    filename: &Path,
+) -> anyhow::Result<Option<Vec<u8>>> {
+    let Some(command) = config::get_pp_command(project_context, &package.config)? else {
+        return Ok(None);
+    };
+    let _span = trace::span_with_args(
+        &format!("preprocess {}", filename.display()),
+        "pp",
+        Some(serde_json::json!({ "package": package.name })),
+    );
+
+    // run it the way bsc does with `-pp`: in the build folder, with the absolute path of the source
+    let file_path = package.path.join(filename);
+    let output = if cfg!(target_os = "windows") {
+        Command::new("cmd")
+            .args(["/C", &format!("{} \"{}\"", command, file_path.display())])
+            .current_dir(package.get_build_path())
+            .output()
+    } else {
+        // the file is passed as an argument of the script, so it doesn't need to be quoted
+        Command::new("sh")
+            .args(["-c", &format!("{command} \"$1\""), "sh"])
+            .arg(&file_path)
+            .current_dir(package.get_build_path())
+            .output()
+    }
+    .map_err(|e| anyhow!("Error running the preprocessor '{}': {}", command, e))?;
+
+    if output.status.success() {
+        Ok(Some(output.stdout))
+    } else {
+        Err(anyhow!(
+            "Error in {}: the preprocessor '{}' failed for {}\n{}",
+            package.name,
+            command,
+            filename.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))
+    }
+}
+
 fn include_ppx(flag: &str, contents: &str) -> bool {
     if flag.contains("bisect") {
         return std::env::var("BISECT_ENABLE").is_ok();
@@ -423,3 +582,67 @@ fn filter_ppx_flags(
             .collect::<Vec<OneOrMore<String>>>()
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::build::packages;
+    use std::fs;
+    use tempfile::TempDir;
+
+    fn project(pp_flags: &str) -> (TempDir, PathBuf) {
+        let (temp_dir, root) = crate::build::build_types::tests::project(
+            &format!(r#"{{"name": "test", "sources": "src", "pp-flags": "{pp_flags}"}}"#),
+            &[("src/Main.res", "let x = VALUE\n")],
+        );
+        // the preprocessor runs in the build folder
+        fs::create_dir_all(root.join("lib").join("bs")).unwrap();
+        (temp_dir, root)
+    }
+
+    fn package(project_context: &ProjectContext) -> Package {
+        packages::make(&None, project_context, false)
+            .expect("packages should be read")
+            .remove("test")
+            .expect("package should exist")
+    }
+
+    #[test]
+    fn resolves_the_preprocessor_relative_to_the_package() {
+        let (_temp_dir, root) = project("./pp.sh -D FOO");
+        let project_context = ProjectContext::new(&root).expect("project context should be read");
+
+        assert_eq!(
+            config::get_pp_command(&project_context, &project_context.current_config).unwrap(),
+            Some(format!("{} -D FOO", root.join("./pp.sh").display()))
+        );
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn passes_the_preprocessor_to_the_parser() {
+        let (_temp_dir, root) = project("sed s/VALUE/1/");
+        let project_context = ProjectContext::new(&root).expect("project context should be read");
+        let package = package(&project_context);
+        let source = Path::new("src/Main.res");
+
+        assert_eq!(
+            preprocess(&project_context, &package, source).unwrap(),
+            Some(b"let x = 1\n".to_vec())
+        );
+
+        let (_, args) = parser_args(&project_context, &package.config, source, "", true, None).unwrap();
+        assert_eq!(args[..2], ["-pp".to_string(), "sed s/VALUE/1/".to_string()]);
+        assert_eq!(args.last().map(String::as_str), Some("../../src/Main.res"));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn reports_a_failing_preprocessor() {
+        let (_temp_dir, root) = project("false");
+        let project_context = ProjectContext::new(&root).expect("project context should be read");
+        let package = package(&project_context);
+
+        assert!(preprocess(&project_context, &package, Path::new("src/Main.res")).is_err());
+    }
+}
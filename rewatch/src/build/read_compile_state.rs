@@ -1,17 +1,26 @@
+use super::build_graph::{AstSource, AstSources};
 use super::build_types::*;
 use super::packages;
 use crate::helpers;
+use crate::trace;
 use ahash::{AHashMap, AHashSet};
 use rayon::prelude::*;
 use std::fs;
 use std::path::{Path, PathBuf};
 use std::time::SystemTime;
 
-pub fn read(build_state: &mut BuildCommandState) -> anyhow::Result<CompileAssetsState> {
+/// Scans the compile assets of all packages. The sources of ASTs that didn't change since
+/// `cached_ast_sources` was recorded are not read from the AST again.
+pub fn read(
+    build_state: &mut BuildCommandState,
+    cached_ast_sources: &AstSources,
+) -> anyhow::Result<CompileAssetsState> {
+    let _span = trace::span("read_compile_state::read");
     let mut ast_modules: AHashMap<PathBuf, AstModule> = AHashMap::new();
     let mut cmi_modules: AHashMap<String, SystemTime> = AHashMap::new();
     let mut cmt_modules: AHashMap<String, SystemTime> = AHashMap::new();
     let mut ast_rescript_file_locations = AHashSet::new();
+    let mut ast_sources = AstSources::new();
 
     let mut rescript_file_locations = build_state
         .modules
@@ -81,7 +90,18 @@ pub fn read(build_state: &mut BuildCommandState) -> anyhow::Result<CompileAssets
                 "iast" | "ast" => {
                     let module_name = helpers::file_path_to_module_name(path, package_namespace);
 
-                    if let Some(res_file_path_buf) = get_res_path_from_ast(path) {
+                    let res_file_path = match cached_ast_sources.get(path) {
+                        Some(cached) if cached.modified == *last_modified => Some(cached.source.to_owned()),
+                        _ => get_res_path_from_ast(path),
+                    };
+                    if let Some(res_file_path_buf) = res_file_path {
+                        ast_sources.insert(
+                            path.to_owned(),
+                            AstSource {
+                                modified: last_modified.to_owned(),
+                                source: res_file_path_buf.to_owned(),
+                            },
+                        );
                         let _ = ast_modules.insert(
                             res_file_path_buf.clone(),
                             AstModule {
@@ -129,6 +149,7 @@ pub fn read(build_state: &mut BuildCommandState) -> anyhow::Result<CompileAssets
         cmt_modules,
         ast_rescript_file_locations,
         rescript_file_locations,
+        ast_sources,
     })
 }
 
@@ -0,0 +1,296 @@
+//! A remote cache for compiler outputs with a simple HTTP protocol, like the HTTP cache of Bazel:
+//! an entry is read with `GET <url>/<key>` (a 404 when it's missing) and written with
+//! `PUT <url>/<key>`. Only plain `http://` URLs are supported, put a proxy in front of the cache
+//! server for TLS.
+//!
+//! When the server can't be reached, the remote cache is disabled for the rest of the build and
+//! we fall back to the local cache (if any) and the compiler.
+
+use anyhow::{Result, anyhow};
+use clap::ValueEnum;
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+const IO_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum RemoteCacheMode {
+    /// Only restore compiler outputs from the remote cache
+    ReadOnly,
+    /// Also upload new compiler outputs to the remote cache
+    ReadWrite,
+}
+
+#[derive(Debug)]
+pub struct RemoteCache {
+    /// `host:port` of the server
+    authority: String,
+    /// The path of the url, without a trailing slash
+    base_path: String,
+    mode: RemoteCacheMode,
+    unreachable: AtomicBool,
+}
+
+struct Response {
+    status: u16,
+    body: Vec<u8>,
+}
+
+impl RemoteCache {
+    pub fn new(url: &str, mode: RemoteCacheMode) -> Result<Self> {
+        let rest = url
+            .strip_prefix("http://")
+            .ok_or_else(|| anyhow!("Only http:// urls are supported for the remote cache, got {url}"))?;
+        let (authority, path) = match rest.find('/') {
+            Some(index) => rest.split_at(index),
+            None => (rest, ""),
+        };
+        if authority.is_empty() {
+            return Err(anyhow!("Missing host in the remote cache url {url}"));
+        }
+        let authority = if authority.contains(':') {
+            authority.to_string()
+        } else {
+            format!("{authority}:80")
+        };
+        Ok(Self {
+            authority,
+            base_path: path.trim_end_matches('/').to_string(),
+            mode,
+            unreachable: AtomicBool::new(false),
+        })
+    }
+
+    pub fn get(&self, key: &str) -> Option<Vec<u8>> {
+        match self.request("GET", key, None)? {
+            Response { status: 200, body } => Some(body),
+            Response { status: 404, .. } => None,
+            Response { status, .. } => {
+                log::debug!("Remote cache returned {status} for GET {key}");
+                None
+            }
+        }
+    }
+
+    pub fn put(&self, key: &str, body: &[u8]) {
+        if self.mode == RemoteCacheMode::ReadOnly {
+            return;
+        }
+        if let Some(Response { status, .. }) = self.request("PUT", key, Some(body))
+            && !(200..300).contains(&status)
+        {
+            log::debug!("Remote cache returned {status} for PUT {key}");
+        }
+    }
+
+    /// Sends a request, or returns `None` when the server is (or was before) unreachable.
+    fn request(&self, method: &str, key: &str, body: Option<&[u8]>) -> Option<Response> {
+        if self.unreachable.load(Ordering::Relaxed) {
+            return None;
+        }
+        match self.send(method, key, body) {
+            Ok(response) => Some(response),
+            Err(err) => {
+                if !self.unreachable.swap(true, Ordering::Relaxed) {
+                    log::warn!(
+                        "Could not reach the remote cache at {}, continuing without it: {err}",
+                        self.authority
+                    );
+                }
+                None
+            }
+        }
+    }
+
+    fn send(&self, method: &str, key: &str, body: Option<&[u8]>) -> io::Result<Response> {
+        let address = self
+            .authority
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "could not resolve the host"))?;
+        let mut stream = TcpStream::connect_timeout(&address, CONNECT_TIMEOUT)?;
+        stream.set_read_timeout(Some(IO_TIMEOUT))?;
+        stream.set_write_timeout(Some(IO_TIMEOUT))?;
+
+        let body = body.unwrap_or_default();
+        let request = format!(
+            "{method} {}/{key} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\nContent-Length: {}\r\n\r\n",
+            self.base_path,
+            self.authority,
+            body.len()
+        );
+        stream.write_all(request.as_bytes())?;
+        stream.write_all(body)?;
+        stream.flush()?;
+
+        let mut response = vec![];
+        stream.read_to_end(&mut response)?;
+        parse_response(&response)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "invalid HTTP response"))
+    }
+}
+
+fn parse_response(response: &[u8]) -> Option<Response> {
+    let header_end = response.windows(4).position(|window| window == b"\r\n\r\n")?;
+    let head = std::str::from_utf8(&response[..header_end]).ok()?;
+    let body = &response[header_end + 4..];
+    let mut lines = head.split("\r\n");
+    let status = lines.next()?.split_whitespace().nth(1)?.parse::<u16>().ok()?;
+
+    let mut content_length = None;
+    let mut chunked = false;
+    for line in lines {
+        let (name, value) = line.split_once(':')?;
+        let value = value.trim();
+        if name.eq_ignore_ascii_case("content-length") {
+            content_length = value.parse::<usize>().ok();
+        } else if name.eq_ignore_ascii_case("transfer-encoding") {
+            chunked = value.eq_ignore_ascii_case("chunked");
+        }
+    }
+
+    let body = if chunked {
+        decode_chunked(body)?
+    } else {
+        match content_length {
+            Some(length) => body.get(..length)?.to_vec(),
+            None => body.to_vec(),
+        }
+    };
+    Some(Response { status, body })
+}
+
+fn decode_chunked(mut body: &[u8]) -> Option<Vec<u8>> {
+    let mut decoded = vec![];
+    loop {
+        let line_end = body.windows(2).position(|window| window == b"\r\n")?;
+        let size = std::str::from_utf8(&body[..line_end]).ok()?;
+        // chunk extensions come after a ;
+        let size = usize::from_str_radix(size.split(';').next()?.trim(), 16).ok()?;
+        body = &body[line_end + 2..];
+        if size == 0 {
+            return Some(decoded);
+        }
+        decoded.extend_from_slice(body.get(..size)?);
+        body = body.get(size + 2..)?;
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::io::{BufRead, BufReader};
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+
+    /// The entries of the mock server by request path
+    pub(crate) type MockEntries = Arc<Mutex<HashMap<String, Vec<u8>>>>;
+
+    /// A mock cache server that keeps entries in memory, returns its url.
+    pub(crate) fn start_mock_server() -> (String, MockEntries) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/cache", listener.local_addr().unwrap());
+        let entries = MockEntries::default();
+        let server_entries = entries.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(stream) = stream else { continue };
+                let mut reader = BufReader::new(stream);
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).unwrap();
+                let mut parts = request_line.split_whitespace();
+                let method = parts.next().unwrap().to_string();
+                let path = parts.next().unwrap().to_string();
+                let mut content_length = 0;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if line == "\r\n" {
+                        break;
+                    }
+                    if let Some(value) = line.to_ascii_lowercase().strip_prefix("content-length:") {
+                        content_length = value.trim().parse().unwrap();
+                    }
+                }
+                let mut body = vec![0; content_length];
+                reader.read_exact(&mut body).unwrap();
+
+                let mut entries = server_entries.lock().unwrap();
+                let response = match method.as_str() {
+                    "PUT" => {
+                        entries.insert(path, body);
+                        b"HTTP/1.1 201 Created\r\nContent-Length: 0\r\n\r\n".to_vec()
+                    }
+                    _ => match entries.get(&path) {
+                        Some(entry) => {
+                            let mut response =
+                                format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n", entry.len())
+                                    .into_bytes();
+                            response.extend_from_slice(entry);
+                            response
+                        }
+                        None => b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n".to_vec(),
+                    },
+                };
+                reader.get_mut().write_all(&response).unwrap();
+            }
+        });
+        (url, entries)
+    }
+
+    #[test]
+    fn gets_and_puts_entries() {
+        let (url, entries) = start_mock_server();
+        let cache = RemoteCache::new(&url, RemoteCacheMode::ReadWrite).unwrap();
+
+        assert_eq!(cache.get("abc"), None);
+        cache.put("abc", b"outputs");
+        assert_eq!(cache.get("abc"), Some(b"outputs".to_vec()));
+        assert!(entries.lock().unwrap().contains_key("/cache/abc"));
+    }
+
+    #[test]
+    fn does_not_put_entries_when_read_only() {
+        let (url, entries) = start_mock_server();
+        let cache = RemoteCache::new(&url, RemoteCacheMode::ReadOnly).unwrap();
+
+        cache.put("abc", b"outputs");
+        assert_eq!(cache.get("abc"), None);
+        assert!(entries.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn falls_back_when_unreachable() {
+        // nothing listens on the port of a listener that was dropped
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let cache =
+            RemoteCache::new(&format!("http://127.0.0.1:{port}"), RemoteCacheMode::ReadWrite).unwrap();
+
+        assert_eq!(cache.get("abc"), None);
+        assert!(cache.unreachable.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn parses_urls_and_responses() {
+        assert!(RemoteCache::new("https://cache.example.com", RemoteCacheMode::ReadOnly).is_err());
+        let cache =
+            RemoteCache::new("http://cache.example.com/rescript/", RemoteCacheMode::ReadOnly).unwrap();
+        assert_eq!(cache.authority, "cache.example.com:80");
+        assert_eq!(cache.base_path, "/rescript");
+
+        let response = parse_response(
+            b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabc\r\n2;x=y\r\nde\r\n0\r\n\r\n",
+        )
+        .unwrap();
+        assert_eq!(response.status, 200);
+        assert_eq!(response.body, b"abcde");
+    }
+}
@@ -0,0 +1,365 @@
+use super::build_types::BuildCommandState;
+use super::diagnostics::{Diagnostic, Severity};
+use anyhow::{Result, anyhow};
+use serde::Serialize;
+use std::collections::BTreeSet;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReportFormat {
+    Sarif,
+}
+
+/// A report requested with `--report <format>=<path>`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Report {
+    pub format: ReportFormat,
+    pub path: PathBuf,
+}
+
+impl FromStr for Report {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (format, path) = s
+            .split_once('=')
+            .ok_or_else(|| format!("expected <format>=<path>, got '{s}'"))?;
+        let format = match format {
+            "sarif" => ReportFormat::Sarif,
+            other => {
+                return Err(format!(
+                    "unknown report format '{other}', supported formats: sarif"
+                ));
+            }
+        };
+        if path.is_empty() {
+            return Err(format!("missing path for the {} report", format.name()));
+        }
+        Ok(Report {
+            format,
+            path: PathBuf::from(path),
+        })
+    }
+}
+
+impl ReportFormat {
+    fn name(&self) -> &'static str {
+        match self {
+            ReportFormat::Sarif => "sarif",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Phase {
+    Parse,
+    Compile,
+}
+
+/// A diagnostic together with the module it was reported for.
+struct ReportedDiagnostic<'a> {
+    package: &'a str,
+    module: &'a str,
+    phase: Phase,
+    diagnostic: &'a Diagnostic,
+}
+
+fn collect_diagnostics(build_state: &BuildCommandState) -> Vec<ReportedDiagnostic<'_>> {
+    let mut modules = build_state.modules.iter().collect::<Vec<_>>();
+    modules.sort_by_key(|(module_name, _)| *module_name);
+
+    modules
+        .into_iter()
+        .flat_map(|(module_name, module)| {
+            let parse = module.parse_diagnostics.iter().map(|d| (Phase::Parse, d));
+            let compile = module.compile_diagnostics.iter().map(|d| (Phase::Compile, d));
+            parse
+                .chain(compile)
+                .map(|(phase, diagnostic)| ReportedDiagnostic {
+                    package: &module.package_name,
+                    module: module_name,
+                    phase,
+                    diagnostic,
+                })
+        })
+        .collect()
+}
+
+/// Writes the requested reports for the (finished or failed) build.
+pub fn write(build_state: &BuildCommandState, reports: &[Report]) -> Result<()> {
+    for report in reports {
+        match report.format {
+            ReportFormat::Sarif => write_sarif(build_state, &report.path)?,
+        }
+    }
+    Ok(())
+}
+
+// The subset of SARIF 2.1.0 that we need, see
+// https://docs.oasis-open.org/sarif/sarif/v2.1.0/sarif-v2.1.0.html
+
+const SRCROOT: &str = "%SRCROOT%";
+
+#[derive(Serialize)]
+struct SarifLog<'a> {
+    #[serde(rename = "$schema")]
+    schema: &'static str,
+    version: &'static str,
+    runs: Vec<SarifRun<'a>>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SarifRun<'a> {
+    tool: SarifTool,
+    original_uri_base_ids: serde_json::Value,
+    results: Vec<SarifResult<'a>>,
+}
+
+#[derive(Serialize)]
+struct SarifTool {
+    driver: SarifDriver,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SarifDriver {
+    name: &'static str,
+    version: &'static str,
+    information_uri: &'static str,
+    rules: Vec<SarifRule>,
+}
+
+#[derive(Serialize)]
+struct SarifRule {
+    id: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SarifResult<'a> {
+    rule_id: String,
+    level: &'static str,
+    message: SarifMessage,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    locations: Vec<SarifLocation>,
+    properties: SarifProperties<'a>,
+}
+
+#[derive(Serialize)]
+struct SarifMessage {
+    text: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SarifLocation {
+    physical_location: SarifPhysicalLocation,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SarifPhysicalLocation {
+    artifact_location: SarifArtifactLocation,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    region: Option<SarifRegion>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SarifArtifactLocation {
+    uri: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    uri_base_id: Option<&'static str>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SarifRegion {
+    start_line: u32,
+    start_column: u32,
+    end_line: u32,
+    /// Exclusive in SARIF, bsc reports an inclusive end column
+    end_column: u32,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SarifProperties<'a> {
+    package: &'a str,
+    module: &'a str,
+    phase: &'static str,
+}
+
+fn rule_id(diagnostic: &Diagnostic) -> String {
+    match diagnostic.warning_number {
+        Some(number) => format!("warning-{number}"),
+        None => "error".to_string(),
+    }
+}
+
+fn to_uri(path: &Path) -> String {
+    path.to_string_lossy().replace('\\', "/")
+}
+
+fn artifact_location(file: &Path, root: &Path) -> SarifArtifactLocation {
+    match file.strip_prefix(root) {
+        Ok(relative) => SarifArtifactLocation {
+            uri: to_uri(relative),
+            uri_base_id: Some(SRCROOT),
+        },
+        Err(_) => SarifArtifactLocation {
+            uri: to_uri(file),
+            uri_base_id: None,
+        },
+    }
+}
+
+fn to_sarif<'a>(diagnostics: &[ReportedDiagnostic<'a>], root: &Path) -> SarifLog<'a> {
+    let results = diagnostics
+        .iter()
+        .map(|reported| {
+            let diagnostic = reported.diagnostic;
+            SarifResult {
+                rule_id: rule_id(diagnostic),
+                level: match diagnostic.severity {
+                    Severity::Error => "error",
+                    Severity::Warning => "warning",
+                },
+                message: SarifMessage {
+                    text: diagnostic.message.clone(),
+                },
+                locations: diagnostic
+                    .file
+                    .iter()
+                    .map(|file| SarifLocation {
+                        physical_location: SarifPhysicalLocation {
+                            artifact_location: artifact_location(file, root),
+                            region: diagnostic.range.map(|range| SarifRegion {
+                                start_line: range.start_line,
+                                start_column: range.start_column,
+                                end_line: range.end_line,
+                                end_column: range.end_column + 1,
+                            }),
+                        },
+                    })
+                    .collect(),
+                properties: SarifProperties {
+                    package: reported.package,
+                    module: reported.module,
+                    phase: match reported.phase {
+                        Phase::Parse => "parse",
+                        Phase::Compile => "compile",
+                    },
+                },
+            }
+        })
+        .collect::<Vec<SarifResult>>();
+
+    let rules = results
+        .iter()
+        .map(|result| result.rule_id.clone())
+        .collect::<BTreeSet<String>>()
+        .into_iter()
+        .map(|id| SarifRule { id })
+        .collect();
+
+    // Windows paths (C:/...) need an extra slash to be a valid file URI
+    let root = to_uri(root);
+    let mut root_uri = if root.starts_with('/') {
+        format!("file://{root}")
+    } else {
+        format!("file:///{root}")
+    };
+    if !root_uri.ends_with('/') {
+        root_uri.push('/');
+    }
+
+    SarifLog {
+        schema: "https://json.schemastore.org/sarif-2.1.0.json",
+        version: "2.1.0",
+        runs: vec![SarifRun {
+            tool: SarifTool {
+                driver: SarifDriver {
+                    name: "rescript",
+                    version: env!("CARGO_PKG_VERSION"),
+                    information_uri: "https://rescript-lang.org",
+                    rules,
+                },
+            },
+            original_uri_base_ids: serde_json::json!({ SRCROOT: { "uri": root_uri } }),
+            results,
+        }],
+    }
+}
+
+fn write_sarif(build_state: &BuildCommandState, path: &Path) -> Result<()> {
+    let diagnostics = collect_diagnostics(build_state);
+    let sarif = to_sarif(&diagnostics, build_state.project_context.get_root_path());
+
+    let file = File::create(path)
+        .map_err(|e| anyhow!("Could not create SARIF report {}: {e}", path.to_string_lossy()))?;
+    serde_json::to_writer_pretty(BufWriter::new(file), &sarif)
+        .map_err(|e| anyhow!("Could not write SARIF report {}: {e}", path.to_string_lossy()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::build::diagnostics::Range;
+
+    #[test]
+    fn parses_report_argument() {
+        assert_eq!(
+            "sarif=out/rescript.sarif".parse::<Report>(),
+            Ok(Report {
+                format: ReportFormat::Sarif,
+                path: PathBuf::from("out/rescript.sarif"),
+            })
+        );
+        assert!("sarif".parse::<Report>().is_err());
+        assert!("sarif=".parse::<Report>().is_err());
+        assert!("junit=out.xml".parse::<Report>().is_err());
+    }
+
+    #[test]
+    fn converts_diagnostics_to_sarif() {
+        let diagnostic = Diagnostic {
+            file: Some(PathBuf::from("/project/packages/main/src/Main.res")),
+            range: Some(Range {
+                start_line: 1,
+                start_column: 5,
+                end_line: 1,
+                end_column: 15,
+            }),
+            severity: Severity::Warning,
+            warning_number: Some(32),
+            message: "unused value unusedValue.".to_string(),
+            rendered: String::new(),
+        };
+        let diagnostics = vec![ReportedDiagnostic {
+            package: "@testrepo/main",
+            module: "Main",
+            phase: Phase::Compile,
+            diagnostic: &diagnostic,
+        }];
+
+        let sarif = serde_json::to_value(to_sarif(&diagnostics, Path::new("/project"))).unwrap();
+        let run = &sarif["runs"][0];
+        assert_eq!(run["tool"]["driver"]["rules"][0]["id"], "warning-32");
+        assert_eq!(run["originalUriBaseIds"][SRCROOT]["uri"], "file:///project/");
+
+        let result = &run["results"][0];
+        assert_eq!(result["level"], "warning");
+        assert_eq!(result["message"]["text"], "unused value unusedValue.");
+        assert_eq!(result["properties"]["module"], "Main");
+        let location = &result["locations"][0]["physicalLocation"];
+        assert_eq!(location["artifactLocation"]["uri"], "packages/main/src/Main.res");
+        assert_eq!(location["artifactLocation"]["uriBaseId"], SRCROOT);
+        assert_eq!(location["region"]["startColumn"], 5);
+        assert_eq!(location["region"]["endColumn"], 16);
+    }
+}
@@ -0,0 +1,628 @@
+//! Updates the build state of the watcher in place when source files are created, deleted or
+//! renamed.
+//!
+//! Initializing the build again resolves every package, reads every source folder and cleans up
+//! the previous build, which takes seconds in big projects. Instead, the module of a created file
+//! is added (and the module of a deleted file removed), the mlmap of its namespace is generated
+//! again and the modules that depend on it are marked dirty. Changes that can't be applied in
+//! place, like a module name that is used twice, still need a full build.
+
+use super::build_types::*;
+use super::clean;
+use super::packages::{self, Package, SourceFileMeta};
+use crate::config;
+use crate::helpers;
+use crate::helpers::emojis::*;
+use ahash::{AHashMap, AHashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Update {
+    /// None of the paths are source files of the build
+    Unchanged,
+    /// The build state was updated, an incremental build compiles the changes
+    Updated,
+    /// The changes can't be applied in place, the build needs to be initialized again
+    NeedsFullBuild,
+}
+
+impl Update {
+    fn and(self, other: Update) -> Update {
+        match (self, other) {
+            (Update::NeedsFullBuild, _) | (_, Update::NeedsFullBuild) => Update::NeedsFullBuild,
+            (Update::Updated, _) | (_, Update::Updated) => Update::Updated,
+            (Update::Unchanged, Update::Unchanged) => Update::Unchanged,
+        }
+    }
+}
+
+/// A source file of a local package, relative to the package.
+struct SourcePath {
+    package_name: String,
+    path: PathBuf,
+    is_type_dev: bool,
+}
+
+/// Applies the created, deleted and renamed files and folders in `paths` (absolute paths, as
+/// reported by the watcher) to the build state. Whether a path was created or deleted is decided
+/// by whether it exists now, so the paths of both sides of a rename can be passed.
+pub fn update(build_state: &mut BuildState, paths: &[PathBuf], filter: &Option<regex::Regex>) -> Update {
+    let mut added: Vec<PathBuf> = vec![];
+    let mut removed: Vec<PathBuf> = vec![];
+    for path in paths.iter().collect::<AHashSet<_>>() {
+        if path.is_dir() {
+            read_source_files(path, &mut added);
+        } else if path.exists() {
+            if is_source_file(path) {
+                added.push(path.to_owned());
+            }
+        } else {
+            // a deleted folder, or file, with all the source files in it
+            removed.extend(get_known_source_files(build_state, path));
+        }
+    }
+
+    // removals go first, so a file that is moved to another folder doesn't clash with itself
+    let mut update = Update::Unchanged;
+    for path in removed {
+        if let Some(source_path) = get_known_source_path(build_state, &path) {
+            update = update.and(remove_source_file(build_state, source_path));
+        }
+    }
+    for path in added {
+        if let Some(source_path) = get_source_path(build_state, &path, filter) {
+            update = update.and(add_source_file(build_state, &path, source_path));
+        }
+    }
+    update
+}
+
+fn is_source_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(helpers::is_source_file)
+}
+
+fn read_source_files(dir: &Path, files: &mut Vec<PathBuf>) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            read_source_files(&path, files);
+        } else if is_source_file(&path) {
+            files.push(path);
+        }
+    }
+}
+
+fn local_packages(build_state: &BuildState) -> impl Iterator<Item = &Package> {
+    build_state
+        .packages
+        .values()
+        .filter(|package| package.is_local_dep)
+}
+
+/// The source files of the build that are `path`, or in the folder `path`.
+fn get_known_source_files(build_state: &BuildState, path: &Path) -> Vec<PathBuf> {
+    local_packages(build_state)
+        .filter(|package| path.starts_with(&package.path))
+        .flat_map(|package| {
+            package
+                .source_files
+                .iter()
+                .flat_map(|source_files| source_files.keys())
+                .map(|source_file| package.path.join(source_file))
+                .filter(|source_file| source_file.starts_with(path))
+        })
+        .collect()
+}
+
+fn get_known_source_path(build_state: &BuildState, path: &Path) -> Option<SourcePath> {
+    local_packages(build_state).find_map(|package| {
+        let source_files = package.source_files.as_ref()?;
+        source_files.iter().find_map(|(source_file, meta)| {
+            (package.path.join(source_file) == path).then(|| SourcePath {
+                package_name: package.name.to_owned(),
+                path: source_file.to_owned(),
+                is_type_dev: meta.is_type_dev,
+            })
+        })
+    })
+}
+
+/// The path of a new file relative to its package, when it is in one of the source folders of a
+/// local package. The path is joined to the folder as configured, the same way the source folders
+/// are read when the build is initialized.
+fn get_source_path(
+    build_state: &BuildState,
+    path: &Path,
+    filter: &Option<regex::Regex>,
+) -> Option<SourcePath> {
+    let name = path.file_name()?.to_string_lossy();
+    if !packages::matches_filter(filter, &name) {
+        return None;
+    }
+    // the package that is nested the deepest
+    let package = local_packages(build_state)
+        .filter(|package| path.starts_with(&package.path))
+        .max_by_key(|package| package.path.components().count())?;
+    package
+        .source_folders
+        .iter()
+        .filter_map(|source| {
+            let relative_path = path.strip_prefix(package.path.join(&source.dir)).ok()?;
+            let is_nested = relative_path.components().count() > 1;
+            let recurse = matches!(source.subdirs, Some(config::Subdirs::Recurse(true)));
+            (!is_nested || recurse).then_some((source, relative_path))
+        })
+        .max_by_key(|(source, _)| Path::new(&source.dir).components().count())
+        .filter(|(source, _)| source.get_file_filter().includes(&name))
+        .filter(|(source, relative_path)| {
+            // the source folder itself is never ignored, only the folders in it
+            let ignored_dirs = package.config.get_ignored_dirs();
+            let dir = Path::new(&source.dir);
+            !relative_path
+                .parent()
+                .into_iter()
+                .flat_map(|parent| parent.ancestors())
+                .filter(|ancestor| !ancestor.as_os_str().is_empty())
+                .any(|ancestor| ignored_dirs.is_ignored(&dir.join(ancestor)))
+        })
+        .map(|(source, relative_path)| SourcePath {
+            package_name: package.name.to_owned(),
+            path: Path::new(&source.dir).join(relative_path),
+            is_type_dev: source.is_type_dev(),
+        })
+}
+
+fn get_modified(path: &Path) -> SystemTime {
+    path.metadata()
+        .and_then(|metadata| metadata.modified())
+        .unwrap_or_else(|_| SystemTime::now())
+}
+
+fn add_source_file(build_state: &mut BuildState, abs_path: &Path, source_path: SourcePath) -> Update {
+    let SourcePath {
+        package_name,
+        path,
+        is_type_dev,
+    } = source_path;
+    let package = build_state
+        .packages
+        .get(&package_name)
+        .expect("Package not found");
+    let module_name = helpers::file_path_to_module_name(&path, &package.namespace);
+    let modified = get_modified(abs_path);
+    let is_known = package
+        .source_files
+        .as_ref()
+        .is_some_and(|source_files| source_files.contains_key(&path));
+    let is_implementation = helpers::is_implementation_file(&helpers::get_extension(&path));
+
+    // an editor that saves by replacing the file
+    if is_known {
+        if let Some(module) = build_state.modules.get_mut(&module_name)
+            && let SourceType::SourceFile(source_file) = &mut module.source_type
+        {
+            if is_implementation {
+                source_file.implementation.last_modified = modified;
+                source_file.implementation.parse_dirty = true;
+            } else if let Some(interface) = &mut source_file.interface {
+                interface.last_modified = modified;
+                interface.parse_dirty = true;
+            }
+        }
+        return Update::Updated;
+    }
+
+    let existing_module = build_state.modules.get(&module_name);
+    let implementation_path = path.with_extension("res");
+    let update = match existing_module {
+        // a module with the same name, the full build reports it
+        Some(_) if is_implementation => return Update::NeedsFullBuild,
+        None if is_implementation => {
+            let interface_path = path.with_extension("resi");
+            let interface = package
+                .source_files
+                .as_ref()
+                .and_then(|source_files| source_files.get(&interface_path))
+                .map(|meta| new_interface(&interface_path, meta.modified));
+            build_state.insert_module(
+                &module_name,
+                Module {
+                    deps_dirty: true,
+                    source_type: SourceType::SourceFile(SourceFile {
+                        implementation: Implementation {
+                            path: path.to_owned(),
+                            parse_state: ParseState::Pending,
+                            compile_state: CompileState::Pending,
+                            last_modified: modified,
+                            parse_dirty: true,
+                        },
+                        interface,
+                    }),
+                    deps: AHashSet::new(),
+                    dependents: AHashSet::new(),
+                    package_name: package_name.to_owned(),
+                    compile_dirty: true,
+                    last_compiled_cmt: None,
+                    last_compiled_cmi: None,
+                    is_type_dev,
+                    parse_diagnostics: vec![],
+                    compile_diagnostics: vec![],
+                },
+            );
+            mark_referencing_modules_dirty(build_state, &package_name, &path);
+            Update::Updated
+        }
+        Some(Module {
+            source_type: SourceType::SourceFile(source_file),
+            ..
+        }) if source_file.implementation.path == implementation_path => {
+            let module = build_state
+                .modules
+                .get_mut(&module_name)
+                .expect("Module not found");
+            if let SourceType::SourceFile(source_file) = &mut module.source_type {
+                source_file.interface = Some(new_interface(&path, modified));
+            }
+            module.compile_dirty = true;
+            module.deps_dirty = true;
+            Update::Updated
+        }
+        // an interface with a different path or case than its implementation
+        Some(_) => return Update::NeedsFullBuild,
+        None => {
+            eprintln!(
+                "{} No implementation file found for interface file (skipping): {}",
+                LINE_CLEAR,
+                path.to_string_lossy()
+            );
+            Update::Unchanged
+        }
+    };
+
+    let package = build_state
+        .packages
+        .get_mut(&package_name)
+        .expect("Package not found");
+    let mut source_files = package.source_files.take().unwrap_or_default();
+    source_files.insert(
+        path.to_owned(),
+        SourceFileMeta {
+            modified,
+            is_type_dev,
+        },
+    );
+    update_source_files(package, source_files);
+    if is_implementation {
+        package
+            .modules
+            .get_or_insert_with(AHashSet::new)
+            .insert(module_name);
+        create_js_dirs(build_state, &package_name, &path);
+        update_mlmap(build_state, &package_name);
+    }
+    update
+}
+
+fn new_interface(path: &Path, modified: SystemTime) -> Interface {
+    Interface {
+        path: path.to_owned(),
+        parse_state: ParseState::Pending,
+        compile_state: CompileState::Pending,
+        last_modified: modified,
+        parse_dirty: true,
+    }
+}
+
+/// The dependencies of a module only contain the modules that existed when they were read, so the
+/// modules that could reference the new module have to read them again: the modules that failed to
+/// compile, and the modules of the namespace that referenced a module with this name outside of it.
+fn mark_referencing_modules_dirty(build_state: &mut BuildState, package_name: &str, path: &Path) {
+    let name = helpers::file_path_to_module_name(path, &packages::Namespace::NoNamespace);
+    for module in build_state.modules.values_mut() {
+        let has_compile_error = match &module.source_type {
+            SourceType::SourceFile(source_file) => {
+                source_file.implementation.compile_state == CompileState::Error
+                    || source_file
+                        .interface
+                        .as_ref()
+                        .is_some_and(|interface| interface.compile_state == CompileState::Error)
+            }
+            SourceType::MlMap(_) => false,
+        };
+        if has_compile_error || (module.package_name == package_name && module.deps.contains(&name)) {
+            module.deps_dirty = true;
+            module.compile_dirty = true;
+        }
+    }
+}
+
+fn remove_source_file(build_state: &mut BuildState, source_path: SourcePath) -> Update {
+    let SourcePath {
+        package_name, path, ..
+    } = source_path;
+    let package = build_state
+        .packages
+        .get_mut(&package_name)
+        .expect("Package not found");
+    let module_name = helpers::file_path_to_module_name(&path, &package.namespace);
+    let mut source_files = package.source_files.take().unwrap_or_default();
+    source_files.remove(&path);
+    update_source_files(package, source_files);
+
+    let is_module_of_file =
+        build_state
+            .modules
+            .get(&module_name)
+            .is_some_and(|module| match &module.source_type {
+                SourceType::SourceFile(source_file) => {
+                    source_file.implementation.path == path
+                        || source_file
+                            .interface
+                            .as_ref()
+                            .is_some_and(|interface| interface.path == path)
+                }
+                SourceType::MlMap(_) => false,
+            });
+    // an interface without implementation was never part of the build
+    if !is_module_of_file {
+        return Update::Unchanged;
+    }
+
+    let package = build_state
+        .packages
+        .get(&package_name)
+        .expect("Package not found");
+    if helpers::is_implementation_file(&helpers::get_extension(&path)) {
+        let module = build_state
+            .modules
+            .remove(&module_name)
+            .expect("Module not found");
+        build_state.module_names.remove(&module_name);
+        clean::remove_source_file_assets(package, build_state.project_context.get_root_config(), &path);
+        for dep in module.deps.iter() {
+            if let Some(dep_module) = build_state.modules.get_mut(dep) {
+                dep_module.dependents.remove(&module_name);
+            }
+        }
+        // they are compiled again, to report that the module doesn't exist anymore
+        for dependent in module.dependents.iter() {
+            if let Some(dependent_module) = build_state.modules.get_mut(dependent) {
+                dependent_module.deps_dirty = true;
+                dependent_module.compile_dirty = true;
+            }
+        }
+        let package = build_state
+            .packages
+            .get_mut(&package_name)
+            .expect("Package not found");
+        if let Some(modules) = package.modules.as_mut() {
+            modules.remove(&module_name);
+        }
+        update_mlmap(build_state, &package_name);
+    } else {
+        clean::remove_interface_assets(package, &path);
+        let module = build_state
+            .modules
+            .get_mut(&module_name)
+            .expect("Module not found");
+        if let SourceType::SourceFile(source_file) = &mut module.source_type {
+            source_file.interface = None;
+            source_file.implementation.parse_dirty = true;
+        }
+        module.deps_dirty = true;
+        module.compile_dirty = true;
+    }
+    Update::Updated
+}
+
+/// Sets the source files of a package, and updates the folders they are in. The modification time
+/// of the folders that were read is updated too, so the build graph cache stays valid.
+fn update_source_files(package: &mut Package, source_files: AHashMap<PathBuf, SourceFileMeta>) {
+    package.dirs = Some(
+        source_files
+            .keys()
+            .filter_map(|path| path.parent())
+            .map(|dir| dir.to_owned())
+            .collect(),
+    );
+    if let Some(read_dirs) = package.read_dirs.as_mut() {
+        read_dirs.retain(|dir, _| dir.is_dir());
+        for dir in package.dirs.iter().flatten() {
+            let dir = package.path.join(dir);
+            if let Ok(modified) = dir.metadata().and_then(|metadata| metadata.modified()) {
+                read_dirs.insert(dir, modified);
+            }
+        }
+    }
+    package.source_files = Some(source_files);
+}
+
+fn create_js_dirs(build_state: &BuildState, package_name: &str, path: &Path) {
+    let package = build_state.packages.get(package_name).expect("Package not found");
+    let Some(dir) = path.parent() else {
+        return;
+    };
+    for spec in build_state.get_root_config().get_package_specs() {
+        if spec.in_source {
+            continue;
+        }
+        if spec.is_common_js() {
+            helpers::create_path_for_path(&package.get_js_path().join(dir))
+        } else {
+            helpers::create_path_for_path(&package.get_esmodule_path().join(dir))
+        }
+    }
+}
+
+/// Generates the mlmap of the namespace of a package again, and marks it to be compiled.
+fn update_mlmap(build_state: &mut BuildState, package_name: &str) {
+    let package = build_state.packages.get(package_name).expect("Package not found");
+    let Some((mlmap_name, deps)) = packages::gen_mlmap(package) else {
+        return;
+    };
+    if let Some(module) = build_state.modules.get_mut(&mlmap_name) {
+        module.deps = deps;
+        module.compile_dirty = true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::build::build_types::tests::build_state;
+    use tempfile::TempDir;
+
+    fn project() -> (TempDir, PathBuf) {
+        crate::build::build_types::tests::project(
+            r#"{"name": "test", "namespace": "App", "sources": {"dir": "src", "subdirs": true}}"#,
+            &[("src/Main.res", "let x = Util.x"), ("src/Util.res", "let x = 1")],
+        )
+    }
+
+    fn mlmap(build_state: &BuildState) -> String {
+        fs::read_to_string(build_state.packages["test"].get_mlmap_path()).unwrap()
+    }
+
+    #[test]
+    fn adds_created_files() {
+        let (_temp_dir, root) = project();
+        let mut build_state = build_state(&root);
+        let path = root.join("src").join("nested").join("New.res");
+        fs::create_dir(path.parent().unwrap()).unwrap();
+        fs::write(&path, "let y = 2").unwrap();
+
+        assert_eq!(
+            update(&mut build_state, &[path.parent().unwrap().to_owned()], &None),
+            Update::Updated
+        );
+
+        let module = build_state.get_module("New-App").expect("module should be added");
+        assert!(module.compile_dirty && module.deps_dirty);
+        assert!(build_state.module_names.contains("New-App"));
+        let package = &build_state.packages["test"];
+        assert!(
+            package
+                .source_files
+                .as_ref()
+                .unwrap()
+                .contains_key(Path::new("src/nested/New.res"))
+        );
+        assert!(package.dirs.as_ref().unwrap().contains(Path::new("src/nested")));
+        assert_eq!(mlmap(&build_state), "randjbuildsystem\nMain\nNew\nUtil\n");
+        assert!(build_state.get_module("App").unwrap().deps.contains("New-App"));
+    }
+
+    #[test]
+    fn removes_deleted_files() {
+        let (_temp_dir, root) = project();
+        let mut build_state = build_state(&root);
+        build_state.modules.get_mut("Main-App").unwrap().deps = AHashSet::from(["Util-App".to_string()]);
+        build_state.modules.get_mut("Util-App").unwrap().dependents =
+            AHashSet::from(["Main-App".to_string()]);
+        build_state.modules.values_mut().for_each(|module| {
+            module.compile_dirty = false;
+            module.deps_dirty = false;
+        });
+        let path = root.join("src").join("Util.res");
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(update(&mut build_state, &[path], &None), Update::Updated);
+
+        assert!(build_state.get_module("Util-App").is_none());
+        assert!(!build_state.module_names.contains("Util-App"));
+        let main = build_state.get_module("Main-App").unwrap();
+        assert!(main.compile_dirty && main.deps_dirty);
+        assert!(build_state.get_module("App").unwrap().compile_dirty);
+        assert_eq!(mlmap(&build_state), "randjbuildsystem\nMain\n");
+    }
+
+    #[test]
+    fn renames_files_in_place() {
+        let (_temp_dir, root) = project();
+        let mut build_state = build_state(&root);
+        let from = root.join("src").join("Util.res");
+        let to = root.join("src").join("Helpers.res");
+        fs::rename(&from, &to).unwrap();
+
+        assert_eq!(update(&mut build_state, &[from, to], &None), Update::Updated);
+
+        assert!(build_state.get_module("Util-App").is_none());
+        assert!(build_state.get_module("Helpers-App").is_some());
+        assert_eq!(mlmap(&build_state), "randjbuildsystem\nHelpers\nMain\n");
+    }
+
+    #[test]
+    fn needs_a_full_build_for_duplicate_modules() {
+        let (_temp_dir, root) = project();
+        let mut build_state = build_state(&root);
+        let path = root.join("src").join("nested").join("Util.res");
+        fs::create_dir(path.parent().unwrap()).unwrap();
+        fs::write(&path, "let x = 2").unwrap();
+
+        assert_eq!(update(&mut build_state, &[path], &None), Update::NeedsFullBuild);
+    }
+
+    #[test]
+    fn ignores_files_outside_of_the_sources() {
+        let (_temp_dir, root) = project();
+        let mut build_state = build_state(&root);
+        let path = root.join("scripts").join("Script.res");
+        fs::create_dir(path.parent().unwrap()).unwrap();
+        fs::write(&path, "let x = 2").unwrap();
+        let output = root.join("src").join("Main.res.mjs");
+        fs::write(&output, "").unwrap();
+
+        assert_eq!(
+            update(&mut build_state, &[path, output], &None),
+            Update::Unchanged
+        );
+    }
+    #[test]
+    fn ignores_files_that_are_not_in_the_files_of_their_source() {
+        let (_temp_dir, root) = project();
+        fs::write(
+            root.join("rescript.json"),
+            r#"{"name": "test", "namespace": "App", "sources": {"dir": "src", "subdirs": true, "files": {"excludes": ["Experimental.res"]}}}"#,
+        )
+        .unwrap();
+        let mut build_state = build_state(&root);
+        let excluded = root.join("src").join("Experimental.res");
+        let included = root.join("src").join("New.res");
+        fs::write(&excluded, "let x = 2").unwrap();
+        fs::write(&included, "let x = 3").unwrap();
+
+        assert_eq!(
+            update(&mut build_state, &[excluded, included], &None),
+            Update::Updated
+        );
+        assert!(build_state.get_module("Experimental-App").is_none());
+        assert!(build_state.get_module("New-App").is_some());
+    }
+
+    #[test]
+    fn ignores_files_in_ignored_dirs() {
+        let (_temp_dir, root) = project();
+        fs::write(
+            root.join("rescript.json"),
+            r#"{"name": "test", "namespace": "App", "sources": {"dir": "src", "subdirs": true}, "ignored-dirs": ["__fixtures__"]}"#,
+        )
+        .unwrap();
+        let fixtures = root.join("src").join("a").join("__fixtures__");
+        fs::create_dir_all(&fixtures).unwrap();
+        fs::write(fixtures.join("Fixture.res"), "let x = 2").unwrap();
+        let mut build_state = build_state(&root);
+        assert!(build_state.get_module("Fixture-App").is_none());
+
+        let path = fixtures.join("Other.res");
+        fs::write(&path, "let x = 3").unwrap();
+        assert_eq!(update(&mut build_state, &[path], &None), Update::Unchanged);
+        assert!(build_state.get_module("Other-App").is_none());
+    }
+}
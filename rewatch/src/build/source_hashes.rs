@@ -0,0 +1,201 @@
+//! Content hashes of the parsed source files, stored per package in `lib/bs/source-hashes.json`.
+//!
+//! A source file that is newer than its AST is only parsed again when its content changed since
+//! the AST was generated. That way a `git checkout` that touches many files without changing
+//! them doesn't trigger a (near) full rebuild.
+//!
+//! For packages with a preprocessor (pp-flags) we hash the preprocessed source instead, as that is
+//! what the AST is generated from. A module with such a source that was modified since it was
+//! hashed is preprocessed while parsing, and only parsed again when the preprocessed source
+//! changed. Either way its modification time is recorded, so it isn't preprocessed again on the
+//! next build.
+
+use super::packages::Package;
+use super::parse;
+use crate::helpers;
+use crate::project_context::ProjectContext;
+use ahash::AHashMap;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+const VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct SourceHashesFile {
+    version: u32,
+    /// blake3 hashes by source path relative to the package
+    files: BTreeMap<String, String>,
+    /// Modification times of the preprocessed sources when they were hashed, in nanoseconds since
+    /// the Unix epoch
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    modified: BTreeMap<String, u64>,
+}
+
+/// The hash of what the AST of a source was generated from, see `compute`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SourceHash {
+    pub hash: blake3::Hash,
+    /// The modification time of a preprocessed source when it was hashed
+    pub modified: Option<u64>,
+}
+
+/// The hashes of the sources the current ASTs were generated from, by path relative to the package.
+pub type SourceHashes = AHashMap<PathBuf, SourceHash>;
+
+fn get_modified(path: &Path) -> Option<u64> {
+    let modified = fs::metadata(path).ok()?.modified().ok()?;
+    u64::try_from(modified.duration_since(UNIX_EPOCH).ok()?.as_nanos()).ok()
+}
+
+pub fn read(package: &Package) -> SourceHashes {
+    let Ok(contents) = fs::read_to_string(package.get_source_hashes_path()) else {
+        return SourceHashes::new();
+    };
+    match serde_json::from_str::<SourceHashesFile>(&contents) {
+        Ok(file) if file.version == VERSION => file
+            .files
+            .iter()
+            .filter_map(|(path, hash)| {
+                let hash = SourceHash {
+                    hash: blake3::Hash::from_hex(hash).ok()?,
+                    modified: file.modified.get(path).copied(),
+                };
+                Some((PathBuf::from(path), hash))
+            })
+            .collect(),
+        // unknown or invalid format, we fall back to comparing modification times
+        _ => SourceHashes::new(),
+    }
+}
+
+/// The hash of what the AST of `source_path` (relative to the package) is generated from: the
+/// source, or its preprocessed source for packages with a preprocessor.
+pub fn compute(
+    project_context: &ProjectContext,
+    package: &Package,
+    source_path: &Path,
+) -> Option<SourceHash> {
+    // before preprocessing, so a change in the meantime is picked up by the next build
+    let modified = get_modified(&package.path.join(source_path));
+    match parse::preprocess(project_context, package, source_path) {
+        Ok(Some(output)) => Some(SourceHash {
+            hash: blake3::hash(&output),
+            modified,
+        }),
+        Ok(None) => helpers::compute_file_hash(&package.path.join(source_path))
+            .map(|hash| SourceHash { hash, modified: None }),
+        Err(err) => {
+            log::debug!("{err}");
+            None
+        }
+    }
+}
+
+/// Whether `source_path` (relative to the package) has the same content as when its AST was
+/// generated. Running the preprocessor for each source would be slow, so preprocessed sources are
+/// only unchanged when they weren't modified since they were hashed. The others are checked in
+/// parallel by `is_preprocessed_unchanged` when parsing.
+pub fn is_unchanged(hashes: &SourceHashes, package: &Package, source_path: &Path) -> bool {
+    let source_path_abs = package.path.join(source_path);
+    match hashes.get(source_path) {
+        Some(SourceHash {
+            modified: Some(modified),
+            ..
+        }) if package.config.pp_flags.is_some() => get_modified(&source_path_abs) == Some(*modified),
+        _ if package.config.pp_flags.is_some() => false,
+        Some(SourceHash { hash, .. }) => helpers::compute_file_hash(&source_path_abs).as_ref() == Some(hash),
+        None => false,
+    }
+}
+
+/// Whether the AST of a preprocessed source (relative to the package) exists and was generated from
+/// a preprocessed source with the given hash, see `compute`.
+pub fn is_preprocessed_unchanged(
+    hashes: &SourceHashes,
+    package: &Package,
+    source_path: &Path,
+    hash: Option<SourceHash>,
+) -> bool {
+    hash.is_some_and(|hash| hashes.get(source_path).map(|stored| stored.hash) == Some(hash.hash))
+        && package
+            .get_build_path()
+            .join(helpers::get_ast_path(source_path))
+            .exists()
+}
+
+/// Records the hashes of the files that were just parsed. A `None` hash removes the file, which
+/// we do for files that could not be parsed. Files that are no longer part of the package are
+/// dropped.
+pub fn update(package: &Package, parsed: &[(PathBuf, Option<SourceHash>)]) {
+    let mut hashes = read(package);
+    for (path, hash) in parsed {
+        match hash {
+            Some(hash) => hashes.insert(path.to_owned(), *hash),
+            None => hashes.remove(path),
+        };
+    }
+    if let Some(source_files) = &package.source_files {
+        hashes.retain(|path, _| source_files.contains_key(path));
+    }
+
+    let file = SourceHashesFile {
+        version: VERSION,
+        files: hashes
+            .iter()
+            .map(|(path, hash)| (path.to_string_lossy().to_string(), hash.hash.to_hex().to_string()))
+            .collect(),
+        modified: hashes
+            .iter()
+            .filter_map(|(path, hash)| Some((path.to_string_lossy().to_string(), hash.modified?)))
+            .collect(),
+    };
+    match serde_json::to_string(&file) {
+        Ok(contents) => {
+            if let Err(err) = fs::write(package.get_source_hashes_path(), contents) {
+                log::debug!("Could not write source hashes of {}: {err}", package.name);
+            }
+        }
+        Err(err) => log::debug!("Could not serialize source hashes of {}: {err}", package.name),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::build::{build_types, packages};
+    use std::fs::File;
+    use std::time::{Duration, SystemTime};
+
+    #[test]
+    #[cfg(unix)]
+    fn preprocessed_sources_are_unchanged_until_modified() {
+        let (_temp_dir, root) = build_types::tests::project(
+            r#"{"name": "test", "sources": "src", "pp-flags": "cat"}"#,
+            &[("src/Main.res", "let x = 1\n")],
+        );
+        fs::create_dir_all(root.join("lib").join("bs")).unwrap();
+        let project_context = ProjectContext::new(&root).expect("project context should be read");
+        let package = packages::make(&None, &project_context, false)
+            .expect("packages should be read")
+            .remove("test")
+            .expect("package should exist");
+        let source = Path::new("src/Main.res");
+
+        let hash = compute(&project_context, &package, source);
+        assert!(hash.is_some_and(|hash| hash.modified.is_some()));
+        update(&package, &[(source.to_path_buf(), hash)]);
+        assert!(is_unchanged(&read(&package), &package, source));
+
+        // e.g. touched by a git checkout, it has to be preprocessed again
+        File::options()
+            .write(true)
+            .open(root.join(source))
+            .unwrap()
+            .set_modified(SystemTime::now() + Duration::from_secs(10))
+            .unwrap();
+        assert!(!is_unchanged(&read(&package), &package, source));
+    }
+}
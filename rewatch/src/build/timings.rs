@@ -0,0 +1,456 @@
+use super::build_types::BuildCommandState;
+use super::packages;
+use ahash::AHashMap;
+use anyhow::{Result, anyhow};
+use clap::ValueEnum;
+use serde::Serialize;
+use std::fmt::Write as _;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::Instant;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum TimingsFormat {
+    Html,
+    Json,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum UnitKind {
+    Parse,
+    Compile,
+    CompileInterface,
+    Mlmap,
+}
+
+impl UnitKind {
+    fn name(&self) -> &'static str {
+        match self {
+            UnitKind::Parse => "parse",
+            UnitKind::Compile => "compile",
+            UnitKind::CompileInterface => "compile_interface",
+            UnitKind::Mlmap => "mlmap",
+        }
+    }
+}
+
+/// A single bsc invocation.
+#[derive(Serialize, Debug, Clone)]
+pub struct Unit {
+    pub kind: UnitKind,
+    pub package: String,
+    pub module: String,
+    /// The rayon worker that ran the invocation, `None` when it ran on the main thread.
+    pub thread: Option<usize>,
+    /// Seconds since the start of the build.
+    pub start: f64,
+    pub duration: f64,
+    pub success: bool,
+}
+
+/// Records the bsc invocations of a build for `--timings`. Shared between the rayon workers, so
+/// recording only takes a short lock to push the finished unit.
+#[derive(Debug)]
+pub struct Timings {
+    start: Instant,
+    units: Mutex<Vec<Unit>>,
+}
+
+impl Default for Timings {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Timings {
+    pub fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            units: Mutex::new(vec![]),
+        }
+    }
+
+    /// Records an invocation that started at `started` and just finished.
+    pub fn record(&self, kind: UnitKind, package: &str, module: &str, started: Instant, success: bool) {
+        let unit = Unit {
+            kind,
+            package: package.to_string(),
+            module: module.to_string(),
+            thread: rayon::current_thread_index(),
+            start: started.duration_since(self.start).as_secs_f64(),
+            duration: started.elapsed().as_secs_f64(),
+            success,
+        };
+        self.units.lock().expect("timings lock poisoned").push(unit);
+    }
+
+    fn units(&self) -> Vec<Unit> {
+        let mut units = self.units.lock().expect("timings lock poisoned").clone();
+        units.sort_by(|a, b| a.start.total_cmp(&b.start));
+        units
+    }
+}
+
+/// Records the invocation when timings are enabled for this build.
+pub fn record(
+    timings: &Option<Timings>,
+    kind: UnitKind,
+    package: &str,
+    module: &str,
+    started: Instant,
+    success: bool,
+) {
+    if let Some(timings) = timings {
+        timings.record(kind, package, module, started, success);
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+struct CriticalPathEntry {
+    module: String,
+    package: String,
+    duration: f64,
+}
+
+#[derive(Serialize, Debug)]
+struct TimingsReport {
+    version: &'static str,
+    total: f64,
+    threads: usize,
+    units: Vec<Unit>,
+    /// The longest chain of dependent modules (by compile time) that was compiled in this build,
+    /// starting with the module without dependencies.
+    critical_path: Vec<CriticalPathEntry>,
+}
+
+/// The chain of compiled modules with the largest sum of compile times, following module deps.
+fn critical_path(build_state: &BuildCommandState, units: &[Unit]) -> Vec<CriticalPathEntry> {
+    let mut compile_times: AHashMap<&str, (f64, &str)> = AHashMap::new();
+    for unit in units {
+        if matches!(unit.kind, UnitKind::Compile | UnitKind::CompileInterface) {
+            let entry = compile_times
+                .entry(unit.module.as_str())
+                .or_insert((0.0, unit.package.as_str()));
+            entry.0 += unit.duration;
+        }
+    }
+
+    // Longest path ending in each module, memoized. The module graph is a DAG when compilation
+    // succeeded; cycles are guarded against by `visiting`.
+    fn longest<'a>(
+        module: &'a str,
+        build_state: &'a BuildCommandState,
+        compile_times: &AHashMap<&'a str, (f64, &'a str)>,
+        memo: &mut AHashMap<&'a str, (f64, Option<&'a str>)>,
+        visiting: &mut Vec<&'a str>,
+    ) -> f64 {
+        if let Some((cost, _)) = memo.get(module) {
+            return *cost;
+        }
+        if visiting.contains(&module) {
+            return 0.0;
+        }
+        visiting.push(module);
+        let mut best: (f64, Option<&str>) = (0.0, None);
+        if let Some(deps) = build_state.get_module(module).map(|m| &m.deps) {
+            for dep in deps.iter().filter(|dep| compile_times.contains_key(dep.as_str())) {
+                let cost = longest(dep, build_state, compile_times, memo, visiting);
+                if cost > best.0 {
+                    best = (cost, Some(dep.as_str()));
+                }
+            }
+        }
+        visiting.pop();
+        let own = compile_times.get(module).map(|(time, _)| *time).unwrap_or(0.0);
+        memo.insert(module, (own + best.0, best.1));
+        own + best.0
+    }
+
+    let mut memo = AHashMap::new();
+    let mut end: Option<(&str, f64)> = None;
+    let mut modules = compile_times.keys().copied().collect::<Vec<&str>>();
+    modules.sort();
+    for module in modules {
+        let cost = longest(module, build_state, &compile_times, &mut memo, &mut vec![]);
+        if end.is_none_or(|(_, best)| cost > best) {
+            end = Some((module, cost));
+        }
+    }
+
+    let mut path = vec![];
+    let mut current = end.map(|(module, _)| module);
+    while let Some(module) = current {
+        let (duration, package) = compile_times[module];
+        path.push(CriticalPathEntry {
+            module: module.to_string(),
+            package: package.to_string(),
+            duration,
+        });
+        current = memo.get(module).and_then(|(_, next)| *next);
+    }
+    path.reverse();
+    path
+}
+
+fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn to_html(report: &TimingsReport) -> String {
+    let total = report.total.max(f64::EPSILON);
+    let mut rows: Vec<Option<usize>> = report.units.iter().map(|unit| unit.thread).collect();
+    rows.sort();
+    rows.dedup();
+
+    let mut html = String::new();
+    let _ = write!(
+        html,
+        r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>ReScript build timings</title>
+<style>
+body {{ font-family: sans-serif; margin: 2em; }}
+table {{ border-collapse: collapse; margin-bottom: 2em; }}
+td, th {{ border: 1px solid #ccc; padding: 2px 8px; text-align: left; }}
+.timeline {{ position: relative; margin-bottom: 2em; }}
+.row {{ position: relative; height: 18px; border-bottom: 1px solid #eee; }}
+.row-label {{ position: absolute; left: -5em; width: 4.5em; font-size: 12px; text-align: right; }}
+.unit {{ position: absolute; height: 16px; top: 1px; min-width: 1px; }}
+.parse {{ background: #7fb3d5; }}
+.compile {{ background: #82e0aa; }}
+.compile_interface {{ background: #f7dc6f; }}
+.mlmap {{ background: #d7bde2; }}
+.failed {{ background: #e74c3c; }}
+</style>
+</head>
+<body>
+<h1>ReScript build timings</h1>
+<table>
+<tr><td>Total time</td><td>{:.2}s</td></tr>
+<tr><td>bsc invocations</td><td>{}</td></tr>
+<tr><td>Threads</td><td>{}</td></tr>
+</table>
+<h2>Timeline</h2>
+<p><span class="parse">&nbsp;parse&nbsp;</span> <span class="compile">&nbsp;compile&nbsp;</span> <span class="compile_interface">&nbsp;compile interface&nbsp;</span> <span class="mlmap">&nbsp;mlmap&nbsp;</span> <span class="failed">&nbsp;failed&nbsp;</span></p>
+<div class="timeline" style="margin-left: 5em;">
+"#,
+        report.total,
+        report.units.len(),
+        report.threads
+    );
+
+    for row in rows {
+        let label = row.map(|thread| thread.to_string()).unwrap_or("main".to_string());
+        let _ = writeln!(html, r#"<div class="row"><span class="row-label">{label}</span>"#);
+        for unit in report.units.iter().filter(|unit| unit.thread == row) {
+            let class = if unit.success { unit.kind.name() } else { "failed" };
+            let _ = writeln!(
+                html,
+                r#"<div class="unit {class}" style="left: {:.3}%; width: {:.3}%;" title="{} ({}) {} {:.3}s"></div>"#,
+                unit.start / total * 100.0,
+                unit.duration / total * 100.0,
+                escape_html(&unit.module),
+                escape_html(&unit.package),
+                unit.kind.name(),
+                unit.duration
+            );
+        }
+        html.push_str("</div>\n");
+    }
+    html.push_str("</div>\n");
+
+    html.push_str(
+        "<h2>Critical path</h2>\n<table>\n<tr><th>Module</th><th>Package</th><th>Compile time</th></tr>\n",
+    );
+    for entry in report.critical_path.iter() {
+        let _ = writeln!(
+            html,
+            "<tr><td>{}</td><td>{}</td><td>{:.3}s</td></tr>",
+            escape_html(&entry.module),
+            escape_html(&entry.package),
+            entry.duration
+        );
+    }
+    html.push_str("</table>\n");
+
+    let mut slowest = report.units.iter().collect::<Vec<&Unit>>();
+    slowest.sort_by(|a, b| b.duration.total_cmp(&a.duration));
+    html.push_str("<h2>Slowest invocations</h2>\n<table>\n<tr><th>Module</th><th>Package</th><th>Kind</th><th>Time</th></tr>\n");
+    for unit in slowest.into_iter().take(50) {
+        let _ = writeln!(
+            html,
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{:.3}s</td></tr>",
+            escape_html(&unit.module),
+            escape_html(&unit.package),
+            unit.kind.name(),
+            unit.duration
+        );
+    }
+    html.push_str("</table>\n</body>\n</html>\n");
+    html
+}
+
+/// Writes the timings report(s) to `lib/bs` of the root package and returns the written paths.
+pub fn write(build_state: &BuildCommandState, formats: &[TimingsFormat]) -> Result<Vec<PathBuf>> {
+    let Some(timings) = &build_state.timings else {
+        return Ok(vec![]);
+    };
+    let units = timings.units();
+    let report = TimingsReport {
+        version: env!("CARGO_PKG_VERSION"),
+        total: timings.start.elapsed().as_secs_f64(),
+        threads: rayon::current_num_threads(),
+        critical_path: critical_path(build_state, &units),
+        units,
+    };
+
+    let build_path = packages::get_build_path(build_state.project_context.get_root_path());
+    formats
+        .iter()
+        .map(|format| {
+            let (path, contents) = match format {
+                TimingsFormat::Html => (build_path.join("rescript-timings.html"), to_html(&report)),
+                TimingsFormat::Json => (
+                    build_path.join("rescript-timings.json"),
+                    serde_json::to_string_pretty(&report)?,
+                ),
+            };
+            std::fs::write(&path, contents)
+                .map_err(|e| anyhow!("Could not write timings report {}: {e}", path.to_string_lossy()))?;
+            Ok(path)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::build::build_types;
+    use tempfile::TempDir;
+
+    fn unit(kind: UnitKind, module: &str, thread: Option<usize>, start: f64, duration: f64) -> Unit {
+        Unit {
+            kind,
+            package: "@testrepo/main".to_string(),
+            module: module.to_string(),
+            thread,
+            start,
+            duration,
+            success: true,
+        }
+    }
+
+    /// A build of the modules with the given dependencies.
+    fn build_state(deps: &[(&str, &[&str])]) -> (TempDir, BuildCommandState) {
+        let files = deps
+            .iter()
+            .map(|(module, _)| (format!("src/{module}.res"), String::new()))
+            .collect::<Vec<_>>();
+        let (temp_dir, root) = build_types::tests::project(
+            r#"{"name": "test", "sources": "src"}"#,
+            &files
+                .iter()
+                .map(|(path, contents)| (path.as_str(), contents.as_str()))
+                .collect::<Vec<_>>(),
+        );
+        let mut build_state = build_types::tests::build_command_state(&root);
+        for (module, module_deps) in deps {
+            build_state
+                .modules
+                .get_mut(*module)
+                .expect("module should exist")
+                .deps = module_deps.iter().map(|dep| dep.to_string()).collect();
+        }
+        (temp_dir, build_state)
+    }
+
+    fn modules(path: &[CriticalPathEntry]) -> Vec<&str> {
+        path.iter().map(|entry| entry.module.as_str()).collect()
+    }
+
+    #[test]
+    fn follows_the_dependencies_of_the_slowest_chain() {
+        let (_temp_dir, build_state) = build_state(&[
+            ("A", &[]),
+            ("B", &["A"]),
+            ("C", &["B"]),
+            ("D", &["A"]),
+            ("Cached", &[]),
+        ]);
+        let units = vec![
+            unit(UnitKind::Compile, "A", Some(0), 0.0, 1.0),
+            unit(UnitKind::CompileInterface, "B", Some(0), 1.0, 0.5),
+            unit(UnitKind::Compile, "B", Some(0), 1.5, 1.5),
+            unit(UnitKind::Compile, "D", Some(1), 1.0, 2.5),
+            unit(UnitKind::Compile, "C", Some(0), 3.0, 1.0),
+            // parsing isn't part of the critical path
+            unit(UnitKind::Parse, "D", Some(1), 0.0, 10.0),
+        ];
+
+        let path = critical_path(&build_state, &units);
+        assert_eq!(modules(&path), ["A", "B", "C"]);
+        // the interface and the implementation of B
+        assert_eq!(path[1].duration, 2.0);
+    }
+
+    #[test]
+    fn picks_the_slower_of_competing_dependencies() {
+        let (_temp_dir, build_state) = build_state(&[
+            ("Fast", &[]),
+            ("Slow", &[]),
+            ("Base", &[]),
+            ("Mid", &["Base"]),
+            ("Top", &["Fast", "Slow", "Mid"]),
+        ]);
+        let units = vec![
+            unit(UnitKind::Compile, "Fast", Some(0), 0.0, 0.5),
+            unit(UnitKind::Compile, "Slow", Some(1), 0.0, 2.0),
+            unit(UnitKind::Compile, "Base", Some(2), 0.0, 1.0),
+            unit(UnitKind::Compile, "Mid", Some(2), 1.0, 0.75),
+            unit(UnitKind::Compile, "Top", Some(0), 2.0, 1.0),
+        ];
+
+        assert_eq!(modules(&critical_path(&build_state, &units)), ["Slow", "Top"]);
+
+        // a dependency that wasn't compiled in this build doesn't count
+        let units = units
+            .into_iter()
+            .filter(|unit| unit.module != "Slow")
+            .collect::<Vec<_>>();
+        assert_eq!(
+            modules(&critical_path(&build_state, &units)),
+            ["Base", "Mid", "Top"]
+        );
+    }
+
+    #[test]
+    fn renders_a_row_per_thread() {
+        let report = TimingsReport {
+            version: "test",
+            total: 2.0,
+            threads: 2,
+            units: vec![
+                unit(UnitKind::Mlmap, "Namespace", None, 0.0, 0.5),
+                unit(UnitKind::Parse, "A", Some(0), 0.5, 0.5),
+                unit(UnitKind::Compile, "<B>", Some(1), 1.0, 1.0),
+            ],
+            critical_path: vec![CriticalPathEntry {
+                module: "<B>".to_string(),
+                package: "@testrepo/main".to_string(),
+                duration: 1.0,
+            }],
+        };
+
+        let html = to_html(&report);
+        assert_eq!(html.matches(r#"<div class="row">"#).count(), 3);
+        assert!(html.contains(r#"<span class="row-label">main</span>"#));
+        assert!(html.contains(r#"class="unit compile" style="left: 50.000%; width: 50.000%;""#));
+        assert!(html.contains("<tr><td>&lt;B&gt;</td><td>@testrepo/main</td><td>1.000s</td></tr>"));
+    }
+}
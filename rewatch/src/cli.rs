@@ -11,12 +11,16 @@
 //
 // However, we may want to revisit the decision to use clap after the v12 release.
 
-use std::{env, ffi::OsString, ops::Deref};
+use std::{env, ffi::OsString, num::NonZeroUsize, ops::Deref, path::PathBuf};
 
 use clap::{Args, CommandFactory, Parser, Subcommand, error::ErrorKind};
 use clap_verbosity_flag::InfoLevel;
 use regex::Regex;
 
+use crate::build::remote_cache::RemoteCacheMode;
+use crate::build::report::Report;
+use crate::build::timings::TimingsFormat;
+
 fn parse_regex(s: &str) -> Result<Regex, regex::Error> {
     Regex::new(s)
 }
@@ -202,6 +206,60 @@ pub struct WarnErrorArg {
     pub warn_error: Option<String>,
 }
 
+#[derive(Args, Debug, Clone)]
+pub struct JsonArg {
+    /// Print build events as newline-delimited JSON on stdout instead of human-readable output.
+    /// Logs and progress are written to stderr, so stdout only contains one JSON object per line.
+    #[arg(long, default_value_t = false)]
+    pub json: bool,
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct KeepGoingArg {
+    /// Keep compiling after errors: every module whose dependencies succeeded is still compiled,
+    /// only the modules that depend on a module that failed to parse or compile are skipped.
+    #[arg(long, default_value_t = false)]
+    pub keep_going: bool,
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct CacheArgs {
+    /// Restore compiler outputs from a shared cache in <DIR>, and store new ones in it. The cache
+    /// can be shared by checkouts at the same location, e.g. CI jobs.
+    #[arg(long, env = "RESCRIPT_CACHE_DIR", value_name = "DIR")]
+    pub cache_dir: Option<PathBuf>,
+
+    /// Restore compiler outputs from a remote HTTP cache, with `GET <URL>/<key>` (and store new
+    /// ones with `PUT <URL>/<key>`). Only http:// urls are supported. When the server can't be
+    /// reached, the build continues without it.
+    #[arg(long, env = "RESCRIPT_REMOTE_CACHE", value_name = "URL")]
+    pub remote_cache: Option<String>,
+
+    /// Whether to only restore compiler outputs from the remote cache, or also upload new ones.
+    #[arg(
+        long,
+        value_enum,
+        env = "RESCRIPT_REMOTE_CACHE_MODE",
+        default_value_t = RemoteCacheMode::ReadWrite
+    )]
+    pub remote_cache_mode: RemoteCacheMode,
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct JobsArg {
+    /// Maximum number of compiler processes to run at the same time. Defaults to the number of CPUs.
+    #[arg(short, long, env = "RESCRIPT_JOBS", value_name = "N")]
+    pub jobs: Option<NonZeroUsize>,
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct TraceArg {
+    /// Write a trace of the internal build phases and all bsc invocations to <FILE>, in the
+    /// Chrome Trace Event Format. Open it in https://ui.perfetto.dev or chrome://tracing.
+    #[arg(long, value_name = "FILE")]
+    pub trace: Option<PathBuf>,
+}
+
 #[derive(Args, Debug, Clone)]
 pub struct BuildArgs {
     #[command(flatten)]
@@ -216,9 +274,48 @@ pub struct BuildArgs {
     #[command(flatten)]
     pub warn_error: WarnErrorArg,
 
+    #[command(flatten)]
+    pub json: JsonArg,
+
+    #[command(flatten)]
+    pub trace: TraceArg,
+
+    #[command(flatten)]
+    pub jobs: JobsArg,
+
+    #[command(flatten)]
+    pub keep_going: KeepGoingArg,
+
+    #[command(flatten)]
+    pub cache: CacheArgs,
+
+    /// Write a report of the build to a file, in the form <format>=<path>. Can be repeated.
+    /// Supported formats: `sarif` (all parse and compile diagnostics as SARIF 2.1).
+    /// E.g., --report sarif=rescript.sarif
+    #[arg(long, value_name = "FORMAT=PATH")]
+    pub report: Vec<Report>,
+
+    /// Write a timeline of all bsc invocations to lib/bs/rescript-timings.html (and/or .json),
+    /// including the critical path of the build. E.g., --timings or --timings=html,json
+    #[arg(
+        long,
+        value_enum,
+        value_name = "FORMATS",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "html",
+        value_delimiter = ','
+    )]
+    pub timings: Vec<TimingsFormat>,
+
     /// Disable output timing
     #[arg(short, long, default_value_t = false, num_args = 0..=1)]
     pub no_timing: bool,
+
+    /// Wait for a running build to finish instead of failing, e.g. in git hooks. Waits at most the
+    /// given number of seconds, or indefinitely without a value. E.g., --wait or --wait=60
+    #[arg(long, value_name = "SECONDS", num_args = 0..=1, require_equals = true)]
+    pub wait: Option<Option<u64>>,
 }
 
 #[cfg(test)]
@@ -307,6 +404,186 @@ mod tests {
         }
     }
 
+    #[test]
+    fn json_flag_is_accepted_by_build_and_watch() {
+        let cli = parse(&["rescript", "build", "--json"]).expect("expected build command");
+        match cli.command {
+            Command::Build(build_args) => assert!(*build_args.json),
+            other => panic!("expected build command, got {other:?}"),
+        }
+
+        let cli = parse(&["rescript", "watch", "--json"]).expect("expected watch command");
+        match cli.command {
+            Command::Watch(watch_args) => assert!(*watch_args.json),
+            other => panic!("expected watch command, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn trace_option_is_accepted_by_build_and_watch() {
+        let cli = parse(&["rescript", "build", "--trace", "trace.json"]).expect("expected build command");
+        match cli.command {
+            Command::Build(build_args) => assert_eq!(*build_args.trace, Some(PathBuf::from("trace.json"))),
+            other => panic!("expected build command, got {other:?}"),
+        }
+
+        let cli = parse(&["rescript", "watch", "--trace", "trace.json"]).expect("expected watch command");
+        match cli.command {
+            Command::Watch(watch_args) => assert_eq!(*watch_args.trace, Some(PathBuf::from("trace.json"))),
+            other => panic!("expected watch command, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn jobs_option_is_accepted_by_build_watch_and_format() {
+        let cli = parse(&["rescript", "build", "-j", "2"]).expect("expected build command");
+        match cli.command {
+            Command::Build(build_args) => assert_eq!(*build_args.jobs, NonZeroUsize::new(2)),
+            other => panic!("expected build command, got {other:?}"),
+        }
+
+        let cli = parse(&["rescript", "watch", "--jobs", "3"]).expect("expected watch command");
+        match cli.command {
+            Command::Watch(watch_args) => assert_eq!(*watch_args.jobs, NonZeroUsize::new(3)),
+            other => panic!("expected watch command, got {other:?}"),
+        }
+
+        let cli = parse(&["rescript", "format", "-j", "1"]).expect("expected format command");
+        match cli.command {
+            Command::Format { jobs, .. } => assert_eq!(*jobs, NonZeroUsize::new(1)),
+            other => panic!("expected format command, got {other:?}"),
+        }
+
+        let err = parse(&["rescript", "build", "-j", "0"]).expect_err("expected parse failure");
+        assert_eq!(err.kind(), ErrorKind::ValueValidation);
+    }
+
+    #[test]
+    fn only_builds_without_build_options_are_delegated() {
+        let delegates = |args: &[&str]| match parse(args).expect("expected build command").command {
+            Command::Build(build_args) => build_args.can_delegate_to_watcher(),
+            other => panic!("expected build command, got {other:?}"),
+        };
+
+        assert!(delegates(&["rescript", "build"]));
+        assert!(delegates(&["rescript", "build", "--no-timing", "-j", "2"]));
+        assert!(!delegates(&["rescript", "build", "--filter", "Foo"]));
+        assert!(!delegates(&["rescript", "build", "--warn-error", "+3"]));
+        assert!(!delegates(&["rescript", "build", "--keep-going"]));
+        assert!(!delegates(&["rescript", "build", "--json"]));
+        assert!(!delegates(&["rescript", "build", "--report", "sarif=out.sarif"]));
+        assert!(!delegates(&["rescript", "build", "--timings"]));
+        assert!(!delegates(&["rescript", "build", "--trace", "trace.json"]));
+        assert!(!delegates(&["rescript", "build", "--cache-dir", "/tmp/cache"]));
+        assert!(!delegates(&[
+            "rescript",
+            "build",
+            "--remote-cache",
+            "http://cache"
+        ]));
+    }
+
+    #[test]
+    fn keep_going_flag_is_accepted_by_build_and_watch() {
+        let cli = parse(&["rescript", "build", "--keep-going"]).expect("expected build command");
+        match cli.command {
+            Command::Build(build_args) => assert!(*build_args.keep_going),
+            other => panic!("expected build command, got {other:?}"),
+        }
+
+        let cli = parse(&["rescript", "watch", "--keep-going"]).expect("expected watch command");
+        match cli.command {
+            Command::Watch(watch_args) => assert!(*watch_args.keep_going),
+            other => panic!("expected watch command, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn cache_dir_is_accepted_by_build_and_watch() {
+        let cli = parse(&["rescript", "build", "--cache-dir", "/tmp/rescript-cache"])
+            .expect("expected build command");
+        match cli.command {
+            Command::Build(build_args) => {
+                assert_eq!(
+                    build_args.cache.cache_dir,
+                    Some(PathBuf::from("/tmp/rescript-cache"))
+                );
+                assert_eq!(build_args.cache.remote_cache, None);
+                assert_eq!(build_args.cache.remote_cache_mode, RemoteCacheMode::ReadWrite);
+            }
+            other => panic!("expected build command, got {other:?}"),
+        }
+
+        let cli = parse(&["rescript", "watch", "--cache-dir", "/tmp/rescript-cache"])
+            .expect("expected watch command");
+        match cli.command {
+            Command::Watch(watch_args) => {
+                assert_eq!(
+                    watch_args.cache.cache_dir,
+                    Some(PathBuf::from("/tmp/rescript-cache"))
+                )
+            }
+            other => panic!("expected watch command, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn remote_cache_options_are_parsed() {
+        let cli = parse(&[
+            "rescript",
+            "build",
+            "--remote-cache",
+            "http://localhost:8080/cache",
+            "--remote-cache-mode",
+            "read-only",
+        ])
+        .expect("expected build command");
+        match cli.command {
+            Command::Build(build_args) => {
+                assert_eq!(
+                    build_args.cache.remote_cache.as_deref(),
+                    Some("http://localhost:8080/cache")
+                );
+                assert_eq!(build_args.cache.remote_cache_mode, RemoteCacheMode::ReadOnly);
+            }
+            other => panic!("expected build command, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn report_option_is_parsed() {
+        let cli =
+            parse(&["rescript", "build", "--report", "sarif=out.sarif"]).expect("expected build command");
+        match cli.command {
+            Command::Build(build_args) => assert_eq!(build_args.report[0].path.to_str(), Some("out.sarif")),
+            other => panic!("expected build command, got {other:?}"),
+        }
+
+        let err =
+            parse(&["rescript", "build", "--report", "html=out.html"]).expect_err("expected parse failure");
+        assert_eq!(err.kind(), ErrorKind::ValueValidation);
+    }
+
+    #[test]
+    fn timings_option_defaults_to_html() {
+        let cli = parse(&["rescript", "build", "--timings", "my-project"]).expect("expected build command");
+        match cli.command {
+            Command::Build(build_args) => {
+                assert_eq!(build_args.timings, vec![TimingsFormat::Html]);
+                assert_eq!(build_args.folder.folder, "my-project");
+            }
+            other => panic!("expected build command, got {other:?}"),
+        }
+
+        let cli = parse(&["rescript", "build", "--timings=html,json"]).expect("expected build command");
+        match cli.command {
+            Command::Build(build_args) => {
+                assert_eq!(build_args.timings, vec![TimingsFormat::Html, TimingsFormat::Json])
+            }
+            other => panic!("expected build command, got {other:?}"),
+        }
+    }
+
     // Subcommand flag handling.
     #[test]
     fn respects_global_flag_before_subcommand() {
@@ -315,6 +592,86 @@ mod tests {
         assert!(matches!(cli.command, Command::Watch(_)));
     }
 
+    #[test]
+    fn poll_accepts_an_optional_interval() {
+        let cli = parse(&["rescript", "watch", "--poll", "my-project"]).expect("expected watch command");
+        match cli.command {
+            Command::Watch(watch_args) => {
+                assert_eq!(watch_args.poll, Some(None));
+                assert_eq!(watch_args.folder.folder, "my-project");
+            }
+            other => panic!("expected watch command, got {other:?}"),
+        }
+
+        let cli = parse(&["rescript", "daemon", "--poll=500"]).expect("expected daemon command");
+        match cli.command {
+            Command::Daemon(watch_args) => assert_eq!(watch_args.poll, Some(Some(500))),
+            other => panic!("expected daemon command, got {other:?}"),
+        }
+
+        assert!(parse(&["rescript", "watch", "--poll=0"]).is_err());
+    }
+
+    #[test]
+    fn watch_accepts_a_debounce_window() {
+        let cli = parse(&["rescript", "watch", "--debounce", "200", "--wait-for-quiet"])
+            .expect("expected watch command");
+        match cli.command {
+            Command::Watch(watch_args) => {
+                assert_eq!(watch_args.debounce, Some(200));
+                assert_eq!(watch_args.wait_for_quiet, Some(true));
+            }
+            other => panic!("expected watch command, got {other:?}"),
+        }
+
+        let cli = parse(&["rescript", "watch", "--wait-for-quiet=false"]).expect("expected watch command");
+        match cli.command {
+            Command::Watch(watch_args) => assert_eq!(watch_args.wait_for_quiet, Some(false)),
+            other => panic!("expected watch command, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn wait_accepts_an_optional_timeout() {
+        let cli = parse(&["rescript", "build", "--wait", "my-project"]).expect("expected build command");
+        match cli.command {
+            Command::Build(build_args) => {
+                assert_eq!(build_args.wait, Some(None));
+                assert_eq!(build_args.folder.folder, "my-project");
+            }
+            other => panic!("expected build command, got {other:?}"),
+        }
+
+        let cli = parse(&["rescript", "--wait=60"]).expect("expected build command");
+        match cli.command {
+            Command::Build(build_args) => assert_eq!(build_args.wait, Some(Some(60))),
+            other => panic!("expected build command, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn daemon_accepts_watch_arguments() {
+        let cli =
+            parse(&["rescript", "daemon", "my-project", "--filter", "Foo"]).expect("expected daemon command");
+
+        match cli.command {
+            Command::Daemon(watch_args) => {
+                assert_eq!(watch_args.folder.folder, "my-project");
+                assert!(watch_args.filter.is_some());
+            }
+            other => panic!("expected daemon command, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn status_and_stop_accept_a_folder() {
+        let cli = parse(&["rescript", "status", "my-project"]).expect("expected status command");
+        assert!(matches!(cli.command, Command::Status { folder } if folder.folder == "my-project"));
+
+        let cli = parse(&["rescript", "stop"]).expect("expected stop command");
+        assert!(matches!(cli.command, Command::Stop { folder } if folder.folder == "."));
+    }
+
     #[test]
     fn invalid_option_for_subcommand_does_not_fallback() {
         let err = parse(&["rescript", "watch", "--no-timing"]).expect_err("expected watch parse failure");
@@ -372,6 +729,43 @@ pub struct WatchArgs {
 
     #[command(flatten)]
     pub warn_error: WarnErrorArg,
+
+    #[command(flatten)]
+    pub json: JsonArg,
+
+    #[command(flatten)]
+    pub trace: TraceArg,
+
+    #[command(flatten)]
+    pub jobs: JobsArg,
+
+    #[command(flatten)]
+    pub keep_going: KeepGoingArg,
+
+    #[command(flatten)]
+    pub cache: CacheArgs,
+
+    /// Poll for changes instead of relying on file system events, e.g. in Docker bind mounts, on
+    /// network file systems or in shared folders of VMs. Polls every given number of milliseconds,
+    /// or every second without a value. E.g., --poll or --poll=500
+    #[arg(
+        long,
+        value_name = "MILLISECONDS",
+        num_args = 0..=1,
+        require_equals = true,
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    pub poll: Option<Option<u64>>,
+
+    /// How long to wait for more changes after a change before building, in milliseconds (50 by
+    /// default). Changes that arrive in the meantime are built together.
+    #[arg(long, value_name = "MILLISECONDS")]
+    pub debounce: Option<u64>,
+
+    /// Only build when no changes happened for the debounce window, e.g. while code generators or
+    /// formatters rewrite many files. E.g., --wait-for-quiet or --wait-for-quiet=false
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+    pub wait_for_quiet: Option<bool>,
 }
 
 impl From<BuildArgs> for WatchArgs {
@@ -381,6 +775,14 @@ impl From<BuildArgs> for WatchArgs {
             filter: build_args.filter,
             after_build: build_args.after_build,
             warn_error: build_args.warn_error,
+            json: build_args.json,
+            trace: build_args.trace,
+            jobs: build_args.jobs,
+            keep_going: build_args.keep_going,
+            cache: build_args.cache,
+            poll: None,
+            debounce: None,
+            wait_for_quiet: None,
         }
     }
 }
@@ -391,6 +793,19 @@ pub enum Command {
     Build(BuildArgs),
     /// Build, then start a watcher
     Watch(WatchArgs),
+    /// Start a watcher without progress output, that `build` and `compiler-args` delegate to
+    /// through `lib/rescript.sock`
+    Daemon(WatchArgs),
+    /// Show whether a watcher is running, and the result of its last build
+    Status {
+        #[command(flatten)]
+        folder: FolderArg,
+    },
+    /// Stop a running watcher
+    Stop {
+        #[command(flatten)]
+        folder: FolderArg,
+    },
     /// Clean the build artifacts
     Clean {
         #[command(flatten)]
@@ -415,6 +830,9 @@ pub enum Command {
         /// Files to format. If no files are provided, all files are formatted.
         #[arg(group = "format_input_mode")]
         files: Vec<String>,
+
+        #[command(flatten)]
+        jobs: JobsArg,
     },
     /// Print the compiler arguments for a ReScript source file.
     CompilerArgs {
@@ -424,6 +842,22 @@ pub enum Command {
     },
 }
 
+impl BuildArgs {
+    /// Whether a running watcher can do this build instead. It builds with its own options, so
+    /// builds with options that change the build or its outputs have to wait for the lock.
+    pub fn can_delegate_to_watcher(&self) -> bool {
+        self.filter.is_none()
+            && self.warn_error.is_none()
+            && !*self.keep_going
+            && !*self.json
+            && self.trace.is_none()
+            && self.cache.cache_dir.is_none()
+            && self.cache.remote_cache.is_none()
+            && self.report.is_empty()
+            && self.timings.is_empty()
+    }
+}
+
 impl Deref for FolderArg {
     type Target = str;
 
@@ -455,3 +889,35 @@ impl Deref for WarnErrorArg {
         &self.warn_error
     }
 }
+
+impl Deref for JsonArg {
+    type Target = bool;
+
+    fn deref(&self) -> &Self::Target {
+        &self.json
+    }
+}
+
+impl Deref for KeepGoingArg {
+    type Target = bool;
+
+    fn deref(&self) -> &Self::Target {
+        &self.keep_going
+    }
+}
+
+impl Deref for JobsArg {
+    type Target = Option<NonZeroUsize>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.jobs
+    }
+}
+
+impl Deref for TraceArg {
+    type Target = Option<PathBuf>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.trace
+    }
+}
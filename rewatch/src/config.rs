@@ -9,6 +9,7 @@ use serde::{Deserialize, Deserializer};
 use std::collections::HashMap;
 use std::fs;
 use std::path::{MAIN_SEPARATOR, Path, PathBuf};
+use std::time::Duration;
 
 #[derive(Deserialize, Debug, Clone)]
 #[serde(untagged)]
@@ -25,12 +26,44 @@ pub enum Subdirs {
 }
 impl Eq for Subdirs {}
 
+/// The files of a source folder that are compiled: a list of file names, or a regex that file
+/// names have to match together with a list of file names that are excluded.
+#[derive(Deserialize, Debug, Clone, PartialEq, Hash)]
+#[serde(untagged)]
+pub enum SourceFiles {
+    List(Vec<String>),
+    Filter {
+        #[serde(rename = "slow-re")]
+        slow_re: Option<String>,
+        excludes: Option<Vec<String>>,
+    },
+}
+
+impl Eq for SourceFiles {}
+
+/// Files of a source folder that are generated by a rule of `generators`. The edge lists the
+/// outputs, a `:` and the inputs, relative to the source folder: `["Query.res", ":", "Query.graphql"]`.
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct BuildGenerator {
+    pub name: String,
+    pub edge: Vec<String>,
+}
+
+/// A command that generates files, `$in` and `$out` are replaced with the inputs and outputs.
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct RuleGenerator {
+    pub name: String,
+    pub command: String,
+}
+
 #[derive(Deserialize, Debug, Clone, PartialEq, Hash)]
 pub struct PackageSource {
     pub dir: String,
     pub subdirs: Option<Subdirs>,
     #[serde(rename = "type")]
     pub type_: Option<String>,
+    pub files: Option<SourceFiles>,
+    pub generators: Option<Vec<BuildGenerator>>,
 }
 
 impl PackageSource {
@@ -40,6 +73,50 @@ impl PackageSource {
             None => false,
         }
     }
+
+    pub fn get_file_filter(&self) -> FileFilter {
+        match &self.files {
+            // an empty list means that all files are compiled, like in bsb
+            None => FileFilter::default(),
+            Some(SourceFiles::List(files)) if files.is_empty() => FileFilter::default(),
+            Some(SourceFiles::List(files)) => FileFilter {
+                files: Some(files.to_owned()),
+                ..FileFilter::default()
+            },
+            Some(SourceFiles::Filter { slow_re, excludes }) => FileFilter {
+                files: None,
+                slow_re: slow_re.as_ref().map(|slow_re| {
+                    regex::Regex::new(slow_re).expect("slow-re is validated when reading the config")
+                }),
+                excludes: excludes.to_owned().unwrap_or_default(),
+            },
+        }
+    }
+}
+
+/// The `files` of a source folder, which decide whether a file in it is compiled by its name.
+#[derive(Debug, Clone, Default)]
+pub struct FileFilter {
+    files: Option<Vec<String>>,
+    slow_re: Option<regex::Regex>,
+    excludes: Vec<String>,
+}
+
+impl FileFilter {
+    pub fn is_empty(&self) -> bool {
+        self.files.is_none() && self.slow_re.is_none() && self.excludes.is_empty()
+    }
+
+    pub fn includes(&self, file_name: &str) -> bool {
+        self.files
+            .as_ref()
+            .is_none_or(|files| files.iter().any(|file| file == file_name))
+            && self
+                .slow_re
+                .as_ref()
+                .is_none_or(|slow_re| slow_re.is_match(file_name))
+            && !self.excludes.iter().any(|exclude| exclude == file_name)
+    }
 }
 
 impl Eq for PackageSource {}
@@ -65,6 +142,8 @@ impl Source {
                 dir: dir.to_string(),
                 subdirs: None,
                 type_: Some(type_),
+                files: None,
+                generators: None,
             }),
             (Source::Qualified(package_source), type_) => Source::Qualified(PackageSource {
                 type_,
@@ -87,11 +166,15 @@ impl Source {
                     .to_string(),
                 subdirs: None,
                 type_: self.get_type(),
+                files: None,
+                generators: None,
             },
             Source::Qualified(PackageSource {
                 dir,
                 type_,
                 subdirs: Some(Subdirs::Recurse(should_recurse)),
+                files,
+                generators,
             }) => PackageSource {
                 dir: sub_path
                     .map(|p| p.join(Path::new(dir)))
@@ -100,8 +183,16 @@ impl Source {
                     .to_string(),
                 subdirs: Some(Subdirs::Recurse(*should_recurse)),
                 type_: type_.to_owned(),
+                files: files.to_owned(),
+                generators: generators.to_owned(),
             },
-            Source::Qualified(PackageSource { dir, type_, .. }) => PackageSource {
+            Source::Qualified(PackageSource {
+                dir,
+                type_,
+                files,
+                generators,
+                ..
+            }) => PackageSource {
                 dir: sub_path
                     .map(|p| p.join(Path::new(dir)))
                     .unwrap_or(Path::new(dir).to_path_buf())
@@ -109,6 +200,8 @@ impl Source {
                     .to_string(),
                 subdirs: None,
                 type_: type_.to_owned(),
+                files: files.to_owned(),
+                generators: generators.to_owned(),
             },
         }
     }
@@ -243,6 +336,104 @@ pub struct JsPostBuild {
     pub cmd: String,
 }
 
+/// The interval at which the watcher polls for changes when polling is enabled without one.
+pub const DEFAULT_POLL_INTERVAL_MS: u64 = 1000;
+
+/// How long the watcher waits for more changes before it builds, by default.
+pub const DEFAULT_DEBOUNCE_MS: u64 = 50;
+
+/// Configuration of `rescript watch`, which is read from the root config.
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct WatchConfig {
+    /// Poll for changes instead of using the file system events of the OS, e.g. in Docker bind
+    /// mounts or on network file systems: `true`, or an interval in milliseconds.
+    pub poll: Option<WatchPoll>,
+    /// How long to wait for more changes after a change before building, in milliseconds.
+    pub debounce: Option<u64>,
+    /// Wait until no changes happened for the debounce window, instead of building when the window
+    /// after the first change ends.
+    #[serde(rename = "wait-for-quiet")]
+    pub wait_for_quiet: Option<bool>,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum WatchPoll {
+    Enabled(bool),
+    Interval(u64),
+}
+
+/// Directories of a package that are neither compiled nor watched, from `ignored-dirs`. An entry
+/// is the name of a directory (`__fixtures__`) or its path relative to the package (`src/vendor`),
+/// and can contain wildcards: `*` and `?` don't match a `/`, while `**` matches any number of
+/// directories. Entries with a `/` are matched against the path, the others against the name.
+#[derive(Debug, Clone, Default)]
+pub struct IgnoredDirs {
+    patterns: Vec<(regex::Regex, bool)>,
+}
+
+impl IgnoredDirs {
+    pub fn new(entries: &[String]) -> IgnoredDirs {
+        IgnoredDirs {
+            patterns: entries
+                .iter()
+                .map(|entry| entry.trim_start_matches("./").trim_matches('/'))
+                .filter(|entry| !entry.is_empty())
+                .map(|entry| (glob_to_regex(entry), entry.contains('/')))
+                .collect(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.patterns.is_empty()
+    }
+
+    /// Whether a directory, relative to the package, is ignored.
+    pub fn is_ignored(&self, dir: &Path) -> bool {
+        let components = dir
+            .components()
+            .filter_map(|component| match component {
+                std::path::Component::Normal(name) => name.to_str(),
+                _ => None,
+            })
+            .collect::<Vec<&str>>();
+        let Some(name) = components.last() else {
+            return false;
+        };
+        let path = components.join("/");
+        self.patterns.iter().any(|(pattern, is_path)| {
+            if *is_path {
+                pattern.is_match(&path)
+            } else {
+                pattern.is_match(name)
+            }
+        })
+    }
+}
+
+fn glob_to_regex(glob: &str) -> regex::Regex {
+    let mut regex = String::from("^");
+    let mut chars = glob.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                if chars.peek() == Some(&'/') {
+                    chars.next();
+                    regex.push_str("(?:.*/)?");
+                } else {
+                    regex.push_str(".*");
+                }
+            }
+            '*' => regex.push_str("[^/]*"),
+            '?' => regex.push_str("[^/]"),
+            c => regex.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    regex.push('$');
+    regex::Regex::new(&regex).expect("Could not create regex")
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
 pub enum DeprecationWarning {}
 
@@ -289,15 +480,24 @@ pub struct Config {
     // In the case of monorepos, the root source won't necessarily have to have sources. It can
     // just be sources in packages
     pub sources: Option<OneOrMore<Source>>,
+    #[serde(rename = "ignored-dirs")]
+    pub ignored_dirs: Option<Vec<String>>,
     #[serde(rename = "package-specs")]
     pub package_specs: Option<OneOrMore<PackageSpec>>,
     pub warnings: Option<Warnings>,
     pub suffix: Option<String>,
     pub dependencies: Option<Vec<String>>,
+    pub generators: Option<Vec<RuleGenerator>>,
+    #[serde(rename = "cut-generators")]
+    pub cut_generators: Option<bool>,
     #[serde(rename = "dev-dependencies")]
     pub dev_dependencies: Option<Vec<String>>,
+    #[serde(rename = "bs-external-includes")]
+    pub bs_external_includes: Option<Vec<String>>,
     #[serde(rename = "ppx-flags")]
     pub ppx_flags: Option<Vec<OneOrMore<String>>>,
+    #[serde(rename = "pp-flags")]
+    pub pp_flags: Option<String>,
 
     #[serde(rename = "compiler-flags")]
     pub compiler_flags: Option<Vec<OneOrMore<String>>>,
@@ -322,6 +522,7 @@ pub struct Config {
     // this is a new feature of rewatch, and it's not part of the rescript.json spec
     #[serde(rename = "allowed-dependents")]
     pub allowed_dependents: Option<Vec<String>>,
+    pub watch: Option<WatchConfig>,
 
     // Holds all deprecation warnings for the config struct
     #[serde(skip)]
@@ -418,6 +619,45 @@ pub fn flatten_ppx_flags(
     }
 }
 
+/// The command of the preprocessor in pp-flags. When its first word is a path it is resolved
+/// relative to the package (`./bin/pp.sh`) or in node_modules (`my-pp/bin/pp.exe`), otherwise the
+/// command is looked up in the PATH.
+pub fn get_pp_command(project_context: &ProjectContext, package_config: &Config) -> Result<Option<String>> {
+    let Some(pp_flags) = &package_config.pp_flags else {
+        return Ok(None);
+    };
+    let mut words = pp_flags.split_whitespace();
+    let Some(command) = words.next() else {
+        return Ok(None);
+    };
+    let command = if command.starts_with('.') {
+        package_config
+            .path
+            .parent()
+            .map(|package_dir| package_dir.join(command))
+            .ok_or_else(|| {
+                anyhow!(
+                    "Expected {} to have a parent folder",
+                    package_config.path.display()
+                )
+            })?
+            .to_string_lossy()
+            .to_string()
+    } else if command.contains('/') {
+        helpers::try_package_path(package_config, project_context, command)?
+            .to_string_lossy()
+            .to_string()
+    } else {
+        command.to_string()
+    };
+    Ok(Some(
+        std::iter::once(command)
+            .chain(words.map(String::from))
+            .collect::<Vec<_>>()
+            .join(" "),
+    ))
+}
+
 fn namespace_from_package_name(package_name: &str) -> String {
     let len = package_name.len();
     let mut buf = String::with_capacity(len);
@@ -478,6 +718,7 @@ impl Config {
                 })?;
 
         config.handle_deprecations()?;
+        config.validate_source_files()?;
         config.unknown_fields = unknown_fields;
 
         Ok(config)
@@ -641,6 +882,39 @@ impl Config {
         }
     }
 
+    /// The interval at which `rescript watch` polls for changes, when polling is configured.
+    pub fn get_poll_interval(&self) -> Option<Duration> {
+        match self.watch.as_ref()?.poll? {
+            WatchPoll::Enabled(true) => Some(Duration::from_millis(DEFAULT_POLL_INTERVAL_MS)),
+            WatchPoll::Enabled(false) => None,
+            // the schema requires at least 1, an interval of 0 would poll without pause
+            WatchPoll::Interval(interval) => Some(Duration::from_millis(interval.max(1))),
+        }
+    }
+
+    /// How long `rescript watch` waits for more changes before building, when configured.
+    pub fn get_debounce(&self) -> Option<Duration> {
+        self.watch.as_ref()?.debounce.map(Duration::from_millis)
+    }
+
+    pub fn get_wait_for_quiet(&self) -> Option<bool> {
+        self.watch.as_ref()?.wait_for_quiet
+    }
+
+    /// The absolute folders of bs-external-includes, relative folders are relative to the package.
+    pub fn get_external_includes(&self) -> Vec<PathBuf> {
+        let package_dir = self.path.parent().unwrap_or(Path::new("."));
+        self.bs_external_includes
+            .iter()
+            .flatten()
+            .map(|dir| helpers::get_abs_path(&package_dir.join(dir)))
+            .collect()
+    }
+
+    pub fn get_ignored_dirs(&self) -> IgnoredDirs {
+        IgnoredDirs::new(self.ignored_dirs.as_deref().unwrap_or_default())
+    }
+
     pub fn get_package_specs(&self) -> Vec<PackageSpec> {
         match self.package_specs.clone() {
             None => vec![PackageSpec {
@@ -720,14 +994,7 @@ impl Config {
     }
 
     fn is_unsupported_field(&self, field: &str) -> bool {
-        const UNSUPPORTED_TOP_LEVEL_FIELDS: &[&str] = &[
-            "ignored-dirs",
-            "generators",
-            "cut-generators",
-            "pp-flags",
-            "entries",
-            "bs-external-includes",
-        ];
+        const UNSUPPORTED_TOP_LEVEL_FIELDS: &[&str] = &["entries"];
 
         let top_level = field.split(|c| ['.', '['].contains(&c)).next().unwrap_or(field);
 
@@ -737,6 +1004,33 @@ impl Config {
     fn handle_deprecations(&mut self) -> Result<()> {
         Ok(())
     }
+
+    /// The `slow-re` of the sources is only compiled when the source folders are read, so it is
+    /// checked up front.
+    fn validate_source_files(&self) -> Result<()> {
+        fn validate(source: &Source) -> Result<()> {
+            if let Source::Qualified(package_source) = source {
+                if let Some(SourceFiles::Filter {
+                    slow_re: Some(slow_re),
+                    ..
+                }) = &package_source.files
+                {
+                    regex::Regex::new(slow_re)
+                        .map_err(|err| anyhow!("Invalid slow-re in the sources of rescript.json: {err}"))?;
+                }
+                if let Some(Subdirs::Qualified(subdirs)) = &package_source.subdirs {
+                    subdirs.iter().try_for_each(validate)?;
+                }
+            }
+            Ok(())
+        }
+
+        match &self.sources {
+            None => Ok(()),
+            Some(OneOrMore::Single(source)) => validate(source),
+            Some(OneOrMore::Multiple(sources)) => sources.iter().try_for_each(validate),
+        }
+    }
 }
 
 fn validate_package_specs_value(value: &serde_json::Value) -> Result<()> {
@@ -841,12 +1135,17 @@ pub mod tests {
             sources: Some(crate::config::OneOrMore::Single(Source::Shorthand(String::from(
                 "Source",
             )))),
+            ignored_dirs: None,
             package_specs: None,
             warnings: None,
             suffix: None,
             dependencies: Some(args.bs_deps),
+            generators: None,
+            cut_generators: None,
             dev_dependencies: Some(args.build_dev_deps),
+            bs_external_includes: None,
             ppx_flags: None,
+            pp_flags: None,
             compiler_flags: None,
             namespace: None,
             jsx: None,
@@ -858,11 +1157,27 @@ pub mod tests {
             deprecation_warnings: vec![],
             experimental_features: None,
             allowed_dependents: args.allowed_dependents,
+            watch: None,
             unknown_fields: vec![],
             path: args.path,
         }
     }
 
+    #[test]
+    fn test_get_external_includes() {
+        let mut config = Config::new_from_json_string(
+            r#"{"name": "test", "bs-external-includes": ["vendor/cmi", "/opt/cmi"]}"#,
+        )
+        .unwrap();
+        config.path = PathBuf::from("/project/rescript.json");
+
+        assert!(config.get_unsupported_fields().is_empty());
+        assert_eq!(
+            config.get_external_includes(),
+            vec![PathBuf::from("/project/vendor/cmi"), PathBuf::from("/opt/cmi")]
+        );
+    }
+
     #[test]
     fn test_getters() {
         let json = r#"
@@ -1229,12 +1544,12 @@ pub mod tests {
                 "dir": "src",
                 "subdirs": true
             },
-            "ignored-dirs": ["scripts"]
+            "entries": []
         }
         "#;
 
         let config = Config::new_from_json_string(json).expect("a valid json string");
-        assert_eq!(config.get_unsupported_fields(), vec!["ignored-dirs".to_string()]);
+        assert_eq!(config.get_unsupported_fields(), vec!["entries".to_string()]);
         assert!(config.get_unknown_fields().is_empty());
     }
 
@@ -1295,6 +1610,101 @@ pub mod tests {
         assert!(config.get_deprecations().is_empty());
     }
 
+    #[test]
+    fn test_get_poll_interval() {
+        let poll_interval = |json: &str| {
+            Config::new_from_json_string(json)
+                .expect("config should be read")
+                .get_poll_interval()
+        };
+        assert_eq!(poll_interval(r#"{"name": "test", "sources": "src"}"#), None);
+        assert_eq!(
+            poll_interval(r#"{"name": "test", "sources": "src", "watch": {"poll": true}}"#),
+            Some(Duration::from_millis(DEFAULT_POLL_INTERVAL_MS))
+        );
+        assert_eq!(
+            poll_interval(r#"{"name": "test", "sources": "src", "watch": {"poll": false}}"#),
+            None
+        );
+        assert_eq!(
+            poll_interval(r#"{"name": "test", "sources": "src", "watch": {"poll": 250}}"#),
+            Some(Duration::from_millis(250))
+        );
+    }
+
+    #[test]
+    fn test_get_debounce() {
+        let config = Config::new_from_json_string(
+            r#"{"name": "test", "sources": "src", "watch": {"debounce": 300, "wait-for-quiet": true}}"#,
+        )
+        .expect("config should be read");
+        assert_eq!(config.get_debounce(), Some(Duration::from_millis(300)));
+        assert_eq!(config.get_wait_for_quiet(), Some(true));
+        assert!(config.get_unknown_fields().is_empty());
+
+        let config = Config::new_from_json_string(r#"{"name": "test", "sources": "src", "watch": {}}"#)
+            .expect("config should be read");
+        assert_eq!(config.get_debounce(), None);
+        assert_eq!(config.get_wait_for_quiet(), None);
+    }
+
+    #[test]
+    fn test_get_ignored_dirs() {
+        let config = Config::new_from_json_string(
+            r#"{"name": "test", "sources": "src", "ignored-dirs": ["__fixtures__", "./src/vendor/", "gen-*", "src/**/tmp"]}"#,
+        )
+        .expect("config should be read");
+        assert!(config.get_unknown_fields().is_empty());
+        assert!(config.get_unsupported_fields().is_empty());
+
+        let ignored_dirs = config.get_ignored_dirs();
+        assert!(ignored_dirs.is_ignored(Path::new("src/__fixtures__")));
+        assert!(ignored_dirs.is_ignored(Path::new("./src/a/__fixtures__")));
+        assert!(ignored_dirs.is_ignored(Path::new("src/vendor")));
+        assert!(!ignored_dirs.is_ignored(Path::new("lib/src/vendor")));
+        assert!(ignored_dirs.is_ignored(Path::new("src/gen-api")));
+        assert!(ignored_dirs.is_ignored(Path::new("src/tmp")));
+        assert!(ignored_dirs.is_ignored(Path::new("src/a/b/tmp")));
+        assert!(!ignored_dirs.is_ignored(Path::new("test/tmp")));
+        assert!(!ignored_dirs.is_ignored(Path::new("src/fixtures")));
+        assert!(!ignored_dirs.is_ignored(Path::new("src/gen")));
+        assert!(Config::default().get_ignored_dirs().is_empty());
+    }
+
+    #[test]
+    fn test_source_files() {
+        let config = Config::new_from_json_string(
+            r#"{
+                "name": "test",
+                "sources": [
+                    {"dir": "src", "files": {"slow-re": "\\.res$", "excludes": ["Skip.res"]}},
+                    {"dir": "list", "files": ["A.res"]},
+                    {"dir": "all", "files": []}
+                ]
+            }"#,
+        )
+        .expect("config should be read");
+        assert!(config.get_unknown_fields().is_empty());
+        let Some(OneOrMore::Multiple(sources)) = &config.sources else {
+            panic!("sources should be read")
+        };
+        let filters = sources
+            .iter()
+            .map(|source| source.to_qualified_without_children(None).get_file_filter())
+            .collect::<Vec<_>>();
+        assert!(filters[0].includes("Main.res"));
+        assert!(!filters[0].includes("Main.resi"));
+        assert!(!filters[0].includes("Skip.res"));
+        assert!(filters[1].includes("A.res"));
+        assert!(!filters[1].includes("B.res"));
+        assert!(filters[2].is_empty());
+
+        let invalid = Config::new_from_json_string(
+            r#"{"name": "test", "sources": {"dir": "src", "files": {"slow-re": "("}}}"#,
+        );
+        assert!(invalid.is_err());
+    }
+
     fn test_find_is_type_dev(source: OneOrMore<Source>, path: &Path, expected: bool) {
         let config = Config {
             name: String::from("testrepo"),
@@ -1312,6 +1722,8 @@ pub mod tests {
                 dir: String::from("src"),
                 subdirs: None,
                 type_: Some(String::from("dev")),
+                files: None,
+                generators: None,
             })),
             Path::new("src/Foo.res"),
             true,
@@ -1325,6 +1737,8 @@ pub mod tests {
                 dir: String::from("src"),
                 subdirs: None,
                 type_: None,
+                files: None,
+                generators: None,
             })),
             Path::new("src/Foo.res"),
             false,
@@ -1338,6 +1752,8 @@ pub mod tests {
                 dir: String::from("src"),
                 subdirs: None,
                 type_: Some(String::from("dev")),
+                files: None,
+                generators: None,
             })]),
             Path::new("src/Foo.res"),
             true,
@@ -1360,6 +1776,8 @@ pub mod tests {
                 dir: String::from("src"),
                 subdirs: Some(Subdirs::Recurse(true)),
                 type_: Some(String::from("dev")),
+                files: None,
+                generators: None,
             })]),
             Path::new("src/bar/Foo.res"),
             true,
@@ -1375,8 +1793,12 @@ pub mod tests {
                     dir: String::from("bar"),
                     subdirs: None,
                     type_: None,
+                    files: None,
+                    generators: None,
                 })])),
                 type_: Some(String::from("dev")),
+                files: None,
+                generators: None,
             })]),
             Path::new("src/bar/Foo.res"),
             true,
@@ -1390,6 +1812,8 @@ pub mod tests {
                 dir: String::from("src"),
                 subdirs: Some(Subdirs::Qualified(vec![Source::Shorthand(String::from("bar"))])),
                 type_: Some(String::from("dev")),
+                files: None,
+                generators: None,
             })]),
             Path::new("src/bar/Foo.res"),
             true,
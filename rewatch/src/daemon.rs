@@ -0,0 +1,482 @@
+//! The socket of a running watcher (`rescript watch` or `rescript daemon`) in
+//! `lib/rescript.sock`, through which other processes use its warm build state instead of
+//! building the project from scratch.
+//!
+//! The protocol is one JSON request per connection, on a single line, answered with one JSON
+//! response line:
+//!
+//! ```text
+//! {"method":"build"}
+//! {"method":"compiler-args","path":"/abs/path/to/src/File.res"}
+//! {"method":"status"}
+//! {"method":"stop"}
+//! ```
+//!
+//! A build request is answered when the watcher finished building all changes it has seen, with
+//! `{"success":bool,"output":"<rendered errors and warnings>"}`. A compiler-args request is
+//! answered right away with the same output as `rescript compiler-args`. A status request is
+//! answered with a `status` object as well, and a stop request makes the watcher clean up and exit.
+
+use crate::build;
+use crate::build::build_types::BuildCommandState;
+use crate::helpers;
+use crate::lock;
+use crate::queue::{FifoQueue, Queue};
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, mpsc};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub static SOCKET: &str = "rescript.sock";
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "method", rename_all = "kebab-case")]
+pub enum Request {
+    Build,
+    CompilerArgs { path: PathBuf },
+    Status,
+    Stop,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Response {
+    pub success: bool,
+    pub output: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<Status>,
+}
+
+impl Response {
+    pub fn new(success: bool, output: String) -> Self {
+        Self {
+            success,
+            output,
+            status: None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Status {
+    pub pid: u32,
+    /// Seconds since the Unix epoch
+    pub started_at: u64,
+    pub folder: PathBuf,
+    /// `None` while the initial build is running
+    pub last_build_success: Option<bool>,
+    /// The `--filter` of the watcher, unless it was turned off
+    #[serde(default)]
+    pub filter: Option<String>,
+    /// The `--warn-error` of the watcher
+    #[serde(default)]
+    pub warn_error: Option<String>,
+}
+
+impl Status {
+    /// Whether the builds of the watcher are the ones of a plain `rescript build`.
+    pub fn builds_everything(&self) -> bool {
+        self.filter.is_none() && self.warn_error.is_none()
+    }
+}
+
+/// A request that the watcher still has to answer.
+pub struct PendingRequest {
+    pub request: Request,
+    pub reply: mpsc::Sender<Response>,
+}
+
+pub type Requests = Arc<FifoQueue<PendingRequest>>;
+
+pub fn get_socket_path(folder: &Path) -> PathBuf {
+    folder.join("lib").join(SOCKET)
+}
+
+/// The result of the last build of the watcher, with all errors and warnings of its modules.
+pub fn build_response(build_state: &BuildCommandState, success: bool) -> Response {
+    let mut modules = build_state.modules.iter().collect::<Vec<_>>();
+    modules.sort_by_key(|(module_name, _)| *module_name);
+    let output = build_state
+        .build_error
+        .iter()
+        .map(String::as_str)
+        .chain(
+            modules
+                .into_iter()
+                .flat_map(|(_, module)| {
+                    module
+                        .parse_diagnostics
+                        .iter()
+                        .chain(module.compile_diagnostics.iter())
+                })
+                .map(|diagnostic| diagnostic.rendered.as_str()),
+        )
+        .collect::<Vec<&str>>()
+        .concat();
+    Response::new(success, output)
+}
+
+/// The status of the watcher, with the errors and warnings of its last build as output.
+pub fn status_response(
+    build_state: &BuildCommandState,
+    folder: &Path,
+    started_at: SystemTime,
+    last_build_success: Option<bool>,
+    filter: &Option<regex::Regex>,
+) -> Response {
+    Response {
+        status: Some(Status {
+            pid: std::process::id(),
+            started_at: started_at
+                .duration_since(UNIX_EPOCH)
+                .map(|duration| duration.as_secs())
+                .unwrap_or_default(),
+            folder: helpers::get_abs_path(folder),
+            last_build_success,
+            filter: filter.as_ref().map(|regex| regex.to_string()),
+            warn_error: build_state.get_warn_error_override(),
+        }),
+        ..build_response(build_state, true)
+    }
+}
+
+/// Answers a compiler-args request, with the project context of the watcher when the file belongs
+/// to the package it builds.
+pub fn compiler_args_response(build_state: &BuildCommandState, path: &Path) -> Response {
+    match get_compiler_args(build_state, path) {
+        Ok(output) => Response::new(true, output),
+        Err(err) => Response::new(false, format!("{err:#}")),
+    }
+}
+
+fn get_compiler_args(build_state: &BuildCommandState, path: &Path) -> Result<String> {
+    if !path.is_file() {
+        return Err(anyhow!("{} is not a file", path.display()));
+    }
+    let current_package = build::get_package_of_file(path)?;
+    let watched_package = build_state
+        .project_context
+        .current_config
+        .path
+        .parent()
+        .map(helpers::get_abs_path);
+    if watched_package.as_deref() == Some(current_package.as_path()) {
+        build::get_compiler_args_in_context(&build_state.project_context, &current_package, path)
+    } else {
+        build::get_compiler_args(path)
+    }
+}
+
+#[cfg(unix)]
+mod unix {
+    use super::*;
+    use std::io::{BufRead, BufReader, Write};
+    use std::os::unix::net::{UnixListener, UnixStream};
+
+    fn write_response(mut stream: &UnixStream, response: &Response) -> std::io::Result<()> {
+        let mut line = serde_json::to_string(response)?;
+        line.push('\n');
+        stream.write_all(line.as_bytes())
+    }
+
+    fn handle(stream: UnixStream, requests: &Requests, cancel: &AtomicBool) -> std::io::Result<()> {
+        let mut line = String::new();
+        BufReader::new(&stream).read_line(&mut line)?;
+        let request = match serde_json::from_str::<Request>(&line) {
+            Ok(request) => request,
+            Err(err) => {
+                return write_response(&stream, &Response::new(false, format!("Invalid request: {err}")));
+            }
+        };
+        // a running build is cut short, the watcher only answers between builds
+        if request == Request::Stop {
+            cancel.store(true, Ordering::Relaxed);
+        }
+        let (reply, response) = mpsc::channel();
+        requests.push(PendingRequest { request, reply });
+        match response.recv() {
+            Ok(response) => write_response(&stream, &response),
+            // the watcher exited before it could answer
+            Err(_) => Ok(()),
+        }
+    }
+
+    pub fn serve(folder: &Path, cancel: Arc<AtomicBool>) -> Result<Requests> {
+        let socket_path = get_socket_path(folder);
+        // we hold the lock, so a socket that is still there is from a watcher that crashed
+        let _ = std::fs::remove_file(&socket_path);
+        let listener = UnixListener::bind(&socket_path)
+            .map_err(|e| anyhow!("Could not listen on {}: {e}", socket_path.to_string_lossy()))?;
+        let requests: Requests = Arc::new(FifoQueue::new());
+        let server_requests = requests.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let requests = server_requests.clone();
+                let cancel = cancel.clone();
+                std::thread::spawn(move || {
+                    if let Err(err) = handle(stream, &requests, &cancel) {
+                        log::debug!("Could not answer a request on the watcher socket: {err}");
+                    }
+                });
+            }
+        });
+        Ok(requests)
+    }
+
+    pub fn request(folder: &Path, request: &Request) -> Result<Response> {
+        let stream = UnixStream::connect(get_socket_path(folder))?;
+        let mut line = serde_json::to_string(request)?;
+        line.push('\n');
+        (&stream).write_all(line.as_bytes())?;
+        let mut response = String::new();
+        BufReader::new(&stream).read_line(&mut response)?;
+        Ok(serde_json::from_str(&response)?)
+    }
+}
+
+/// Starts listening for requests on the socket of the watcher of `folder`. A stop request sets
+/// `cancel`, so the watcher doesn't finish the build it is running first.
+pub fn serve(folder: &Path, cancel: Arc<AtomicBool>) -> Result<Requests> {
+    #[cfg(unix)]
+    return unix::serve(folder, cancel);
+    #[cfg(not(unix))]
+    {
+        drop(cancel);
+        Err(anyhow!(
+            "The watcher socket is only supported on Unix, {folder:?} is not served"
+        ))
+    }
+}
+
+/// Sends a request to the watcher of `folder`, fails when no watcher is listening.
+pub fn request(folder: &Path, request: &Request) -> Result<Response> {
+    #[cfg(unix)]
+    return unix::request(folder, request);
+    #[cfg(not(unix))]
+    return Err(anyhow!(
+        "The watcher socket is only supported on Unix, can't send {request:?} to {folder:?}"
+    ));
+}
+
+/// Lets the watcher of `folder` build, fails when none is listening or when its builds differ from a
+/// plain `rescript build`, because of a filter or a warn-error override.
+pub fn request_build(folder: &Path) -> Result<Response> {
+    match request(folder, &Request::Status)?.status {
+        Some(status) if status.builds_everything() => request(folder, &Request::Build),
+        _ => Err(anyhow!("The watcher of {folder:?} builds with other options")),
+    }
+}
+
+pub fn stop_serving(folder: &Path) {
+    let _ = std::fs::remove_file(get_socket_path(folder));
+}
+
+/// Prints the status of the watcher of `folder`, fails when none is running.
+pub fn status(folder: &str) -> Result<()> {
+    match request(Path::new(folder), &Request::Status) {
+        Ok(Response {
+            output,
+            status: Some(status),
+            ..
+        }) => {
+            let last_build = match status.last_build_success {
+                None => "running",
+                Some(true) => "succeeded",
+                Some(false) => "failed",
+            };
+            println!("Watcher running for {}", status.folder.display());
+            println!("  PID:         {}", status.pid);
+            println!(
+                "  Running for: {}",
+                helpers::format_elapsed_since(status.started_at)
+            );
+            println!("  Last build:  {last_build}");
+            if let Some(filter) = &status.filter {
+                println!("  Filter:      {filter}");
+            }
+            if let Some(warn_error) = &status.warn_error {
+                println!("  Warn error:  {warn_error}");
+            }
+            if !output.is_empty() {
+                println!("\n{output}");
+            }
+            Ok(())
+        }
+        _ => match lock::get_running_owner(folder) {
+            Some(owner) => {
+                println!("{owner}, but it does not answer status requests");
+                Ok(())
+            }
+            None => Err(anyhow!("No ReScript watcher is running for {folder}")),
+        },
+    }
+}
+
+/// Stops the watcher of `folder` and waits until it exited. Watchers that don't listen on the
+/// socket are stopped by removing the lockfile. Builds and cleans holding the lock, and watchers on
+/// other hosts, are left alone.
+pub fn stop(folder: &str) -> Result<()> {
+    let Some(owner) = lock::get_running_owner(folder) else {
+        println!("No ReScript watcher is running for {folder}");
+        return Ok(());
+    };
+    if !matches!(owner.command, Some(lock::Command::Watch | lock::Command::Daemon)) {
+        return Err(anyhow!("{owner}, which is not a watcher that can be stopped"));
+    }
+    if owner.is_foreign() {
+        return Err(anyhow!(
+            "{owner}. A watcher on another host can't be stopped from here"
+        ));
+    }
+    let pid = owner.pid;
+    // the watcher can exit before its answer is written, so we only wait for the process
+    if request(Path::new(folder), &Request::Stop).is_err() {
+        lock::remove(folder);
+    }
+    for _ in 0..100 {
+        if !lock::pid_exists(pid) {
+            println!("Stopped the ReScript watcher (PID {pid})");
+            return Ok(());
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    }
+    Err(anyhow!("The ReScript watcher (PID {pid}) did not stop"))
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn answers_requests_through_the_socket() {
+        let temp_dir = TempDir::new().expect("temp dir should be created");
+        std::fs::create_dir(temp_dir.path().join("lib")).unwrap();
+        let requests = serve(temp_dir.path(), Arc::default()).expect("should listen on the socket");
+
+        // the watch loop
+        std::thread::spawn(move || {
+            let pending = requests.pop();
+            assert_eq!(pending.request, Request::Build);
+            pending
+                .reply
+                .send(Response::new(false, "error".to_string()))
+                .unwrap();
+        });
+
+        let response = request(temp_dir.path(), &Request::Build).expect("should get a response");
+        assert_eq!(response, Response::new(false, "error".to_string()));
+        stop_serving(temp_dir.path());
+        assert!(request(temp_dir.path(), &Request::Build).is_err());
+    }
+
+    #[test]
+    fn serializes_requests() {
+        assert_eq!(
+            serde_json::to_string(&Request::CompilerArgs {
+                path: PathBuf::from("/project/src/Main.res")
+            })
+            .unwrap(),
+            r#"{"method":"compiler-args","path":"/project/src/Main.res"}"#
+        );
+        assert_eq!(
+            serde_json::from_str::<Request>(r#"{"method":"build"}"#).unwrap(),
+            Request::Build
+        );
+        assert_eq!(
+            serde_json::from_str::<Request>(r#"{"method":"stop"}"#).unwrap(),
+            Request::Stop
+        );
+        // responses without a status are still read by newer clients
+        assert_eq!(
+            serde_json::from_str::<Response>(r#"{"success":true,"output":""}"#).unwrap(),
+            Response::new(true, String::new())
+        );
+    }
+
+    #[test]
+    fn does_not_stop_a_build() {
+        let temp_dir = TempDir::new().expect("temp dir should be created");
+        let folder = temp_dir.path().to_str().expect("path should be valid");
+        assert!(matches!(
+            lock::get(folder, lock::Command::Build),
+            lock::Lock::Aquired(_)
+        ));
+
+        assert!(stop(folder).is_err());
+        assert!(lock::get_running_owner(folder).is_some());
+    }
+
+    #[test]
+    fn answers_with_the_error_of_a_failing_generator() {
+        let (_temp_dir, root) = crate::build::build_types::tests::project(
+            r#"{
+                "name": "test",
+                "sources": {"dir": "src", "generators": [{"name": "fail", "edge": ["A.res", ":", "A.txt"]}]},
+                "generators": [{"name": "fail", "command": "echo broken >&2; false"}]
+            }"#,
+            &[("src/A.txt", "")],
+        );
+        let mut build_state = crate::build::build_types::tests::build_command_state(&root);
+
+        let result =
+            crate::build::incremental_build(&mut build_state, None, true, false, false, false, true, false);
+        assert!(result.is_err());
+        let response = build_response(&build_state, false);
+        assert!(response.output.contains("The generator 'fail' failed"));
+        assert!(response.output.contains("broken"));
+    }
+
+    #[test]
+    fn stop_requests_cancel_the_running_build() {
+        let temp_dir = TempDir::new().expect("temp dir should be created");
+        std::fs::create_dir(temp_dir.path().join("lib")).unwrap();
+        let cancel = Arc::new(AtomicBool::new(false));
+        let requests = serve(temp_dir.path(), cancel.clone()).expect("should listen on the socket");
+
+        // the watch loop, which only answers after its build
+        let watch_cancel = cancel.clone();
+        std::thread::spawn(move || {
+            let pending = requests.pop();
+            assert_eq!(pending.request, Request::Stop);
+            assert!(watch_cancel.load(Ordering::Relaxed));
+            pending.reply.send(Response::new(true, String::new())).unwrap();
+        });
+
+        let response = request(temp_dir.path(), &Request::Stop).expect("should get a response");
+        assert!(response.success);
+        stop_serving(temp_dir.path());
+    }
+
+    #[test]
+    fn only_requests_builds_from_watchers_without_a_filter() {
+        let temp_dir = TempDir::new().expect("temp dir should be created");
+        std::fs::create_dir(temp_dir.path().join("lib")).unwrap();
+        let requests = serve(temp_dir.path(), Arc::default()).expect("should listen on the socket");
+
+        // a watcher with a filter, which must not get build requests
+        std::thread::spawn(move || {
+            let pending = requests.pop();
+            assert_eq!(pending.request, Request::Status);
+            let status = Status {
+                pid: 1,
+                started_at: 0,
+                folder: PathBuf::from("/project"),
+                last_build_success: Some(true),
+                filter: Some("^Foo".to_string()),
+                warn_error: None,
+            };
+            pending
+                .reply
+                .send(Response {
+                    status: Some(status),
+                    ..Response::new(true, String::new())
+                })
+                .unwrap();
+        });
+
+        assert!(request_build(temp_dir.path()).is_err());
+        stop_serving(temp_dir.path());
+    }
+}
@@ -1,6 +1,5 @@
 use crate::{helpers, project_context};
 use anyhow::{Result, bail};
-use num_cpus;
 use rayon::prelude::*;
 use std::fs;
 use std::io::{self, Write};
@@ -83,35 +82,33 @@ fn format_stdin(bsc_exe: &Path, extension: FileExtension) -> Result<()> {
 }
 
 fn format_files(bsc_exe: &Path, files: Vec<String>, check: bool) -> Result<()> {
-    let batch_size = 4 * num_cpus::get();
     let incorrectly_formatted_files = AtomicUsize::new(0);
 
-    files.par_chunks(batch_size).try_for_each(|batch| {
-        batch.iter().try_for_each(|file| {
-            let mut cmd = Command::new(bsc_exe);
-            // Always get formatted output to stdout for comparison
-            cmd.arg("-format").arg(file);
-
-            let output = cmd.output()?;
-
-            if output.status.success() {
-                let original_content = fs::read_to_string(file)?;
-                let formatted_content = String::from_utf8_lossy(&output.stdout);
-                if original_content != formatted_content {
-                    if check {
-                        eprintln!("[format check] {file}");
-                        incorrectly_formatted_files.fetch_add(1, Ordering::SeqCst);
-                    } else {
-                        // Only write if content actually changed
-                        fs::write(file, &*formatted_content)?;
-                    }
+    // The rayon pool (sized by `--jobs`) limits how many bsc processes run at the same time
+    files.par_iter().try_for_each(|file| {
+        let mut cmd = Command::new(bsc_exe);
+        // Always get formatted output to stdout for comparison
+        cmd.arg("-format").arg(file);
+
+        let output = cmd.output()?;
+
+        if output.status.success() {
+            let original_content = fs::read_to_string(file)?;
+            let formatted_content = String::from_utf8_lossy(&output.stdout);
+            if original_content != formatted_content {
+                if check {
+                    eprintln!("[format check] {file}");
+                    incorrectly_formatted_files.fetch_add(1, Ordering::SeqCst);
+                } else {
+                    // Only write if content actually changed
+                    fs::write(file, &*formatted_content)?;
                 }
-            } else {
-                let stderr_str = String::from_utf8_lossy(&output.stderr);
-                bail!("Error formatting {}: {}", file, stderr_str);
             }
-            Ok(())
-        })
+        } else {
+            let stderr_str = String::from_utf8_lossy(&output.stderr);
+            bail!("Error formatting {}: {}", file, stderr_str);
+        }
+        Ok(())
     })?;
 
     let count = incorrectly_formatted_files.load(Ordering::SeqCst);
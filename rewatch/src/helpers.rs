@@ -1,5 +1,5 @@
 use crate::build::packages;
-use crate::config::Config;
+use crate::config::{Config, PackageSpec};
 use crate::helpers;
 use crate::project_context::ProjectContext;
 use anyhow::anyhow;
@@ -292,6 +292,20 @@ pub fn get_abs_path(path: &Path) -> PathBuf {
         .expect("Could not canonicalize")
 }
 
+/// A short human readable duration since `since` (in seconds since the Unix epoch), e.g. `1m 5s`.
+pub fn format_elapsed_since(since: u64) -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default();
+    let seconds = now.saturating_sub(since);
+    match (seconds / 3600, seconds % 3600 / 60, seconds % 60) {
+        (0, 0, seconds) => format!("{seconds}s"),
+        (0, minutes, seconds) => format!("{minutes}m {seconds}s"),
+        (hours, minutes, _) => format!("{hours}h {minutes}m"),
+    }
+}
+
 pub fn get_basename(path: &Path) -> String {
     path.file_stem()
         .expect("Could not get basename")
@@ -544,6 +558,27 @@ pub fn get_source_file_from_rescript_file(path: &Path, suffix: &str) -> PathBuf
     )
 }
 
+/// The JavaScript file bsc writes for a source file (relative to the package) and a package spec:
+/// - in-source: true  -> next to the source file (e.g., src/Foo.js)
+/// - in-source: false -> in lib/<module>/ directory (e.g., lib/es6/src/Foo.js)
+pub fn get_js_output_path(
+    package: &packages::Package,
+    root_config: &Config,
+    spec: &PackageSpec,
+    path: &Path,
+) -> PathBuf {
+    let path = if spec.in_source {
+        package.path.join(path)
+    } else {
+        package
+            .path
+            .join("lib")
+            .join(spec.get_out_of_source_dir())
+            .join(path)
+    };
+    get_source_file_from_rescript_file(&path, &root_config.get_suffix(spec))
+}
+
 pub fn is_local_package(workspace_path: &Path, canonical_package_path: &Path) -> bool {
     canonical_package_path.starts_with(workspace_path)
         && !canonical_package_path
@@ -2,10 +2,12 @@ pub mod build;
 pub mod cli;
 pub mod cmd;
 pub mod config;
+pub mod daemon;
 pub mod format;
 pub mod helpers;
 pub mod lock;
 pub mod project_context;
 pub mod queue;
 pub mod sourcedirs;
+pub mod trace;
 pub mod watcher;
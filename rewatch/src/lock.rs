@@ -1,19 +1,94 @@
+use crate::helpers;
+use serde::{Deserialize, Serialize};
+use std::fmt;
 use std::fs;
 use std::fs::File;
 use std::io::Write;
 use std::path::Path;
 use std::process;
-use sysinfo::{PidExt, System, SystemExt};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use sysinfo::{Pid, PidExt, ProcessExt, System, SystemExt};
 
-/* This locking mechanism is meant to never be deleted. Instead, it stores the PID of the process
- * that's running, when trying to aquire a lock, it checks wether that process is still running. If
- * not, it rewrites the lockfile to have its own PID instead. */
+/* This locking mechanism is meant to never be deleted. Instead, it stores the PID and start time of
+ * the process that's running, when trying to aquire a lock, it checks wether that process is still
+ * running. If not, it rewrites the lockfile with its own process instead. */
 
 pub static LOCKFILE: &str = "rescript.lock";
 
+const WAIT_INTERVAL: Duration = Duration::from_millis(200);
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Command {
+    Build,
+    Watch,
+    Daemon,
+    Clean,
+}
+
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let command = match self {
+            Command::Build => "build",
+            Command::Watch => "watch",
+            Command::Daemon => "daemon",
+            Command::Clean => "clean",
+        };
+        write!(f, "{command}")
+    }
+}
+
+/// The process that holds the lock, as stored in the lockfile. Lockfiles of older versions only
+/// contain the PID.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Owner {
+    pub pid: u32,
+    pub command: Option<Command>,
+    /// Start time of the process, in seconds since the Unix epoch
+    pub started_at: Option<u64>,
+    pub hostname: Option<String>,
+}
+
+impl fmt::Display for Owner {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.command {
+            Some(command) => write!(f, "A ReScript {command} is already running")?,
+            None => write!(f, "A ReScript build is already running")?,
+        }
+        if let Some(hostname) = self.foreign_hostname() {
+            write!(f, " on {hostname}")?;
+        }
+        if let Some(started_at) = self.started_at {
+            write!(f, " (started {} ago)", helpers::format_elapsed_since(started_at))?;
+        }
+        write!(f, ". The process ID (PID) is {}", self.pid)?;
+        if self.is_foreign() {
+            write!(
+                f,
+                ". It can't be checked from this host, so if it is no longer running, remove lib/{LOCKFILE}"
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl Owner {
+    fn foreign_hostname(&self) -> Option<&String> {
+        self.hostname
+            .as_ref()
+            .filter(|hostname| Some(*hostname) != get_hostname().as_ref())
+    }
+
+    /// Whether the lock is held by a process on another host (e.g. another container sharing the
+    /// project folder), which can't be checked or waited for.
+    pub fn is_foreign(&self) -> bool {
+        self.foreign_hostname().is_some()
+    }
+}
+
 pub enum Error {
-    Locked(u32),
-    ParsingLockfile(std::num::ParseIntError),
+    Locked(Owner),
+    ParsingLockfile(serde_json::Error),
     ReadingLockfile(std::io::Error),
     WritingLockfile(std::io::Error),
     ProjectFolderMissing(std::path::PathBuf),
@@ -22,9 +97,7 @@ pub enum Error {
 impl std::fmt::Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         let msg = match self {
-            Error::Locked(pid) => {
-                format!("A ReScript build is already running. The process ID (PID) is {pid}")
-            }
+            Error::Locked(owner) => owner.to_string(),
             Error::ParsingLockfile(e) => format!(
                 "Could not parse lockfile: \n {e} \n  (try removing it and running the command again)"
             ),
@@ -46,14 +119,72 @@ pub enum Lock {
     Error(Error),
 }
 
-fn pid_exists(to_check_pid: u32) -> bool {
-    System::new_all()
-        .processes()
-        .iter()
-        .any(|(pid, _process)| pid.as_u32() == to_check_pid)
+fn get_hostname() -> Option<String> {
+    System::new().host_name()
+}
+
+/// The start time of a running process in seconds since the Unix epoch, `None` when it's not
+/// running.
+fn get_start_time(pid: u32) -> Option<u64> {
+    let pid = Pid::from_u32(pid);
+    let mut system = System::new();
+    if !system.refresh_process(pid) {
+        return None;
+    }
+    system.process(pid).map(|process| process.start_time())
+}
+
+pub fn pid_exists(to_check_pid: u32) -> bool {
+    get_start_time(to_check_pid).is_some()
 }
 
-pub fn get(folder: &str) -> Lock {
+/// Whether the owner of a lockfile is still running. A PID can be reused by another process (e.g.
+/// in containers, where PIDs start at 1 again), so the start time has to match as well. A process
+/// on another host can't be checked, so its lock is only considered stale when it was taken before
+/// this host booted.
+fn is_running(owner: &Owner) -> bool {
+    if owner.is_foreign() {
+        return owner
+            .started_at
+            .is_none_or(|started_at| started_at >= System::new().boot_time());
+    }
+    match (get_start_time(owner.pid), owner.started_at) {
+        (None, _) => false,
+        (Some(_), None) => true,
+        // start times are in seconds, but can be rounded differently
+        (Some(start_time), Some(started_at)) => start_time.abs_diff(started_at) <= 1,
+    }
+}
+
+fn parse_lockfile(contents: &str) -> Result<Owner, serde_json::Error> {
+    match contents.trim().parse::<u32>() {
+        Ok(pid) => Ok(Owner {
+            pid,
+            command: None,
+            started_at: None,
+            hostname: None,
+        }),
+        Err(_) => serde_json::from_str(contents),
+    }
+}
+
+fn current_owner(command: Command) -> Owner {
+    let pid = process::id();
+    let started_at = get_start_time(pid).unwrap_or_else(|| {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or_default()
+    });
+    Owner {
+        pid,
+        command: Some(command),
+        started_at: Some(started_at),
+        hostname: get_hostname(),
+    }
+}
+
+pub fn get(folder: &str, command: Command) -> Lock {
     let project_folder = Path::new(folder);
     if !project_folder.exists() {
         return Lock::Error(Error::ProjectFolderMissing(project_folder.to_path_buf()));
@@ -61,13 +192,12 @@ pub fn get(folder: &str) -> Lock {
 
     let lib_dir = project_folder.join("lib");
     let location = lib_dir.join(LOCKFILE);
-    let pid = process::id();
 
-    // When a lockfile already exists we parse its PID: if the process is still alive we refuse to
-    // proceed, otherwise we will overwrite the stale lock with our own PID.
+    // When a lockfile already exists we parse its owner: if the process is still alive we refuse to
+    // proceed, otherwise we will overwrite the stale lock with our own process.
     match fs::read_to_string(&location) {
-        Ok(contents) => match contents.parse::<u32>() {
-            Ok(parsed_pid) if pid_exists(parsed_pid) => return Lock::Error(Error::Locked(parsed_pid)),
+        Ok(contents) => match parse_lockfile(&contents) {
+            Ok(owner) if is_running(&owner) => return Lock::Error(Error::Locked(owner)),
             Ok(_) => (),
             Err(e) => return Lock::Error(Error::ParsingLockfile(e)),
         },
@@ -79,16 +209,47 @@ pub fn get(folder: &str) -> Lock {
         return Lock::Error(Error::WritingLockfile(e));
     }
 
-    // Rewrite the lockfile with our own PID.
+    // Rewrite the lockfile with our own process.
+    let owner = current_owner(command);
+    let contents = serde_json::to_string(&owner).expect("Could not serialize lockfile");
     match File::create(&location) {
-        Ok(mut file) => match file.write(pid.to_string().as_bytes()) {
-            Ok(_) => Lock::Aquired(pid),
+        Ok(mut file) => match file.write(contents.as_bytes()) {
+            Ok(_) => Lock::Aquired(owner.pid),
             Err(e) => Lock::Error(Error::WritingLockfile(e)),
         },
         Err(e) => Lock::Error(Error::WritingLockfile(e)),
     }
 }
 
+/// Like `get`, but waits while another process holds the lock, for at most `timeout` (or
+/// indefinitely). When the timeout expires, or the lock is held by another host, the `Locked` error
+/// is returned.
+pub fn wait(folder: &str, command: Command, timeout: Option<Duration>) -> Lock {
+    let started = Instant::now();
+    loop {
+        match get(folder, command) {
+            Lock::Error(Error::Locked(owner))
+                if !owner.is_foreign() && timeout.is_none_or(|timeout| started.elapsed() < timeout) =>
+            {
+                std::thread::sleep(WAIT_INTERVAL)
+            }
+            lock => return lock,
+        }
+    }
+}
+
+/// The process that holds the lock of `folder`, if it is still running.
+pub fn get_running_owner(folder: &str) -> Option<Owner> {
+    let location = Path::new(folder).join("lib").join(LOCKFILE);
+    let owner = parse_lockfile(&fs::read_to_string(location).ok()?).ok()?;
+    is_running(&owner).then_some(owner)
+}
+
+/// Removes the lockfile of `folder`, which makes a running watcher exit.
+pub fn remove(folder: &str) {
+    let _ = fs::remove_file(Path::new(folder).join("lib").join(LOCKFILE));
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -100,7 +261,10 @@ mod tests {
         let temp_dir = TempDir::new().expect("temp dir should be created");
         let missing_folder = temp_dir.path().join("missing_project");
 
-        match get(missing_folder.to_str().expect("path should be valid")) {
+        match get(
+            missing_folder.to_str().expect("path should be valid"),
+            Command::Build,
+        ) {
             Lock::Error(Error::ProjectFolderMissing(path)) => {
                 assert_eq!(path, missing_folder);
             }
@@ -119,7 +283,10 @@ mod tests {
         let project_folder = temp_dir.path().join("project");
         fs::create_dir(&project_folder).expect("project folder should be created");
 
-        match get(project_folder.to_str().expect("path should be valid")) {
+        match get(
+            project_folder.to_str().expect("path should be valid"),
+            Command::Build,
+        ) {
             Lock::Aquired(_) => {}
             _ => panic!("expected lock to be acquired"),
         }
@@ -133,4 +300,117 @@ mod tests {
             "lockfile should be created"
         );
     }
+
+    #[test]
+    fn writes_the_owner_to_the_lockfile() {
+        let temp_dir = TempDir::new().expect("temp dir should be created");
+        let folder = temp_dir.path().to_str().expect("path should be valid");
+
+        assert!(matches!(get(folder, Command::Watch), Lock::Aquired(_)));
+
+        let owner = get_running_owner(folder).expect("owner should be running");
+        assert_eq!(owner.pid, process::id());
+        assert_eq!(owner.command, Some(Command::Watch));
+        assert!(owner.started_at.is_some());
+    }
+
+    #[test]
+    fn reads_lockfiles_with_only_a_pid() {
+        assert_eq!(
+            parse_lockfile("1234").expect("should parse a PID"),
+            Owner {
+                pid: 1234,
+                command: None,
+                started_at: None,
+                hostname: None,
+            }
+        );
+    }
+
+    #[test]
+    fn describes_the_owner() {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+        let owner = Owner {
+            pid: 1234,
+            command: Some(Command::Watch),
+            started_at: Some(now - 65),
+            hostname: get_hostname(),
+        };
+        assert_eq!(
+            owner.to_string(),
+            "A ReScript watch is already running (started 1m 5s ago). The process ID (PID) is 1234"
+        );
+        let owner = Owner {
+            started_at: Some(now - 3725),
+            hostname: Some("other-host".to_string()),
+            ..owner
+        };
+        assert_eq!(
+            owner.to_string(),
+            "A ReScript watch is already running on other-host (started 1h 2m ago). The process ID (PID) is 1234. \
+             It can't be checked from this host, so if it is no longer running, remove lib/rescript.lock"
+        );
+    }
+
+    #[test]
+    fn foreign_lock_from_before_boot_is_stale() {
+        let temp_dir = TempDir::new().expect("temp dir should be created");
+        let folder = temp_dir.path().to_str().expect("path should be valid");
+        let boot_time = System::new().boot_time();
+        let mut owner = Owner {
+            pid: 1234,
+            command: Some(Command::Watch),
+            started_at: Some(boot_time + 10),
+            hostname: Some("other-host".to_string()),
+        };
+        fs::create_dir(temp_dir.path().join("lib")).unwrap();
+        let lockfile = temp_dir.path().join("lib").join(LOCKFILE);
+
+        fs::write(&lockfile, serde_json::to_string(&owner).unwrap()).unwrap();
+        // a foreign lock isn't waited for
+        let started = Instant::now();
+        assert!(matches!(
+            wait(folder, Command::Build, None),
+            Lock::Error(Error::Locked(_))
+        ));
+        assert!(started.elapsed() < Duration::from_secs(1));
+
+        owner.started_at = Some(boot_time - 10);
+        fs::write(&lockfile, serde_json::to_string(&owner).unwrap()).unwrap();
+        assert!(get_running_owner(folder).is_none());
+        assert!(matches!(get(folder, Command::Build), Lock::Aquired(_)));
+    }
+
+    #[test]
+    fn lock_of_a_reused_pid_is_stale() {
+        let temp_dir = TempDir::new().expect("temp dir should be created");
+        let folder = temp_dir.path().to_str().expect("path should be valid");
+        let mut owner = current_owner(Command::Build);
+        // our own PID, but started long before us
+        owner.started_at = owner.started_at.map(|started_at| started_at - 3600);
+        fs::create_dir(temp_dir.path().join("lib")).unwrap();
+        fs::write(
+            temp_dir.path().join("lib").join(LOCKFILE),
+            serde_json::to_string(&owner).unwrap(),
+        )
+        .unwrap();
+
+        assert!(get_running_owner(folder).is_none());
+        assert!(matches!(get(folder, Command::Build), Lock::Aquired(_)));
+    }
+
+    #[test]
+    fn waits_until_the_timeout() {
+        let temp_dir = TempDir::new().expect("temp dir should be created");
+        let folder = temp_dir.path().to_str().expect("path should be valid");
+        assert!(matches!(get(folder, Command::Watch), Lock::Aquired(_)));
+
+        // the lock is held by this process, so waiting for it times out
+        let started = Instant::now();
+        match wait(folder, Command::Build, Some(Duration::from_millis(300))) {
+            Lock::Error(Error::Locked(owner)) => assert_eq!(owner.command, Some(Command::Watch)),
+            _ => panic!("expected the lock to be held"),
+        }
+        assert!(started.elapsed() >= Duration::from_millis(300));
+    }
 }
@@ -1,15 +1,25 @@
 use anyhow::Result;
 use console::Term;
 use log::LevelFilter;
-use std::{io::Write, path::Path};
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
-use rescript::{build, cli, cmd, format, lock, watcher};
+use rescript::{build, cli, cmd, config, daemon, format, lock, trace, watcher};
 
 fn main() -> Result<()> {
     let cli = cli::parse_with_default().unwrap_or_else(|err| err.exit());
 
     let log_level_filter = cli.verbose.log_level_filter();
 
+    let json_output = match &cli.command {
+        cli::Command::Build(build_args) => *build_args.json,
+        cli::Command::Watch(watch_args) | cli::Command::Daemon(watch_args) => *watch_args.json,
+        _ => false,
+    };
+
     let stdout_logger = env_logger::Builder::new()
         .format(|buf, record| writeln!(buf, "{}:\n{}", record.level(), record.args()))
         .filter_level(log_level_filter)
@@ -26,6 +36,8 @@ fn main() -> Result<()> {
     log::set_boxed_logger(Box::new(SplitLogger {
         stdout: stdout_logger,
         stderr: stderr_logger,
+        // With --json, stdout is reserved for build events
+        all_to_stderr: json_output,
     }))
     .expect("Failed to initialize logger");
 
@@ -33,26 +45,104 @@ fn main() -> Result<()> {
     let plain_output = !is_tty;
 
     // The 'normal run' mode will show the 'pretty' formatted progress. But if we turn off the log
-    // level, we should never show that.
-    let show_progress = log_level_filter == LevelFilter::Info;
+    // level, we should never show that. The same goes for `--json`, which replaces it with events.
+    let show_progress = log_level_filter == LevelFilter::Info && !json_output;
+
+    let trace_path = match &cli.command {
+        cli::Command::Build(build_args) => (*build_args.trace).clone(),
+        cli::Command::Watch(watch_args) | cli::Command::Daemon(watch_args) => (*watch_args.trace).clone(),
+        _ => None,
+    };
+    if trace_path.is_some() {
+        trace::enable();
+    }
+
+    let jobs = match &cli.command {
+        cli::Command::Build(build_args) => *build_args.jobs,
+        cli::Command::Watch(watch_args) | cli::Command::Daemon(watch_args) => *watch_args.jobs,
+        cli::Command::Format { jobs, .. } => **jobs,
+        _ => None,
+    };
+    // Every bsc process is started from the rayon pool, so its size limits how many run at once
+    if let Some(jobs) = jobs {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(jobs.get())
+            .build_global()
+            .expect("Could not configure the number of jobs");
+    }
+
+    let is_daemon = matches!(cli.command, cli::Command::Daemon(_));
 
     match cli.command {
         cli::Command::CompilerArgs { path } => {
-            println!("{}", build::get_compiler_args(Path::new(&path))?);
+            let path = Path::new(&path);
+            // A watcher of the package already has its config loaded
+            let from_watcher = build::get_package_of_file(path).and_then(|package| {
+                daemon::request(
+                    &package,
+                    &daemon::Request::CompilerArgs {
+                        path: path.to_path_buf(),
+                    },
+                )
+            });
+            match from_watcher {
+                Ok(daemon::Response {
+                    success: true,
+                    output,
+                    ..
+                }) => println!("{output}"),
+                _ => println!("{}", build::get_compiler_args(path)?),
+            }
             std::process::exit(0);
         }
         cli::Command::Build(build_args) => {
-            let _lock = get_lock(&build_args.folder);
+            let _lock = match lock::get(&build_args.folder, lock::Command::Build) {
+                lock::Lock::Error(lock::Error::Locked(owner)) => {
+                    // Let the running watcher build instead
+                    if build_args.can_delegate_to_watcher()
+                        && let Ok(response) = daemon::request_build(Path::new(&build_args.folder as &str))
+                    {
+                        exit_with_watcher_build(response, (*build_args.after_build).clone())
+                    }
+                    match build_args.wait {
+                        Some(timeout) if !owner.is_foreign() => {
+                            if show_progress {
+                                eprintln!("{owner}. Waiting for it to finish...");
+                            }
+                            exit_on_lock_error(lock::wait(
+                                &build_args.folder,
+                                lock::Command::Build,
+                                timeout.map(Duration::from_secs),
+                            ))
+                        }
+                        _ => exit_on_lock_error(lock::Lock::Error(lock::Error::Locked(owner))),
+                    }
+                }
+                lock => exit_on_lock_error(lock),
+            };
 
-            match build::build(
+            let artifact_cache = build::artifact_cache::ArtifactCache::new(
+                build_args.cache.cache_dir.as_deref(),
+                build_args.cache.remote_cache.as_deref(),
+                build_args.cache.remote_cache_mode,
+            )?;
+            let result = build::build(
                 &build_args.filter,
                 Path::new(&build_args.folder as &str),
                 show_progress,
                 build_args.no_timing,
                 true, // create_sourcedirs is now always enabled
                 plain_output,
+                json_output,
                 (*build_args.warn_error).clone(),
-            ) {
+                *build_args.keep_going,
+                artifact_cache,
+                &build_args.report,
+                &build_args.timings,
+            );
+            write_trace(&trace_path);
+
+            match result {
                 Err(e) => {
                     eprintln!("{:#}", e);
                     std::process::exit(1)
@@ -65,18 +155,41 @@ fn main() -> Result<()> {
                 }
             };
         }
-        cli::Command::Watch(watch_args) => {
-            let _lock = get_lock(&watch_args.folder);
+        cli::Command::Watch(watch_args) | cli::Command::Daemon(watch_args) => {
+            let command = if is_daemon {
+                lock::Command::Daemon
+            } else {
+                lock::Command::Watch
+            };
+            let _lock = exit_on_lock_error(lock::get(&watch_args.folder, command));
 
-            match watcher::start(
+            let artifact_cache = build::artifact_cache::ArtifactCache::new(
+                watch_args.cache.cache_dir.as_deref(),
+                watch_args.cache.remote_cache.as_deref(),
+                watch_args.cache.remote_cache_mode,
+            )?;
+            let result = watcher::start(
                 &watch_args.filter,
-                show_progress,
+                show_progress && !is_daemon,
                 &watch_args.folder,
                 (*watch_args.after_build).clone(),
                 true, // create_sourcedirs is now always enabled
                 plain_output,
+                json_output,
                 (*watch_args.warn_error).clone(),
-            ) {
+                *watch_args.keep_going,
+                artifact_cache,
+                watcher::WatchOptions {
+                    poll_interval: watch_args.poll.map(|interval| {
+                        Duration::from_millis(interval.unwrap_or(config::DEFAULT_POLL_INTERVAL_MS))
+                    }),
+                    debounce: watch_args.debounce.map(Duration::from_millis),
+                    wait_for_quiet: watch_args.wait_for_quiet,
+                },
+            );
+            write_trace(&trace_path);
+
+            match result {
                 Err(e) => {
                     eprintln!("{:#}", e);
                     std::process::exit(1)
@@ -84,16 +197,42 @@ fn main() -> Result<()> {
                 Ok(_) => Ok(()),
             }
         }
+        cli::Command::Status { folder } => daemon::status(&folder),
+        cli::Command::Stop { folder } => daemon::stop(&folder),
         cli::Command::Clean { folder } => {
-            let _lock = get_lock(&folder);
+            let _lock = exit_on_lock_error(lock::get(&folder, lock::Command::Clean));
             build::clean::clean(Path::new(&folder as &str), show_progress, plain_output)
         }
-        cli::Command::Format { stdin, check, files } => format::format(stdin, check, files),
+        cli::Command::Format {
+            stdin, check, files, ..
+        } => format::format(stdin, check, files),
+    }
+}
+
+fn write_trace(trace_path: &Option<PathBuf>) {
+    if let Some(path) = trace_path
+        && let Err(e) = trace::write(path)
+    {
+        eprintln!("{:#}", e);
+    }
+}
+
+/// Exits with the result of a build that was requested from the watcher holding the lock.
+fn exit_with_watcher_build(response: daemon::Response, after_build: Option<String>) -> ! {
+    if !response.output.is_empty() {
+        eprintln!("{}", response.output);
+    }
+    if !response.success {
+        std::process::exit(1);
+    }
+    if let Some(after_build) = after_build {
+        cmd::run(after_build)
     }
+    std::process::exit(0)
 }
 
-fn get_lock(folder: &str) -> lock::Lock {
-    match lock::get(folder) {
+fn exit_on_lock_error(lock: lock::Lock) -> lock::Lock {
+    match lock {
         lock::Lock::Error(error) => {
             eprintln!("Could not start ReScript build: {error}");
             std::process::exit(1);
@@ -105,6 +244,7 @@ fn get_lock(folder: &str) -> lock::Lock {
 struct SplitLogger {
     stdout: env_logger::Logger,
     stderr: env_logger::Logger,
+    all_to_stderr: bool,
 }
 
 impl log::Log for SplitLogger {
@@ -114,6 +254,7 @@ impl log::Log for SplitLogger {
 
     fn log(&self, record: &log::Record) {
         match record.level() {
+            _ if self.all_to_stderr => self.stderr.log(record),
             log::Level::Error | log::Level::Warn => self.stderr.log(record),
             _ => self.stdout.log(record),
         }
@@ -1,6 +1,7 @@
 use crate::build::packages;
 use crate::config::Config;
 use crate::helpers;
+use crate::trace;
 use ahash::{AHashMap, AHashSet};
 use anyhow::anyhow;
 use anyhow::{Context, Result};
@@ -166,6 +167,7 @@ fn is_config_listed_in_workspace(current_config: &Config, workspace_config: &Con
 
 impl ProjectContext {
     pub fn new(path: &Path) -> Result<ProjectContext> {
+        let _span = trace::span("ProjectContext::new");
         let path = helpers::get_abs_path(path);
         let current_config = packages::read_config(&path)
             .with_context(|| format!("Could not read rescript.json at {}", path.to_string_lossy()))?;
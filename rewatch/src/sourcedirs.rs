@@ -1,5 +1,7 @@
 use crate::build::build_types::BuildState;
+use crate::build::generators;
 use crate::build::packages::Package;
+use crate::trace;
 use ahash::{AHashMap, AHashSet};
 use rayon::prelude::*;
 use serde::Serialize;
@@ -12,6 +14,13 @@ type Dir = PathBuf;
 type PackageName = String;
 type AbsolutePath = PathBuf;
 type Pkg = (PackageName, AbsolutePath);
+/// The dirs, pkgs, scan plan and generated files of a package.
+type PackageSourceDirs = (
+    AHashSet<Dir>,
+    AHashMap<PackageName, AbsolutePath>,
+    CmtScanEntry,
+    Vec<String>,
+);
 
 /// `reanalyze` consumes `.sourcedirs.json` to find `.cmt/.cmti` files.
 ///
@@ -81,6 +90,7 @@ fn sort_paths(mut v: Vec<PathBuf>) -> Vec<PathBuf> {
 }
 
 pub fn print(buildstate: &BuildState) {
+    let _span = trace::span("sourcedirs::print");
     // Find Root Package
     let (_name, root_package) = buildstate
         .packages
@@ -102,7 +112,7 @@ pub fn print(buildstate: &BuildState) {
     // Take all local packages with source files.
     // In the case of a monorepo, the root package typically won't have any source files.
     // But in the case of a single package, it will be both local, root and have source files.
-    let collected: Vec<(AHashSet<Dir>, AHashMap<PackageName, AbsolutePath>, CmtScanEntry)> = buildstate
+    let collected: Vec<PackageSourceDirs> = buildstate
         .packages
         .par_iter()
         .filter(|(_name, package)| package.is_local_dep && package.source_files.is_some())
@@ -133,6 +143,19 @@ pub fn print(buildstate: &BuildState) {
                     .collect::<Vec<_>>(),
             );
 
+            // Files generated by the generators of the package, relative to the root
+            let generated = generators::get_generators(package)
+                .into_iter()
+                .flat_map(|generator| generator.outputs.into_iter())
+                .map(|output| {
+                    output
+                        .strip_prefix(&root_package.path)
+                        .unwrap_or(&output)
+                        .to_string_lossy()
+                        .to_string()
+                })
+                .collect::<Vec<String>>();
+
             // NOTE: We intentionally do NOT write per-package `.sourcedirs.json`.
             // The root package's `.sourcedirs.json` contains a complete `cmt_scan` plan
             // and is the only file `reanalyze` needs for root-level monorepo analysis.
@@ -146,6 +169,7 @@ pub fn print(buildstate: &BuildState) {
                     // Namespaces/mlmap artifacts can land in `lib/bs` itself; scanning it is cheap and avoids misses.
                     also_scan_build_root: true,
                 },
+                generated,
             )
         })
         .collect();
@@ -153,11 +177,14 @@ pub fn print(buildstate: &BuildState) {
     let mut dirs: Vec<AHashSet<Dir>> = Vec::with_capacity(collected.len());
     let mut pkgs: Vec<AHashMap<PackageName, AbsolutePath>> = Vec::with_capacity(collected.len());
     let mut cmt_scan_entries: Vec<CmtScanEntry> = Vec::with_capacity(collected.len());
-    for (d, p, s) in collected {
+    let mut generated: Vec<String> = vec![];
+    for (d, p, s, g) in collected {
         dirs.push(d);
         pkgs.push(p);
         cmt_scan_entries.push(s);
+        generated.extend(g);
     }
+    generated.sort();
 
     let mut merged_dirs: AHashSet<Dir> = AHashSet::new();
     let mut merged_pkgs: AHashMap<PackageName, AbsolutePath> = AHashMap::new();
@@ -174,7 +201,7 @@ pub fn print(buildstate: &BuildState) {
             v.sort_by(|(a, _), (b, _)| a.cmp(b));
             v
         },
-        generated: vec![],
+        generated,
         cmt_scan: Some({
             // Ensure deterministic order (use the serialized string form).
             let mut v = cmt_scan_entries;
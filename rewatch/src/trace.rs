@@ -0,0 +1,171 @@
+//! Collects spans of the build internals for `--trace`, written as a Chrome Trace Event Format
+//! file (open it in Perfetto or `chrome://tracing`).
+//!
+//! Tracing is global, like logging, so that phases which run before there is any build state
+//! (like `ProjectContext::new`) can be traced too. When tracing is not enabled, `span` returns
+//! `None` and nothing is recorded.
+
+use anyhow::{Result, anyhow};
+use serde::Serialize;
+use std::cell::Cell;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::Instant;
+
+#[derive(Serialize, Debug, Clone)]
+struct TraceEvent {
+    name: String,
+    cat: &'static str,
+    ph: &'static str,
+    /// Microseconds since tracing was enabled
+    ts: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dur: Option<u64>,
+    pid: u32,
+    tid: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    args: Option<serde_json::Value>,
+}
+
+struct Tracer {
+    start: Instant,
+    events: Mutex<Vec<TraceEvent>>,
+}
+
+static TRACER: OnceLock<Tracer> = OnceLock::new();
+
+static NEXT_THREAD_ID: AtomicU64 = AtomicU64::new(1);
+
+thread_local! {
+    static THREAD_ID: Cell<Option<u64>> = const { Cell::new(None) };
+}
+
+/// A small, stable id for the current thread. The first time a thread is seen, its name is
+/// recorded so the trace viewer can label the row.
+fn thread_id(tracer: &Tracer) -> u64 {
+    THREAD_ID.with(|id| match id.get() {
+        Some(id) => id,
+        None => {
+            let new_id = NEXT_THREAD_ID.fetch_add(1, Ordering::Relaxed);
+            id.set(Some(new_id));
+            let name = match rayon::current_thread_index() {
+                Some(index) => format!("rayon worker {index}"),
+                None => std::thread::current().name().unwrap_or("main").to_string(),
+            };
+            tracer.push(TraceEvent {
+                name: "thread_name".to_string(),
+                cat: "__metadata",
+                ph: "M",
+                ts: 0,
+                dur: None,
+                pid: std::process::id(),
+                tid: new_id,
+                args: Some(serde_json::json!({ "name": name })),
+            });
+            new_id
+        }
+    })
+}
+
+impl Tracer {
+    fn push(&self, event: TraceEvent) {
+        self.events.lock().expect("trace lock poisoned").push(event);
+    }
+}
+
+/// Starts recording spans. Calling it more than once has no effect.
+pub fn enable() {
+    let _ = TRACER.set(Tracer {
+        start: Instant::now(),
+        events: Mutex::new(vec![]),
+    });
+}
+
+pub fn is_enabled() -> bool {
+    TRACER.get().is_some()
+}
+
+/// A span that is recorded when it's dropped.
+pub struct Span {
+    name: String,
+    category: &'static str,
+    start: Instant,
+    args: Option<serde_json::Value>,
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        let Some(tracer) = TRACER.get() else {
+            return;
+        };
+        let tid = thread_id(tracer);
+        tracer.push(TraceEvent {
+            name: std::mem::take(&mut self.name),
+            cat: self.category,
+            ph: "X",
+            ts: self.start.duration_since(tracer.start).as_micros() as u64,
+            dur: Some(self.start.elapsed().as_micros() as u64),
+            pid: std::process::id(),
+            tid,
+            args: self.args.take(),
+        });
+    }
+}
+
+/// Starts a span for an internal phase of the build, e.g. `let _span = trace::span("deps::get_deps");`
+pub fn span(name: &str) -> Option<Span> {
+    span_with_args(name, "build", None)
+}
+
+/// Starts a span with a category and arguments, which are shown in the trace viewer.
+pub fn span_with_args(name: &str, category: &'static str, args: Option<serde_json::Value>) -> Option<Span> {
+    is_enabled().then(|| Span {
+        name: name.to_string(),
+        category,
+        start: Instant::now(),
+        args,
+    })
+}
+
+/// Writes all spans recorded so far to `path`.
+pub fn write(path: &Path) -> Result<()> {
+    let Some(tracer) = TRACER.get() else {
+        return Ok(());
+    };
+    let events = tracer.events.lock().expect("trace lock poisoned");
+    let file = File::create(path)
+        .map_err(|e| anyhow!("Could not create trace file {}: {e}", path.to_string_lossy()))?;
+    serde_json::to_writer(
+        BufWriter::new(file),
+        &serde_json::json!({ "traceEvents": *events, "displayTimeUnit": "ms" }),
+    )
+    .map_err(|e| anyhow!("Could not write trace file {}: {e}", path.to_string_lossy()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_spans_once_enabled() {
+        enable();
+        {
+            let _span = span("test::phase");
+        }
+        let events = TRACER.get().unwrap().events.lock().unwrap();
+        let span = events
+            .iter()
+            .find(|event| event.name == "test::phase")
+            .expect("span should be recorded");
+        assert_eq!(span.ph, "X");
+        assert!(span.dur.is_some());
+        assert!(
+            events
+                .iter()
+                .any(|event| event.ph == "M" && event.tid == span.tid)
+        );
+    }
+}
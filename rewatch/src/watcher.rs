@@ -1,22 +1,50 @@
 use crate::build;
+use crate::build::artifact_cache::ArtifactCache;
 use crate::build::build_types::{BuildCommandState, SourceType};
 use crate::build::clean;
+use crate::build::diagnostics::Severity;
+use crate::build::generators;
+use crate::build::source_files;
+use crate::build::{IncrementalBuildError, IncrementalBuildErrorKind};
 use crate::cmd;
-use crate::config;
+use crate::config::{self, DEFAULT_DEBOUNCE_MS, DEFAULT_POLL_INTERVAL_MS};
+use crate::daemon::{self, Requests};
 use crate::helpers;
 use crate::helpers::StrippedVerbatimPath;
 use crate::helpers::emojis::*;
 use crate::lock::LOCKFILE;
 use crate::queue::FifoQueue;
 use crate::queue::*;
+use ahash::AHashSet;
 use anyhow::{Context, Result};
+use console::Term;
 use futures_timer::Delay;
-use notify::event::ModifyKind;
-use notify::{Config, Error, Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use notify::event::{MetadataKind, ModifyKind};
+use notify::{
+    Config, Error, ErrorKind, Event, EventHandler, EventKind, PollWatcher, RecommendedWatcher, RecursiveMode,
+    Watcher,
+};
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::sync::Mutex;
-use std::time::{Duration, Instant};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::time::{Duration, Instant, SystemTime};
+
+mod keys;
+
+use keys::{KeyCommand, KeyCommands};
+
+/// How often the watcher looks for events while it is idle, or waits for them to settle.
+const IDLE_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Options of the watcher from the command line, they take precedence over the `watch` config.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct WatchOptions {
+    pub poll_interval: Option<Duration>,
+    pub debounce: Option<Duration>,
+    pub wait_for_quiet: Option<bool>,
+}
 
 #[derive(Debug, Clone, PartialEq, Eq, Copy)]
 enum CompileType {
@@ -58,6 +86,95 @@ fn matches_filter(path_buf: &Path, filter: &Option<regex::Regex>) -> bool {
     filter.as_ref().map(|re| !re.is_match(&name)).unwrap_or(true)
 }
 
+/// The files of the local packages that matter to the watcher: the `files` of the source folders,
+/// so changes to files that aren't compiled are ignored, and the edges of the generators.
+#[derive(Debug, Clone, Default)]
+struct WatchedFiles {
+    file_filters: Vec<(PathBuf, bool, config::FileFilter)>,
+    generator_inputs: AHashSet<PathBuf>,
+    generator_outputs: AHashSet<PathBuf>,
+}
+
+impl WatchedFiles {
+    fn new(build_state: &BuildCommandState) -> WatchedFiles {
+        let (generator_inputs, generator_outputs) = generators::get_edges(build_state);
+        WatchedFiles {
+            file_filters: build_state
+                .packages
+                .values()
+                .filter(|package| package.is_local_dep)
+                .flat_map(|package| {
+                    package.source_folders.iter().map(|source| {
+                        (
+                            package.path.join(&source.dir),
+                            matches!(source.subdirs, Some(config::Subdirs::Recurse(true))),
+                            source.get_file_filter(),
+                        )
+                    })
+                })
+                .collect(),
+            generator_inputs,
+            generator_outputs,
+        }
+    }
+
+    fn is_generator_input(&self, path: &Path) -> bool {
+        self.generator_inputs.contains(path)
+    }
+
+    /// Generated files are written by the build itself, they are parsed again when they change.
+    fn is_generator_output(&self, path: &Path) -> bool {
+        self.generator_outputs.contains(path)
+    }
+
+    fn includes(&self, path: &Path) -> bool {
+        let (Some(name), Some(parent)) = (path.file_name().and_then(|name| name.to_str()), path.parent())
+        else {
+            return true;
+        };
+        if self.is_generator_output(path) {
+            return false;
+        }
+        // the files of the source folder that is nested the deepest decide
+        self.file_filters
+            .iter()
+            .filter(|(dir, recurse, _)| parent == dir || (*recurse && parent.starts_with(dir)))
+            .max_by_key(|(dir, _, _)| dir.components().count())
+            .is_none_or(|(_, _, file_filter)| file_filter.includes(name))
+    }
+}
+
+/// Whether an event makes the watcher build again (or exit), so a build that is running is
+/// outdated and can be cancelled.
+fn cancels_build(event: &Event, filter: &Option<regex::Regex>, watched_files: &WatchedFiles) -> bool {
+    if matches!(
+        event.kind,
+        EventKind::Access(_) | EventKind::Other | EventKind::Modify(ModifyKind::Other)
+    ) || matches!(event.kind, EventKind::Modify(ModifyKind::Metadata(kind)) if kind != MetadataKind::WriteTime)
+    {
+        return false;
+    }
+    event.paths.iter().any(|path| {
+        (path.ends_with(LOCKFILE) && matches!(event.kind, EventKind::Remove(_)))
+            || path.file_name().is_some_and(|name| name == "rescript.json")
+            || watched_files.is_generator_input(path)
+            || (is_rescript_file(path)
+                && !is_in_build_path(path)
+                && matches_filter(path, filter)
+                && watched_files.includes(path))
+    })
+}
+
+fn is_cancelled(result: &Result<(), IncrementalBuildError>) -> bool {
+    matches!(
+        result,
+        Err(IncrementalBuildError {
+            kind: IncrementalBuildErrorKind::Cancelled,
+            ..
+        })
+    )
+}
+
 /// Computes the list of paths to watch based on the build state.
 /// Returns tuples of (path, recursive_mode) for each watch target.
 fn compute_watch_paths(build_state: &BuildCommandState, root: &Path) -> Vec<(PathBuf, RecursiveMode)> {
@@ -90,6 +207,7 @@ fn compute_watch_paths(build_state: &BuildCommandState, root: &Path) -> Vec<(Pat
         insert(package.path.clone(), RecursiveMode::NonRecursive);
 
         // Watch each source folder
+        let ignored_dirs = package.config.get_ignored_dirs();
         for source in &package.source_folders {
             let dir = package.path.join(&source.dir);
             if !dir.exists() {
@@ -101,11 +219,17 @@ fn compute_watch_paths(build_state: &BuildCommandState, root: &Path) -> Vec<(Pat
                 );
                 continue;
             }
-            let mode = match &source.subdirs {
-                Some(config::Subdirs::Recurse(true)) => RecursiveMode::Recursive,
-                _ => RecursiveMode::NonRecursive,
-            };
-            insert(dir, mode);
+            match &source.subdirs {
+                // a recursive watch would include the ignored folders, so every other folder is
+                // watched on its own
+                Some(config::Subdirs::Recurse(true)) if !ignored_dirs.is_empty() => {
+                    for dir in get_watched_dirs(&package.path, Path::new(&source.dir), &ignored_dirs) {
+                        insert(dir, RecursiveMode::NonRecursive);
+                    }
+                }
+                Some(config::Subdirs::Recurse(true)) => insert(dir, RecursiveMode::Recursive),
+                _ => insert(dir, RecursiveMode::NonRecursive),
+            }
         }
     }
 
@@ -118,8 +242,26 @@ fn compute_watch_paths(build_state: &BuildCommandState, root: &Path) -> Vec<(Pat
     watch_paths.into_iter().collect()
 }
 
-/// Registers all watch paths with the given watcher.
-fn register_watches(watcher: &mut RecommendedWatcher, watch_paths: &[(PathBuf, RecursiveMode)]) {
+/// A folder of a package and all folders in it, except the ignored ones.
+fn get_watched_dirs(package_dir: &Path, dir: &Path, ignored_dirs: &config::IgnoredDirs) -> Vec<PathBuf> {
+    let mut dirs = vec![package_dir.join(dir)];
+    if let Ok(entries) = std::fs::read_dir(package_dir.join(dir)) {
+        for entry in entries.flatten() {
+            let sub_dir = dir.join(entry.file_name());
+            if entry.path().is_dir() && !ignored_dirs.is_ignored(&sub_dir) {
+                dirs.extend(get_watched_dirs(package_dir, &sub_dir, ignored_dirs));
+            }
+        }
+    }
+    dirs
+}
+
+/// Registers all watch paths with the given watcher. Fails when the limit of watches of the OS is
+/// reached, other errors are logged.
+fn register_watches(
+    watcher: &mut dyn Watcher,
+    watch_paths: &[(PathBuf, RecursiveMode)],
+) -> Result<(), Error> {
     for (path, mode) in watch_paths {
         let mode_str = if *mode == RecursiveMode::Recursive {
             "recursive"
@@ -127,30 +269,156 @@ fn register_watches(watcher: &mut RecommendedWatcher, watch_paths: &[(PathBuf, R
             "non-recursive"
         };
         log::debug!("  watching ({mode_str}): {}", path.display());
-        if let Err(e) = watcher.watch(path, *mode) {
-            log::error!("Could not watch {}: {}", path.display(), e);
+        match watcher.watch(path, *mode) {
+            Err(e) if matches!(e.kind, ErrorKind::MaxFilesWatch) => return Err(e),
+            Err(e) => log::error!("Could not watch {}: {}", path.display(), e),
+            Ok(()) => (),
         }
     }
+    Ok(())
 }
 
 /// Unregisters all watch paths from the given watcher.
-fn unregister_watches(watcher: &mut RecommendedWatcher, watch_paths: &[(PathBuf, RecursiveMode)]) {
+fn unregister_watches(watcher: &mut dyn Watcher, watch_paths: &[(PathBuf, RecursiveMode)]) {
     for (path, _) in watch_paths {
         let _ = watcher.unwatch(path);
     }
 }
 
+/// Registers the watch paths that were added, and unregisters the ones that are gone.
+fn update_watches(
+    watcher: &mut dyn Watcher,
+    current_watch_paths: &mut Vec<(PathBuf, RecursiveMode)>,
+    watch_paths: Vec<(PathBuf, RecursiveMode)>,
+) {
+    let removed = current_watch_paths
+        .iter()
+        .filter(|watch_path| !watch_paths.contains(watch_path))
+        .cloned()
+        .collect::<Vec<_>>();
+    let added = watch_paths
+        .iter()
+        .filter(|watch_path| !current_watch_paths.contains(watch_path))
+        .cloned()
+        .collect::<Vec<_>>();
+    unregister_watches(watcher, &removed);
+    if let Err(e) = register_watches(watcher, &added) {
+        log::error!("Could not watch all source folders: {e}");
+    }
+    *current_watch_paths = watch_paths;
+}
+
+fn create_watcher<F: EventHandler>(
+    event_handler: F,
+    poll_interval: Option<Duration>,
+) -> Result<Box<dyn Watcher>> {
+    Ok(match poll_interval {
+        Some(poll_interval) => Box::new(PollWatcher::new(
+            event_handler,
+            Config::default().with_poll_interval(poll_interval),
+        )?),
+        None => Box::new(RecommendedWatcher::new(event_handler, Config::default())?),
+    })
+}
+
+/// Creates the watcher, polling for changes when there is a poll interval, and registers the
+/// watch paths. When the OS can't watch all paths because of its limit of watches (the inotify
+/// limit on Linux), the watcher falls back to polling.
+fn create_and_register_watcher<F: EventHandler + Clone>(
+    event_handler: F,
+    poll_interval: Option<Duration>,
+    watch_paths: &[(PathBuf, RecursiveMode)],
+) -> Result<Box<dyn Watcher>> {
+    if let Some(poll_interval) = poll_interval {
+        log::debug!("polling for changes every {}ms", poll_interval.as_millis());
+    }
+    let mut watcher = create_watcher(event_handler.clone(), poll_interval)?;
+    match register_watches(watcher.as_mut(), watch_paths) {
+        Ok(()) => Ok(watcher),
+        Err(e) => {
+            log::warn!(
+                "Could not watch all source folders ({e}), polling for changes every {DEFAULT_POLL_INTERVAL_MS}ms instead. Raise the limit (fs.inotify.max_user_watches on Linux), or pass --poll to poll right away."
+            );
+            unregister_watches(watcher.as_mut(), watch_paths);
+            let mut watcher = create_watcher(
+                event_handler,
+                Some(Duration::from_millis(DEFAULT_POLL_INTERVAL_MS)),
+            )?;
+            register_watches(watcher.as_mut(), watch_paths)?;
+            Ok(watcher)
+        }
+    }
+}
+
+/// Moves the events of the queue to `events` until they settled: until `debounce` passed since the
+/// first event, or with `wait_for_quiet`, until no event arrived for `debounce`.
+async fn collect_events(
+    q: &FifoQueue<Result<Event, Error>>,
+    events: &mut Vec<Event>,
+    debounce: Duration,
+    wait_for_quiet: bool,
+) {
+    let started = Instant::now();
+    let mut last_event = started;
+    loop {
+        while !q.is_empty() {
+            if let Ok(event) = q.pop() {
+                events.push(event);
+                last_event = Instant::now();
+            }
+        }
+        let settled_at = if wait_for_quiet {
+            last_event + debounce
+        } else {
+            started + debounce
+        };
+        let now = Instant::now();
+        if now >= settled_at {
+            break;
+        }
+        Delay::new((settled_at - now).min(IDLE_INTERVAL)).await;
+    }
+}
+
+/// Prints the warnings of the last build, for when they scrolled out of view.
+fn print_warnings(build_state: &BuildCommandState) {
+    let mut modules = build_state.modules.iter().collect::<Vec<_>>();
+    modules.sort_by_key(|(module_name, _)| *module_name);
+    let warnings = modules
+        .into_iter()
+        .flat_map(|(_, module)| {
+            module
+                .parse_diagnostics
+                .iter()
+                .chain(module.compile_diagnostics.iter())
+        })
+        .filter(|diagnostic| diagnostic.severity == Severity::Warning)
+        .map(|diagnostic| diagnostic.rendered.as_str())
+        .collect::<Vec<&str>>();
+    if warnings.is_empty() {
+        println!("No warnings");
+    } else {
+        eprintln!("{}", warnings.join("\n"));
+    }
+}
+
 struct AsyncWatchArgs<'a> {
-    watcher: &'a mut RecommendedWatcher,
+    watcher: &'a mut dyn Watcher,
     current_watch_paths: Vec<(PathBuf, RecursiveMode)>,
     initial_build_state: BuildCommandState,
     q: Arc<FifoQueue<Result<Event, Error>>>,
+    requests: Option<Requests>,
+    keys: Option<KeyCommands>,
+    watched_files: Arc<Mutex<WatchedFiles>>,
     path: &'a Path,
     show_progress: bool,
     filter: &'a Option<regex::Regex>,
     after_build: Option<String>,
     create_sourcedirs: bool,
     plain_output: bool,
+    json_output: bool,
+    debounce: Duration,
+    wait_for_quiet: bool,
 }
 
 async fn async_watch(
@@ -159,12 +427,18 @@ async fn async_watch(
         mut current_watch_paths,
         initial_build_state,
         q,
+        requests,
+        keys,
+        watched_files,
         path,
         show_progress,
         filter,
         after_build,
         create_sourcedirs,
         plain_output,
+        json_output,
+        debounce,
+        wait_for_quiet,
     }: AsyncWatchArgs<'_>,
 ) -> Result<()> {
     let mut build_state = initial_build_state;
@@ -180,26 +454,107 @@ async fn async_watch(
     })
     .expect("Error setting Ctrl-C handler");
 
+    let started_at = SystemTime::now();
     let mut initial_build = true;
+    let mut last_build_success = false;
+    // build requests from the socket, answered after the next build
+    let mut pending_builds: Vec<mpsc::Sender<daemon::Response>> = vec![];
+    // the filter can be turned off and on again with the `f` key
+    let mut filter_enabled = true;
+    let mut show_help = keys.is_some();
 
     loop {
-        if *ctrlc_pressed_clone.lock().unwrap() {
+        let mut quit_requested = false;
+        while let Some(keys) = keys.as_ref().filter(|keys| !keys.is_empty()) {
+            match keys.pop() {
+                KeyCommand::Rebuild => {
+                    println!("Rebuilding...");
+                    needs_compile_type = CompileType::Full;
+                }
+                KeyCommand::Clear => {
+                    let _ = Term::stdout().clear_screen();
+                }
+                KeyCommand::Warnings => print_warnings(&build_state),
+                KeyCommand::ToggleFilter => match filter {
+                    Some(regex) => {
+                        filter_enabled = !filter_enabled;
+                        let state = if filter_enabled { "enabled" } else { "disabled" };
+                        println!("Filter {regex} {state}");
+                        needs_compile_type = CompileType::Full;
+                    }
+                    None => println!("No filter was given with --filter"),
+                },
+                KeyCommand::Quit => quit_requested = true,
+            }
+        }
+        let filter = if filter_enabled { filter } else { &None };
+
+        if quit_requested || *ctrlc_pressed_clone.lock().unwrap() {
             if show_progress {
                 println!("\nExiting...");
             }
             clean::cleanup_after_build(&build_state);
+            daemon::stop_serving(path);
             break Ok(());
         }
+        let mut stop_requested = None;
+        while let Some(requests) = requests.as_ref().filter(|requests| !requests.is_empty()) {
+            let daemon::PendingRequest { request, reply } = requests.pop();
+            match request {
+                daemon::Request::Build => pending_builds.push(reply),
+                daemon::Request::CompilerArgs { path } => {
+                    let _ = reply.send(daemon::compiler_args_response(&build_state, &path));
+                }
+                daemon::Request::Status => {
+                    let last_build = if initial_build {
+                        None
+                    } else {
+                        Some(last_build_success)
+                    };
+                    let _ = reply.send(daemon::status_response(
+                        &build_state,
+                        path,
+                        started_at,
+                        last_build,
+                        filter,
+                    ));
+                }
+                daemon::Request::Stop => stop_requested = Some(reply),
+            }
+        }
+        if let Some(reply) = stop_requested {
+            if show_progress {
+                println!("\nExiting... (stop requested)");
+            }
+            clean::cleanup_after_build(&build_state);
+            daemon::stop_serving(path);
+            for pending_build in pending_builds.drain(..) {
+                let _ = pending_build.send(daemon::Response::new(
+                    false,
+                    "The watcher was stopped".to_string(),
+                ));
+            }
+            let _ = reply.send(daemon::Response::new(true, String::new()));
+            return Ok(());
+        }
         let mut events: Vec<Event> = vec![];
-        if !q.is_empty() {
-            // Wait for events to settle
-            Delay::new(Duration::from_millis(50)).await;
+        // A build request can come right after a file was saved, so we give its event a moment
+        // to arrive as well
+        if !q.is_empty() || !pending_builds.is_empty() {
+            // Wait for events to settle, the ones that arrive in the meantime are built together
+            collect_events(&q, &mut events, debounce, wait_for_quiet).await;
+        }
+        // The events that are drained now are part of the next build, later ones cancel it
+        if let Some(cancel) = &build_state.cancel {
+            cancel.store(false, Ordering::Relaxed);
         }
         while !q.is_empty() {
             if let Ok(event) = q.pop() {
                 events.push(event)
             }
         }
+        // files and folders that were created, removed or renamed
+        let mut changed_paths: Vec<PathBuf> = vec![];
 
         for event in events {
             // if there is a file named rescript.lock in the events path, we can quit the watcher
@@ -210,6 +565,7 @@ async fn async_watch(
                     println!("\nExiting... (lockfile removed)");
                 }
                 clean::cleanup_after_build(&build_state);
+                daemon::stop_serving(path);
                 return Ok(());
             }
 
@@ -228,36 +584,60 @@ async fn async_watch(
                 continue;
             }
 
+            // the generators of the input run again before the next build
+            if event
+                .paths
+                .iter()
+                .any(|path| watched_files.lock().unwrap().is_generator_input(path))
+            {
+                log::debug!("generator input changed -> incremental compile");
+                if needs_compile_type == CompileType::None {
+                    needs_compile_type = CompileType::Incremental;
+                }
+                continue;
+            }
+
+            // the build state is updated in place for these, after all events are read
+            if matches!(
+                event.kind,
+                EventKind::Remove(_)
+                    | EventKind::Any
+                    | EventKind::Create(_)
+                    | EventKind::Modify(ModifyKind::Name(_))
+            ) {
+                if needs_compile_type != CompileType::Full {
+                    let watched_files = watched_files.lock().unwrap();
+                    changed_paths.extend(
+                        event
+                            .paths
+                            .into_iter()
+                            .filter(|path| !is_in_build_path(path))
+                            // a generated file that was deleted is generated again by the next build
+                            .filter(|path| !watched_files.is_generator_output(path) || !path.exists()),
+                    );
+                }
+                continue;
+            }
+
             let paths = event
                 .paths
                 .iter()
                 .filter(|path| is_rescript_file(path))
                 .filter(|path| !is_in_build_path(path))
-                .filter(|path| matches_filter(path, filter));
+                .filter(|path| matches_filter(path, filter))
+                .filter(|path| watched_files.lock().unwrap().includes(path));
             for path in paths {
                 let path_buf = path.to_path_buf();
 
                 match (needs_compile_type, event.kind) {
-                    (
-                        CompileType::Incremental | CompileType::None,
-                        // when we have a name change, create or remove event we need to do a full compile
-                        EventKind::Remove(_)
-                        | EventKind::Any
-                        | EventKind::Create(_)
-                        | EventKind::Modify(ModifyKind::Name(_)),
-                    ) => {
-                        // if we are going to do a full compile, we don't need to bother marking
-                        // files dirty because we do a full scan anyway
-                        log::debug!("received {:?} while needs_compile_type was {needs_compile_type:?} -> full compile", event.kind);
-                        needs_compile_type = CompileType::Full;
-                    }
-
                     (
                         CompileType::None | CompileType::Incremental,
                         // when we have a data change event, we can do an incremental compile
                         EventKind::Modify(ModifyKind::Data(_)) |
                         // windows sends ModifyKind::Any on file content changes
-                        EventKind::Modify(ModifyKind::Any),
+                        EventKind::Modify(ModifyKind::Any) |
+                        // the poll watcher only sees that the modification time changed
+                        EventKind::Modify(ModifyKind::Metadata(MetadataKind::WriteTime)),
                     ) => {
                         // if we are going to compile incrementally, we need to mark the exact files
                         // dirty
@@ -317,11 +697,8 @@ async fn async_watch(
 
                     (
                         CompileType::None | CompileType::Incremental,
-                        // these are not relevant events for compilation
-                        EventKind::Access(_)
-                        | EventKind::Other
-                        | EventKind::Modify(ModifyKind::Metadata(_))
-                        | EventKind::Modify(ModifyKind::Other),
+                        // these are not relevant events for compilation, or were handled above
+                        _,
                     ) => (),
                     // if we already need a full compile, we don't need to check for other events
                     (CompileType::Full, _) => (),
@@ -329,14 +706,34 @@ async fn async_watch(
             }
         }
 
+        if needs_compile_type != CompileType::Full && !changed_paths.is_empty() {
+            match source_files::update(&mut build_state, &changed_paths, filter) {
+                source_files::Update::Unchanged => (),
+                source_files::Update::Updated => {
+                    log::debug!("updated the source files in place -> incremental compile");
+                    needs_compile_type = CompileType::Incremental;
+                }
+                source_files::Update::NeedsFullBuild => {
+                    log::debug!("source files can't be updated in place -> full compile");
+                    needs_compile_type = CompileType::Full;
+                }
+            }
+            // new folders need their own watch when their source folder has ignored folders
+            if changed_paths.iter().any(|changed_path| changed_path.is_dir()) {
+                let watch_paths = compute_watch_paths(&build_state, path);
+                update_watches(watcher, &mut current_watch_paths, watch_paths);
+            }
+        }
+
         if needs_compile_type != CompileType::None {
             log::debug!("doing {needs_compile_type:?}");
         }
 
+        let mut build_cancelled = false;
         match needs_compile_type {
             CompileType::Incremental => {
                 let timing_total = Instant::now();
-                if build::incremental_build(
+                let result = build::incremental_build(
                     &mut build_state,
                     None,
                     initial_build,
@@ -344,9 +741,13 @@ async fn async_watch(
                     !initial_build,
                     create_sourcedirs,
                     plain_output,
-                )
-                .is_ok()
-                {
+                    json_output,
+                );
+                build_cancelled = is_cancelled(&result);
+                if !build_cancelled {
+                    last_build_success = result.is_ok();
+                }
+                if last_build_success && !build_cancelled {
                     if let Some(a) = after_build.clone() {
                         cmd::run(a)
                     }
@@ -367,26 +768,36 @@ async fn async_watch(
                     }
                 }
                 needs_compile_type = CompileType::None;
-                initial_build = false;
+                initial_build = initial_build && build_cancelled;
             }
             CompileType::Full => {
                 let timing_total = Instant::now();
+                let keep_going = build_state.keep_going;
+                let artifact_cache = build_state.artifact_cache.take();
+                let cancel = build_state.cancel.take();
                 build_state = build::initialize_build(
                     None,
                     filter,
                     show_progress,
                     path,
                     plain_output,
+                    json_output,
                     build_state.get_warn_error_override(),
                 )
                 .expect("Could not initialize build");
+                build_state.keep_going = keep_going;
+                build_state.artifact_cache = artifact_cache;
+                build_state.cancel = cancel;
+                *watched_files.lock().unwrap() = WatchedFiles::new(&build_state);
 
                 // Re-register watches based on the new build state
                 unregister_watches(watcher, &current_watch_paths);
                 current_watch_paths = compute_watch_paths(&build_state, path);
-                register_watches(watcher, &current_watch_paths);
+                if let Err(e) = register_watches(watcher, &current_watch_paths) {
+                    log::error!("Could not watch all source folders: {e}");
+                }
 
-                let _ = build::incremental_build(
+                let result = build::incremental_build(
                     &mut build_state,
                     None,
                     initial_build,
@@ -394,15 +805,20 @@ async fn async_watch(
                     false,
                     create_sourcedirs,
                     plain_output,
+                    json_output,
                 );
-                if let Some(a) = after_build.clone() {
-                    cmd::run(a)
+                build_cancelled = is_cancelled(&result);
+                if !build_cancelled {
+                    last_build_success = result.is_ok();
+                    if let Some(a) = after_build.clone() {
+                        cmd::run(a)
+                    }
                 }
 
                 build::write_build_ninja(&build_state);
 
                 let timing_total_elapsed = timing_total.elapsed();
-                if !plain_output && show_progress {
+                if !plain_output && show_progress && !build_cancelled {
                     println!(
                         "\n{}{}Finished compilation in {:.2}s\n",
                         LINE_CLEAR,
@@ -411,12 +827,29 @@ async fn async_watch(
                     );
                 }
                 needs_compile_type = CompileType::None;
-                initial_build = false;
+                initial_build = initial_build && build_cancelled;
             }
             CompileType::None => {
                 // We want to sleep for a little while so the CPU can schedule other work. That way we end
                 // up not burning CPU cycles.
-                Delay::new(Duration::from_millis(50)).await;
+                Delay::new(IDLE_INTERVAL).await;
+            }
+        }
+
+        if show_help && !initial_build {
+            println!("{}", keys::HELP);
+            show_help = false;
+        }
+
+        if build_cancelled {
+            log::debug!("build was cancelled because files changed");
+            if show_progress {
+                println!("Files changed, restarting compilation");
+            }
+        } else if !pending_builds.is_empty() {
+            let response = daemon::build_response(&build_state, last_build_success);
+            for reply in pending_builds.drain(..) {
+                let _ = reply.send(response.clone());
             }
         }
     }
@@ -430,45 +863,337 @@ pub fn start(
     after_build: Option<String>,
     create_sourcedirs: bool,
     plain_output: bool,
+    json_output: bool,
     warn_error: Option<String>,
+    keep_going: bool,
+    artifact_cache: Option<ArtifactCache>,
+    options: WatchOptions,
 ) -> Result<()> {
     futures::executor::block_on(async {
         let queue = Arc::new(FifoQueue::<Result<Event, Error>>::new());
         let producer = queue.clone();
         let consumer = queue.clone();
 
-        let mut watcher = RecommendedWatcher::new(move |res| producer.push(res), Config::default())
-            .expect("Could not create watcher");
+        // Events that make the current build outdated cancel it right away, instead of after it
+        // finished
+        let cancel = Arc::new(AtomicBool::new(false));
+        let producer_cancel = cancel.clone();
+        let producer_filter = filter.clone();
+        let watched_files = Arc::new(Mutex::new(WatchedFiles::default()));
+        let producer_watched_files = watched_files.clone();
+        let event_handler = move |res: Result<Event, Error>| {
+            if let Ok(event) = &res
+                && cancels_build(event, &producer_filter, &producer_watched_files.lock().unwrap())
+            {
+                producer_cancel.store(true, Ordering::Relaxed);
+            }
+            producer.push(res)
+        };
 
         let path = Path::new(folder);
 
+        // Other processes can request builds from the socket as soon as the initial build is done
+        let requests = match daemon::serve(path, cancel.clone()) {
+            Ok(requests) => Some(requests),
+            Err(err) => {
+                log::warn!("{err:#}, builds can't be requested from this watcher");
+                None
+            }
+        };
+
         // Do an initial build to discover packages and source folders
-        let build_state: BuildCommandState = build::initialize_build(
+        let mut build_state: BuildCommandState = build::initialize_build(
             None,
             filter,
             show_progress,
             path,
             plain_output,
+            json_output,
             warn_error.clone(),
         )
         .with_context(|| "Could not initialize build")?;
+        build_state.keep_going = keep_going;
+        build_state.artifact_cache = artifact_cache;
+        build_state.cancel = Some(cancel);
+        *watched_files.lock().unwrap() = WatchedFiles::new(&build_state);
 
-        // Compute and register targeted watches based on source folders
+        // Compute and register targeted watches based on source folders. The options of the command
+        // line take precedence over the config.
+        let config = build_state.get_root_config();
+        let poll_interval = options.poll_interval.or_else(|| config.get_poll_interval());
+        let debounce = options
+            .debounce
+            .or_else(|| config.get_debounce())
+            .unwrap_or(Duration::from_millis(DEFAULT_DEBOUNCE_MS));
+        let wait_for_quiet = options
+            .wait_for_quiet
+            .or_else(|| config.get_wait_for_quiet())
+            .unwrap_or(false);
         let current_watch_paths = compute_watch_paths(&build_state, path);
-        register_watches(&mut watcher, &current_watch_paths);
+        let mut watcher = create_and_register_watcher(event_handler, poll_interval, &current_watch_paths)
+            .with_context(|| "Could not create watcher")?;
+
+        // Single-key commands are only read in an interactive terminal, the guard restores the
+        // terminal when the watcher stops
+        let (keys, _raw_mode) = if show_progress && !plain_output {
+            keys::listen().unzip()
+        } else {
+            (None, None)
+        };
 
         async_watch(AsyncWatchArgs {
-            watcher: &mut watcher,
+            watcher: watcher.as_mut(),
             current_watch_paths,
             initial_build_state: build_state,
             q: consumer,
+            requests,
+            keys,
+            watched_files,
             path,
             show_progress,
             filter,
             after_build,
             create_sourcedirs,
             plain_output,
+            json_output,
+            debounce,
+            wait_for_quiet,
         })
         .await
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use notify::event::{AccessKind, CreateKind, DataChange, RemoveKind};
+
+    fn event(kind: EventKind, path: &str) -> Event {
+        Event::new(kind).add_path(PathBuf::from(path))
+    }
+
+    #[test]
+    fn source_changes_cancel_builds() {
+        let modify = EventKind::Modify(ModifyKind::Data(DataChange::Content));
+        assert!(cancels_build(
+            &event(modify, "/project/src/Main.res"),
+            &None,
+            &WatchedFiles::default()
+        ));
+        assert!(cancels_build(
+            &event(EventKind::Create(CreateKind::File), "/project/src/New.resi"),
+            &None,
+            &WatchedFiles::default()
+        ));
+        assert!(cancels_build(
+            &event(modify, "/project/rescript.json"),
+            &None,
+            &WatchedFiles::default()
+        ));
+        assert!(cancels_build(
+            &event(EventKind::Remove(RemoveKind::File), "/project/lib/rescript.lock"),
+            &None,
+            &WatchedFiles::default()
+        ));
+    }
+
+    #[test]
+    fn polled_changes_cancel_builds() {
+        let write_time = EventKind::Modify(ModifyKind::Metadata(MetadataKind::WriteTime));
+        assert!(cancels_build(
+            &event(write_time, "/project/src/Main.res"),
+            &None,
+            &WatchedFiles::default()
+        ));
+        let permissions = EventKind::Modify(ModifyKind::Metadata(MetadataKind::Permissions));
+        assert!(!cancels_build(
+            &event(permissions, "/project/src/Main.res"),
+            &None,
+            &WatchedFiles::default()
+        ));
+    }
+
+    fn queue_with_event(path: &str) -> FifoQueue<Result<Event, Error>> {
+        let q = FifoQueue::new();
+        q.push(Ok(event(EventKind::Create(CreateKind::File), path)));
+        q
+    }
+
+    #[test]
+    fn collects_events_during_the_debounce_window() {
+        let q = Arc::new(queue_with_event("/project/src/A.res"));
+        let producer = q.clone();
+        let handle = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(50));
+            producer.push(Ok(event(
+                EventKind::Create(CreateKind::File),
+                "/project/src/B.res",
+            )));
+        });
+        let mut events = vec![];
+        let started = Instant::now();
+        futures::executor::block_on(collect_events(&q, &mut events, Duration::from_millis(300), false));
+        handle.join().unwrap();
+
+        assert_eq!(events.len(), 2);
+        assert!(started.elapsed() >= Duration::from_millis(300));
+        assert!(q.is_empty());
+    }
+
+    #[test]
+    fn waits_for_a_quiet_period() {
+        let q = Arc::new(queue_with_event("/project/src/A.res"));
+        let producer = q.clone();
+        // a burst that lasts longer than the debounce window
+        let handle = std::thread::spawn(move || {
+            for _ in 0..5 {
+                std::thread::sleep(Duration::from_millis(40));
+                producer.push(Ok(event(
+                    EventKind::Create(CreateKind::File),
+                    "/project/src/B.res",
+                )));
+            }
+        });
+        let mut events = vec![];
+        let started = Instant::now();
+        futures::executor::block_on(collect_events(&q, &mut events, Duration::from_millis(100), true));
+        handle.join().unwrap();
+
+        assert_eq!(events.len(), 6);
+        assert!(started.elapsed() >= Duration::from_millis(300));
+    }
+
+    #[test]
+    fn polls_for_changes() {
+        let temp_dir = tempfile::TempDir::new().expect("temp dir should be created");
+        let (sender, receiver) = mpsc::channel();
+        let _watcher = create_and_register_watcher(
+            move |res: Result<Event, Error>| {
+                let _ = sender.send(res);
+            },
+            Some(Duration::from_millis(20)),
+            &[(temp_dir.path().to_path_buf(), RecursiveMode::Recursive)],
+        )
+        .expect("watcher should be created");
+
+        std::fs::write(temp_dir.path().join("Main.res"), "let x = 1").unwrap();
+        let event = receiver
+            .recv_timeout(Duration::from_secs(5))
+            .expect("the change should be polled")
+            .expect("the event should not be an error");
+        assert_eq!(event.kind, EventKind::Create(CreateKind::Any));
+        assert!(event.paths[0].ends_with("Main.res"));
+    }
+
+    #[test]
+    fn other_changes_do_not_cancel_builds() {
+        let modify = EventKind::Modify(ModifyKind::Data(DataChange::Content));
+        // the outputs of the build itself
+        assert!(!cancels_build(
+            &event(modify, "/project/lib/bs/src/Main.res"),
+            &None,
+            &WatchedFiles::default()
+        ));
+        assert!(!cancels_build(
+            &event(modify, "/project/src/Main.res.mjs"),
+            &None,
+            &WatchedFiles::default()
+        ));
+        assert!(!cancels_build(
+            &event(EventKind::Access(AccessKind::Any), "/project/src/Main.res"),
+            &None,
+            &WatchedFiles::default()
+        ));
+        let filter = Some(regex::Regex::new("_test").unwrap());
+        assert!(!cancels_build(
+            &event(modify, "/project/src/Main_test.res"),
+            &filter,
+            &WatchedFiles::default()
+        ));
+    }
+
+    #[test]
+    fn does_not_watch_ignored_dirs() {
+        let temp_dir = tempfile::TempDir::new().expect("temp dir should be created");
+        let root = temp_dir.path();
+        for dir in ["src/a/b", "src/__fixtures__/c", "src/a/__fixtures__"] {
+            std::fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        let ignored_dirs = config::IgnoredDirs::new(&["__fixtures__".to_string()]);
+
+        let mut dirs = get_watched_dirs(root, Path::new("src"), &ignored_dirs);
+        dirs.sort();
+        assert_eq!(
+            dirs,
+            vec![root.join("src"), root.join("src/a"), root.join("src/a/b")]
+        );
+    }
+
+    #[test]
+    fn excluded_files_do_not_cancel_builds() {
+        let watched_files = WatchedFiles {
+            file_filters: vec![
+                (
+                    PathBuf::from("/project/src"),
+                    true,
+                    config::PackageSource {
+                        dir: "src".to_string(),
+                        subdirs: Some(config::Subdirs::Recurse(true)),
+                        type_: None,
+                        files: Some(config::SourceFiles::Filter {
+                            slow_re: None,
+                            excludes: Some(vec!["Experimental.res".to_string()]),
+                        }),
+                        generators: None,
+                    }
+                    .get_file_filter(),
+                ),
+                (
+                    PathBuf::from("/project/src/all"),
+                    false,
+                    config::FileFilter::default(),
+                ),
+            ],
+            ..WatchedFiles::default()
+        };
+        let modify = EventKind::Modify(ModifyKind::Data(DataChange::Content));
+
+        assert!(!cancels_build(
+            &event(modify, "/project/src/nested/Experimental.res"),
+            &None,
+            &watched_files
+        ));
+        assert!(cancels_build(
+            &event(modify, "/project/src/all/Experimental.res"),
+            &None,
+            &watched_files
+        ));
+        assert!(cancels_build(
+            &event(modify, "/project/src/Main.res"),
+            &None,
+            &watched_files
+        ));
+    }
+
+    #[test]
+    fn generator_inputs_cancel_builds() {
+        let watched_files = WatchedFiles {
+            generator_inputs: AHashSet::from([PathBuf::from("/project/src/Query.graphql")]),
+            generator_outputs: AHashSet::from([PathBuf::from("/project/src/Query.res")]),
+            ..WatchedFiles::default()
+        };
+        let modify = EventKind::Modify(ModifyKind::Data(DataChange::Content));
+
+        assert!(cancels_build(
+            &event(modify, "/project/src/Query.graphql"),
+            &None,
+            &watched_files
+        ));
+        // the build writes the outputs itself
+        assert!(!cancels_build(
+            &event(modify, "/project/src/Query.res"),
+            &None,
+            &watched_files
+        ));
+    }
+}
@@ -0,0 +1,155 @@
+use crate::queue::FifoQueue;
+use crate::queue::*;
+use std::io::{IsTerminal, Read};
+use std::sync::Arc;
+use std::thread;
+
+pub const HELP: &str =
+    "Press r to rebuild, c to clear the screen, w to show warnings, f to toggle the filter, q to quit";
+
+/// Single-key commands of `rescript watch` when stdin is a terminal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyCommand {
+    Rebuild,
+    Clear,
+    Warnings,
+    ToggleFilter,
+    Quit,
+}
+
+impl KeyCommand {
+    pub fn from_key(key: u8) -> Option<KeyCommand> {
+        match key {
+            b'r' => Some(KeyCommand::Rebuild),
+            b'c' => Some(KeyCommand::Clear),
+            b'w' => Some(KeyCommand::Warnings),
+            b'f' => Some(KeyCommand::ToggleFilter),
+            b'q' => Some(KeyCommand::Quit),
+            _ => None,
+        }
+    }
+}
+
+pub type KeyCommands = Arc<FifoQueue<KeyCommand>>;
+
+/// Reads key commands from stdin on a separate thread. The terminal stays in the mode that allows
+/// reading single keys until the returned guard is dropped. Returns `None` when stdin is not a
+/// terminal, when we run in the background (e.g. `rescript watch &`), or when the mode of the
+/// terminal can't be changed.
+pub fn listen() -> Option<(KeyCommands, RawMode)> {
+    if !std::io::stdin().is_terminal() {
+        return None;
+    }
+    let raw_mode = RawMode::enable()?;
+    let commands: KeyCommands = Arc::new(FifoQueue::new());
+    let producer = commands.clone();
+    thread::spawn(move || {
+        for key in std::io::stdin().lock().bytes() {
+            match key {
+                Ok(key) => {
+                    if let Some(command) = KeyCommand::from_key(key) {
+                        producer.push(command)
+                    }
+                }
+                Err(err) => {
+                    log::debug!("Stopped reading keys: {err}");
+                    break;
+                }
+            }
+        }
+    });
+    Some((commands, raw_mode))
+}
+
+#[cfg(unix)]
+pub use unix::RawMode;
+
+#[cfg(unix)]
+mod unix {
+    use std::mem::MaybeUninit;
+    use std::sync::OnceLock;
+
+    /// The mode of the terminal before we changed it, so that it can be restored on exit.
+    static ORIGINAL: OnceLock<libc::termios> = OnceLock::new();
+
+    /// Keys are read without waiting for a newline and without echoing them. Ctrl-C still sends
+    /// SIGINT and the output is processed as usual.
+    ///
+    /// `console::Term::read_key` only changes the mode while it waits for a key, so the terminal
+    /// would stay in that mode when we exit while it waits. That's why we keep the mode ourselves.
+    pub struct RawMode;
+
+    impl RawMode {
+        pub fn enable() -> Option<RawMode> {
+            // A process in the background is stopped (SIGTTOU/SIGTTIN) when it changes the mode
+            // of the terminal or reads from it
+            if unsafe { libc::tcgetpgrp(libc::STDIN_FILENO) != libc::getpgrp() } {
+                return None;
+            }
+            let mut termios = MaybeUninit::<libc::termios>::uninit();
+            if unsafe { libc::tcgetattr(libc::STDIN_FILENO, termios.as_mut_ptr()) } != 0 {
+                return None;
+            }
+            let original = *ORIGINAL.get_or_init(|| unsafe { termios.assume_init() });
+            let mut raw = original;
+            raw.c_lflag &= !(libc::ICANON | libc::ECHO);
+            raw.c_cc[libc::VMIN] = 1;
+            raw.c_cc[libc::VTIME] = 0;
+            if unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &raw) } != 0 {
+                return None;
+            }
+            // When we are moved to the background later on, reading a key fails instead of
+            // stopping the process, which ends the thread that reads the keys
+            unsafe { libc::signal(libc::SIGTTIN, libc::SIG_IGN) };
+            // Some code paths end the process with `std::process::exit`, which does not run our
+            // destructors but does run the handlers registered with `atexit`
+            unsafe { libc::atexit(restore) };
+            Some(RawMode)
+        }
+    }
+
+    impl Drop for RawMode {
+        fn drop(&mut self) {
+            restore();
+        }
+    }
+
+    extern "C" fn restore() {
+        if let Some(original) = ORIGINAL.get() {
+            // also restore the terminal when we were moved to the background in the meantime
+            unsafe { libc::signal(libc::SIGTTOU, libc::SIG_IGN) };
+            unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, original) };
+        }
+    }
+}
+
+#[cfg(not(unix))]
+pub use other::RawMode;
+
+#[cfg(not(unix))]
+mod other {
+    /// Reading single keys is only supported on unix terminals.
+    pub struct RawMode;
+
+    impl RawMode {
+        pub fn enable() -> Option<RawMode> {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maps_keys_to_commands() {
+        assert_eq!(KeyCommand::from_key(b'r'), Some(KeyCommand::Rebuild));
+        assert_eq!(KeyCommand::from_key(b'c'), Some(KeyCommand::Clear));
+        assert_eq!(KeyCommand::from_key(b'w'), Some(KeyCommand::Warnings));
+        assert_eq!(KeyCommand::from_key(b'f'), Some(KeyCommand::ToggleFilter));
+        assert_eq!(KeyCommand::from_key(b'q'), Some(KeyCommand::Quit));
+        assert_eq!(KeyCommand::from_key(b'x'), None);
+        assert_eq!(KeyCommand::from_key(b'\n'), None);
+    }
+}
@@ -0,0 +1,27 @@
+#!/bin/bash
+cd $(dirname $0)
+source "../utils.sh"
+cd ../../testrepo
+
+bold "Test: --json prints only NDJSON build events on stdout"
+
+rewatch clean &> /dev/null
+rewatch build --json > ../tests/json-output.txt 2> /dev/null
+
+# Every line must be a JSON object with an `event` field, and the last one is the build summary
+if node -e '
+  const lines = require("fs").readFileSync(process.argv[1], "utf8").trim().split("\n");
+  const events = lines.map(line => JSON.parse(line));
+  if (!events.every(event => typeof event.event === "string")) process.exit(1);
+  const last = events[events.length - 1];
+  if (last.event !== "finished" || last.success !== true) process.exit(1);
+' ../tests/json-output.txt;
+then
+  success "Build emitted valid NDJSON events"
+  rm ../tests/json-output.txt
+else
+  error "Build did not emit valid NDJSON events"
+  cat ../tests/json-output.txt
+  rm ../tests/json-output.txt
+  exit 1
+fi
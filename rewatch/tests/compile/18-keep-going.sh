@@ -0,0 +1,34 @@
+#!/bin/bash
+cd $(dirname $0)
+source "../utils.sh"
+cd ../../testrepo
+
+bold "Test: --keep-going compiles everything that doesn't depend on a failed module"
+
+rewatch clean &> /dev/null
+
+echo 'let broken = 1 + "a"' >> packages/dep02/src/Dep02.res
+rewatch build --keep-going --json > ../tests/keep-going.txt 2> /dev/null
+build_status=$?
+git checkout -- packages/dep02/src/Dep02.res
+
+# Dep01 depends on the broken Dep02 and is skipped, the unrelated InternalDep is still compiled
+if [ $build_status -ne 0 ] && node -e '
+  const lines = require("fs").readFileSync(process.argv[1], "utf8").trim().split("\n");
+  const events = lines.map(line => JSON.parse(line));
+  const skipped = events.find(e => e.event === "module_skipped" && e.module === "Dep01");
+  const compiled = events.find(e => e.event === "module_compiled" && e.module === "InternalDep");
+  const last = events[events.length - 1];
+  if (!skipped || skipped.failed_dependency !== "Dep02" || !compiled || last.success !== false) process.exit(1);
+' ../tests/keep-going.txt;
+then
+  success "Keep going skipped only the dependents of the failed module"
+  rm ../tests/keep-going.txt
+else
+  error "Keep going did not compile the independent modules"
+  cat ../tests/keep-going.txt
+  rm ../tests/keep-going.txt
+  exit 1
+fi
+
+rewatch build &> /dev/null
@@ -0,0 +1,23 @@
+#!/bin/bash
+cd $(dirname $0)
+source "../utils.sh"
+cd ../../testrepo
+
+bold "Test: Sources that are touched but not changed are not parsed again"
+
+rewatch clean &> /dev/null
+rewatch build &> /dev/null
+
+# Like a git checkout: every source file gets a new modification time, but the same content
+sleep 1
+find packages -name "*.res" -not -path "*/node_modules/*" -exec touch {} +
+
+output=$(rewatch build 2>&1)
+if echo "$output" | grep -q "Parsed 0 source files";
+then
+  success "Touched sources were not parsed again"
+else
+  error "Touched sources were parsed again"
+  echo "$output"
+  exit 1
+fi
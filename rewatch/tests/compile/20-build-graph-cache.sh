@@ -0,0 +1,41 @@
+#!/bin/bash
+cd $(dirname $0)
+source "../utils.sh"
+cd ../../testrepo
+
+bold "Test: The cached build graph picks up new and removed files"
+
+rewatch clean &> /dev/null
+rewatch build &> /dev/null
+
+if [ -f lib/bs/build-graph.json ];
+then
+  success "Build graph cache was written"
+else
+  error "Build graph cache was not written"
+  exit 1
+fi
+
+echo 'let value = 1' > packages/main/src/BuildGraphCache.res
+rewatch build &> /dev/null
+
+if [ -f packages/main/src/BuildGraphCache.mjs ];
+then
+  success "New file was compiled with a cached build graph"
+else
+  error "New file was not compiled with a cached build graph"
+  rm -f packages/main/src/BuildGraphCache.res
+  exit 1
+fi
+
+rm packages/main/src/BuildGraphCache.res
+rewatch build &> /dev/null
+
+if [ ! -f packages/main/src/BuildGraphCache.mjs ];
+then
+  success "Removed file was cleaned up with a cached build graph"
+else
+  error "Removed file was not cleaned up with a cached build graph"
+  rm -f packages/main/src/BuildGraphCache.mjs
+  exit 1
+fi
@@ -0,0 +1,69 @@
+#!/bin/bash
+cd $(dirname $0)
+source "../utils.sh"
+cd ../../testrepo
+
+bold "Test: Compiler outputs are restored from the artifact cache"
+
+cache_dir=$(mktemp -d)
+
+rewatch clean &> /dev/null
+rewatch build --cache-dir "$cache_dir" &> /dev/null
+
+entries=$(find "$cache_dir" -name stderr | wc -l)
+if [ "$entries" -gt 0 ];
+then
+  success "Compiler outputs were stored in the artifact cache"
+else
+  error "No compiler outputs were stored in the artifact cache"
+  rm -rf "$cache_dir"
+  exit 1
+fi
+
+rewatch clean &> /dev/null
+rewatch build --cache-dir "$cache_dir" --timings=json &> /dev/null
+
+# bsc invocations are recorded in the timings, restored modules are not
+compiled=$(grep -c '"kind": "compile' lib/bs/rescript-timings.json)
+rm -f lib/bs/rescript-timings.json
+if [ "$compiled" -eq 0 ];
+then
+  success "All modules were restored from the artifact cache"
+else
+  error "$compiled modules were compiled instead of restored from the artifact cache"
+  rm -rf "$cache_dir"
+  exit 1
+fi
+
+# the .cmi and .cmj files of the modules (not of the namespaces, which aren't cached) have to be
+# the ones in the cache
+cached=$(find "$cache_dir" -type f \( -name cmi -o -name cmj \) -exec cksum {} \; | cut -d ' ' -f 1,2 | sort -u)
+restored=$(find . -type d -path "*/lib/bs" -not -path "*/node_modules/*" \
+  -exec find {} -mindepth 2 \( -name "*.cmi" -o -name "*.cmj" \) \; | sort)
+mismatches=0
+for file in $restored;
+do
+  if ! echo "$cached" | grep -qx "$(cksum < "$file" | cut -d ' ' -f 1,2)";
+  then
+    echo "$file is not in the artifact cache"
+    mismatches=$((mismatches + 1))
+  fi
+done
+rm -rf "$cache_dir"
+
+if [ -n "$restored" ] && [ "$mismatches" -eq 0 ];
+then
+  success "Restored .cmi and .cmj files are the ones in the artifact cache"
+else
+  error "Restored .cmi and .cmj files differ from the ones in the artifact cache"
+  exit 1
+fi
+
+if git diff --exit-code ./ &> /dev/null;
+then
+  success "Restored outputs are the same as compiled outputs"
+else
+  error "Restored outputs differ from compiled outputs"
+  git diff ./
+  exit 1
+fi
@@ -0,0 +1,36 @@
+#!/bin/bash
+cd $(dirname $0)
+source "../utils.sh"
+cd ../../testrepo
+
+bold "Test: Folders in ignored-dirs are not compiled"
+
+rewatch clean &> /dev/null
+
+mkdir -p src/__fixtures__
+echo 'let broken = 1 + "a"' > src/__fixtures__/Broken.res
+node -e '
+  const fs = require("fs");
+  const config = JSON.parse(fs.readFileSync("rescript.json", "utf8"));
+  config["ignored-dirs"] = ["__fixtures__"];
+  fs.writeFileSync("rescript.json", JSON.stringify(config, null, 2) + "\n");
+'
+
+rewatch build &> ../tests/ignored-dirs.txt
+build_status=$?
+
+rm -rf src/__fixtures__
+git checkout -- rescript.json
+
+if [ $build_status -eq 0 ] && ! grep -q "'ignored-dirs'" ../tests/ignored-dirs.txt;
+then
+  success "The ignored folder was not compiled"
+  rm ../tests/ignored-dirs.txt
+else
+  error "The ignored folder was compiled"
+  cat ../tests/ignored-dirs.txt
+  rm ../tests/ignored-dirs.txt
+  exit 1
+fi
+
+rewatch build &> /dev/null
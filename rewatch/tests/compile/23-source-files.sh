@@ -0,0 +1,36 @@
+#!/bin/bash
+cd $(dirname $0)
+source "../utils.sh"
+cd ../../testrepo
+
+bold "Test: Files that are excluded from a source folder are not compiled"
+
+rewatch clean &> /dev/null
+
+echo 'let broken = 1 + "a"' > src/Experimental.res
+node -e '
+  const fs = require("fs");
+  const config = JSON.parse(fs.readFileSync("rescript.json", "utf8"));
+  config.sources.files = { "excludes": ["Experimental.res"] };
+  fs.writeFileSync("rescript.json", JSON.stringify(config, null, 2) + "\n");
+'
+
+rewatch build &> ../tests/source-files.txt
+build_status=$?
+
+rm src/Experimental.res
+git checkout -- rescript.json
+
+if [ $build_status -eq 0 ] && [ ! -f src/Experimental.mjs ];
+then
+  success "The excluded file was not compiled"
+  rm ../tests/source-files.txt
+else
+  error "The excluded file was compiled"
+  cat ../tests/source-files.txt
+  rm ../tests/source-files.txt
+  rm -f src/Experimental.mjs
+  exit 1
+fi
+
+rewatch build &> /dev/null
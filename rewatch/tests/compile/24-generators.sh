@@ -0,0 +1,35 @@
+#!/bin/bash
+cd $(dirname $0)
+source "../utils.sh"
+cd ../../testrepo
+
+bold "Test: Generators create source files before parsing"
+
+rewatch clean &> /dev/null
+
+echo 'let generated = 42' > src/Generated.txt
+node -e '
+  const fs = require("fs");
+  const config = JSON.parse(fs.readFileSync("rescript.json", "utf8"));
+  config.generators = [{ "name": "copy", "command": "cp $in $out" }];
+  config.sources.generators = [{ "name": "copy", "edge": ["Generated.res", ":", "Generated.txt"] }];
+  fs.writeFileSync("rescript.json", JSON.stringify(config, null, 2) + "\n");
+'
+
+rewatch build &> ../tests/generators.txt
+build_status=$?
+
+if [ $build_status -eq 0 ] && [ -f src/Generated.res ] && [ -f src/Generated.mjs ] && grep -q '"src/Generated.res"' lib/bs/.sourcedirs.json;
+then
+  success "The generated file was compiled"
+  result=0
+else
+  error "The generated file was not compiled"
+  cat ../tests/generators.txt
+  result=1
+fi
+
+rm -f ../tests/generators.txt src/Generated.txt src/Generated.res src/Generated.mjs
+git checkout -- rescript.json
+rewatch build &> /dev/null
+exit $result
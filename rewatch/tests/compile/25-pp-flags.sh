@@ -0,0 +1,36 @@
+#!/bin/bash
+cd $(dirname $0)
+source "../utils.sh"
+cd ../../testrepo
+
+bold "Test: pp-flags preprocesses sources before parsing"
+
+rewatch clean &> /dev/null
+
+echo 'let value = PP_VALUE' > src/Preprocessed.res
+node -e '
+  const fs = require("fs");
+  const config = JSON.parse(fs.readFileSync("rescript.json", "utf8"));
+  config["pp-flags"] = "sed s/PP_VALUE/42/";
+  fs.writeFileSync("rescript.json", JSON.stringify(config, null, 2) + "\n");
+'
+
+rewatch build &> ../tests/pp-flags.txt
+build_status=$?
+rewatch compiler-args src/Preprocessed.res > ../tests/pp-flags-args.txt 2>&1
+
+if [ $build_status -eq 0 ] && grep -q '42' src/Preprocessed.mjs && grep -q '"sed s/PP_VALUE/42/"' ../tests/pp-flags-args.txt;
+then
+  success "The preprocessed source was compiled"
+  result=0
+else
+  error "The preprocessed source was not compiled"
+  cat ../tests/pp-flags.txt ../tests/pp-flags-args.txt
+  result=1
+fi
+
+rm -f ../tests/pp-flags.txt ../tests/pp-flags-args.txt src/Preprocessed.res src/Preprocessed.mjs
+git checkout -- rescript.json
+rewatch clean &> /dev/null
+rewatch build &> /dev/null
+exit $result
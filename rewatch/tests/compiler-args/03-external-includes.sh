@@ -0,0 +1,28 @@
+#!/bin/bash
+cd $(dirname $0)
+source "../utils.sh"
+cd ../../testrepo
+
+bold "Test: bs-external-includes are passed to the compiler as include folders"
+
+node -e '
+  const fs = require("fs");
+  const path = "packages/file-casing/rescript.json";
+  const config = JSON.parse(fs.readFileSync(path, "utf8"));
+  config["bs-external-includes"] = ["vendor/cmi"];
+  fs.writeFileSync(path, JSON.stringify(config, null, 2) + "\n");
+'
+
+output=$(cd packages/file-casing && "$REWATCH_EXECUTABLE" compiler-args src/Consume.res 2>&1)
+status=$?
+include_dir="$(cd packages/file-casing && pwd)/vendor/cmi"
+
+git checkout -- packages/file-casing/rescript.json
+
+if [ $status -eq 0 ] && echo "$output" | grep -qF "\"$include_dir\""; then
+  success "compiler-args includes the external include folder"
+else
+  error "compiler-args does not include $include_dir"
+  printf "%s\n" "$output" >&2
+  exit 1
+fi
@@ -22,7 +22,8 @@ success "Watcher Started"
 
 sleep 2
 
-if rewatch build 2>&1 | grep 'Could not start ReScript build:' &> /dev/null;
+# build delegates to the watcher, but clean needs the lock
+if rewatch clean 2>&1 | grep 'Could not start ReScript build:' &> /dev/null;
 then
   success "Lock is correctly set"
   exit_watcher
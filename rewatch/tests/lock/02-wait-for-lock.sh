@@ -0,0 +1,44 @@
+#!/bin/bash
+cd $(dirname $0)
+source "../utils.sh"
+cd ../../testrepo
+
+bold "Test: It should wait for the lock with --wait"
+
+mkdir -p lib
+
+# A lock held by another process that finishes in a few seconds
+sleep 3 &
+echo -n $! > lib/rescript.lock
+
+if rewatch build --wait=20 &> /dev/null;
+then
+  success "Build waited for the lock"
+else
+  error "Build did not wait for the lock"
+  exit 1
+fi
+
+if grep '"command":"build"' lib/rescript.lock &> /dev/null;
+then
+  success "Lockfile records the command"
+else
+  error "Lockfile does not record the command"
+  cat lib/rescript.lock
+  exit 1
+fi
+
+sleep 30 &
+sleep_pid=$!
+echo -n $sleep_pid > lib/rescript.lock
+
+if rewatch build --wait=1 2>&1 | grep 'Could not start ReScript build:' &> /dev/null;
+then
+  success "Build gave up waiting after the timeout"
+else
+  error "Build did not time out waiting for the lock"
+  kill $sleep_pid
+  exit 1
+fi
+
+kill $sleep_pid
@@ -82,6 +82,15 @@ fi
 ./compile/14-no-testrepo-changes.sh &&
 ./compile/15-no-new-files.sh &&
 ./compile/16-snapshots-unchanged.sh &&
+./compile/17-json-output.sh &&
+./compile/18-keep-going.sh &&
+./compile/19-touched-sources-not-reparsed.sh &&
+./compile/20-build-graph-cache.sh &&
+./compile/21-artifact-cache.sh &&
+./compile/22-ignored-dirs.sh &&
+./compile/23-source-files.sh &&
+./compile/24-generators.sh &&
+./compile/25-pp-flags.sh &&
 
 # Watch tests
 ./watch/01-watch-recompile.sh &&
@@ -90,9 +99,14 @@ fi
 ./watch/04-watch-config-change.sh &&
 ./watch/05-watch-ignores-non-source.sh &&
 ./watch/06-watch-missing-source-folder.sh &&
+./watch/07-daemon-build.sh &&
+./watch/08-watch-status-stop.sh &&
+./watch/09-watch-rename-file.sh &&
+./watch/10-watch-poll.sh &&
 
 # Lock tests
 ./lock/01-lock-when-watching.sh &&
+./lock/02-wait-for-lock.sh &&
 
 # Suffix tests
 ./suffix/01-custom-suffix.sh &&
@@ -119,4 +133,5 @@ fi
 
 # Compiler-args tests
 ./compiler-args/01-compiler-args-cwd-invariant.sh &&
-./compiler-args/02-warnings-in-parser-and-compiler.sh
+./compiler-args/02-warnings-in-parser-and-compiler.sh &&
+./compiler-args/03-external-includes.sh
@@ -58,7 +58,7 @@ fi
 # Clean up the new file
 rm -f ./src/NewWatchTestFile.res
 
-# Wait for the compiled output to be removed (full rebuild detects removal)
+# Wait for the compiled output to be removed (the watcher removes the outputs of deleted files)
 sleep 5
 rm -f ./src/NewWatchTestFile.mjs
 
@@ -0,0 +1,88 @@
+#!/bin/bash
+cd $(dirname $0)
+source "../utils.sh"
+cd ../../testrepo
+
+bold "Test: build delegates to a running daemon"
+
+if is_windows; then
+  success "Skipped, the daemon socket is Unix only"
+  exit 0
+fi
+
+error_output=$(rewatch clean 2>&1)
+if [ $? -eq 0 ];
+then
+  success "Repo Cleaned"
+else
+  error "Error Cleaning Repo"
+  printf "%s\n" "$error_output" >&2
+  exit 1
+fi
+
+rewatch_bg daemon > rewatch.log 2>&1 &
+success "Daemon Started"
+
+timeout=20
+while [ ! -S lib/rescript.sock ] && [ "$timeout" -gt 0 ]; do
+  sleep 1
+  timeout=$((timeout - 1))
+done
+if [ ! -S lib/rescript.sock ]; then
+  error "Daemon socket not found"
+  tail -n 200 rewatch.log || true
+  exit_watcher
+  exit 1
+fi
+
+echo 'Js.log("added-by-daemon-test")' >> ./packages/main/src/Main.res
+
+# The lock is held by the daemon, so the build is done by it
+build_output=$(rewatch build 2>&1)
+if [ $? -eq 0 ] && node ./packages/main/src/Main.mjs | grep 'added-by-daemon-test' &> /dev/null;
+then
+  success "Build was done by the daemon"
+else
+  error "Build through the daemon failed"
+  printf "%s\n" "$build_output" >&2
+  tail -n 200 rewatch.log || true
+  exit_watcher
+  exit 1
+fi
+
+compiler_args=$(rewatch compiler-args ./packages/main/src/Main.res 2>&1)
+if echo "$compiler_args" | grep '"compiler_args"' &> /dev/null;
+then
+  success "Compiler args were returned by the daemon"
+else
+  error "Compiler args through the daemon failed"
+  printf "%s\n" "$compiler_args" >&2
+  exit_watcher
+  exit 1
+fi
+
+replace '/Js.log("added-by-daemon-test")/d' ./packages/main/src/Main.res;
+
+rewatch build &> /dev/null
+exit_watcher
+
+timeout=10
+while [ -S lib/rescript.sock ] && [ "$timeout" -gt 0 ]; do
+  sleep 1
+  timeout=$((timeout - 1))
+done
+if [ ! -S lib/rescript.sock ];
+then
+  success "Daemon removed its socket"
+else
+  error "Daemon socket was left behind"
+  exit 1
+fi
+
+if git diff --exit-code ./
+then
+  success "Adding and removing changes nothing"
+else
+  error "Adding and removing changes left some artifacts"
+  exit 1
+fi
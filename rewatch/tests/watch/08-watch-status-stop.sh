@@ -0,0 +1,61 @@
+#!/bin/bash
+cd $(dirname $0)
+source "../utils.sh"
+cd ../../testrepo
+
+bold "Test: status and stop of a running watcher"
+
+if is_windows; then
+  success "Skipped, the watcher socket is Unix only"
+  exit 0
+fi
+
+status_output=$(rewatch status 2>&1)
+if [ $? -ne 0 ];
+then
+  success "No watcher is reported before starting one"
+else
+  error "Expected no running watcher"
+  printf "%s\n" "$status_output" >&2
+  exit 1
+fi
+
+rewatch_bg watch > rewatch.log 2>&1 &
+success "Watcher Started"
+
+timeout=20
+until rewatch status 2>/dev/null | grep 'Last build:  succeeded' &> /dev/null || [ "$timeout" -eq 0 ]; do
+  sleep 1
+  timeout=$((timeout - 1))
+done
+
+status_output=$(rewatch status 2>&1)
+if echo "$status_output" | grep "PID:" &> /dev/null && echo "$status_output" | grep 'Last build:  succeeded' &> /dev/null;
+then
+  success "Status of the watcher is reported"
+else
+  error "Status of the watcher is missing"
+  printf "%s\n" "$status_output" >&2
+  tail -n 200 rewatch.log || true
+  exit_watcher
+  exit 1
+fi
+
+stop_output=$(rewatch stop 2>&1)
+if [ $? -eq 0 ] && echo "$stop_output" | grep "Stopped the ReScript watcher" &> /dev/null;
+then
+  success "Watcher stopped"
+else
+  error "Watcher could not be stopped"
+  printf "%s\n" "$stop_output" >&2
+  exit_watcher
+  exit 1
+fi
+
+if [ ! -S lib/rescript.sock ] && ! rewatch status &> /dev/null;
+then
+  success "No watcher is reported after stopping it"
+else
+  error "Watcher is still reported after stopping it"
+  exit 1
+fi
@@ -0,0 +1,97 @@
+#!/bin/bash
+cd $(dirname $0)
+source "../utils.sh"
+cd ../../testrepo
+
+bold "Test: Watcher handles renamed and deleted files without a full build"
+
+error_output=$(rewatch clean 2>&1)
+if [ $? -eq 0 ];
+then
+  success "Repo Cleaned"
+else
+  error "Error Cleaning Repo"
+  printf "%s\n" "$error_output" >&2
+  exit 1
+fi
+
+cleanup() {
+  rm -f ./src/RenameWatchTestFile.res ./src/RenameWatchTestFile.mjs
+  rm -f ./src/RenamedWatchTestFile.res ./src/RenamedWatchTestFile.mjs
+}
+
+# Start watcher
+rewatch_bg watch > rewatch.log 2>&1 &
+success "Watcher Started"
+
+# Wait for initial build to complete
+if ! wait_for_file "./src/Test.mjs" 20; then
+  error "Initial build did not complete"
+  cat rewatch.log
+  exit_watcher
+  exit 1
+fi
+success "Initial build completed"
+
+sleep 1
+
+cat > ./src/RenameWatchTestFile.res << 'EOT'
+let greeting = "hello from renamed file"
+let () = Js.log(greeting)
+EOT
+
+if ! wait_for_file "./src/RenameWatchTestFile.mjs" 20; then
+  error "New file was not compiled by watcher"
+  cat rewatch.log
+  cleanup
+  exit_watcher
+  exit 1
+fi
+success "New file was compiled"
+
+mv ./src/RenameWatchTestFile.res ./src/RenamedWatchTestFile.res
+
+if ! wait_for_file "./src/RenamedWatchTestFile.mjs" 20 || ! wait_for_file_gone "./src/RenameWatchTestFile.mjs" 20; then
+  error "Renamed file was not compiled, or the output of the old name was not removed"
+  cat rewatch.log
+  cleanup
+  exit_watcher
+  exit 1
+fi
+success "Renamed file was compiled and the old output was removed"
+
+rm ./src/RenamedWatchTestFile.res
+
+if ! wait_for_file_gone "./src/RenamedWatchTestFile.mjs" 20; then
+  error "The output of the deleted file was not removed"
+  cat rewatch.log
+  cleanup
+  exit_watcher
+  exit 1
+fi
+success "The output of the deleted file was removed"
+
+# the full build prints "Finished compilation", incremental builds "Finished incremental compilation"
+if grep -q "Finished compilation" rewatch.log; then
+  error "The watcher did a full build"
+  cat rewatch.log
+  cleanup
+  exit_watcher
+  exit 1
+fi
+success "The build state was updated in place"
+
+exit_watcher
+
+sleep 2
+rm -f rewatch.log
+
+if git diff --exit-code . > /dev/null 2>&1 && [ -z "$(git ls-files --others --exclude-standard .)" ];
+then
+  success "No leftover changes"
+else
+  error "Leftover changes detected"
+  git diff .
+  git ls-files --others --exclude-standard .
+  exit 1
+fi
@@ -0,0 +1,73 @@
+#!/bin/bash
+cd $(dirname $0)
+source "../utils.sh"
+cd ../../testrepo
+
+bold "Test: It should watch by polling for changes"
+
+error_output=$(rewatch clean 2>&1)
+if [ $? -eq 0 ];
+then
+  success "Repo Cleaned"
+else
+  error "Error Cleaning Repo"
+  printf "%s\n" "$error_output" >&2
+  exit 1
+fi
+
+# Start watcher and capture logs for debugging
+rewatch_bg watch --poll=200 > rewatch.log 2>&1 &
+success "Watcher Started"
+
+# Wait for initial build to complete
+if ! wait_for_file "./packages/main/src/Main.mjs" 20; then
+  error "Initial build did not complete"
+  cat rewatch.log
+  exit_watcher
+  exit 1
+fi
+success "Initial build completed"
+
+sleep 1
+
+# Trigger a recompilation
+echo 'Js.log("added-by-poll-test")' >> ./packages/main/src/Main.res
+
+# Wait for the change to be polled and compiled
+wait_for_output() {
+  local timeout=20
+  while [ "$timeout" -gt 0 ]; do
+    node ./packages/main/src/Main.mjs 2>/dev/null | grep -q 'added-by-poll-test' && return 0
+    sleep 1
+    timeout=$((timeout - 1))
+  done
+  return 1
+}
+
+if wait_for_output;
+then
+  success "Polled change was compiled"
+else
+  error "Polled change was not compiled"
+  cat rewatch.log
+  replace '/Js.log("added-by-poll-test")/d' ./packages/main/src/Main.res;
+  exit_watcher
+  exit 1
+fi
+
+replace '/Js.log("added-by-poll-test")/d' ./packages/main/src/Main.res;
+
+sleep 5
+
+exit_watcher
+
+sleep 2
+rm -f rewatch.log
+
+if git diff --exit-code ./
+then
+  success "Adding and removing changes nothing"
+else
+  error "Adding and removing changes left some artifacts"
+  exit 1
+fi